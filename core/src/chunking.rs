@@ -0,0 +1,492 @@
+use crate::types::ChunkStrategy;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use text_splitter::{ChunkConfig as SplitterConfig, CodeSplitter, MarkdownSplitter, TextSplitter};
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const DEFAULT_BREAKPOINT_PERCENTILE: f64 = 10.0;
+
+// languages that the code strategy can parse
+pub const CODE_LANGUAGES: [&str; 6] = ["go", "java", "javascript", "python", "rust", "typescript"];
+
+lazy_static! {
+    static ref BPE: CoreBPE = cl100k_base().expect("failed to load cl100k_base");
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChunkConfig {
+    #[serde(default)]
+    pub strategy: ChunkStrategy,
+    // in tokens for the tokens strategy, and in characters otherwise
+    pub max_size: usize,
+    // size of the end of a chunk that is repeated at the start of the next chunk
+    #[serde(default)]
+    pub overlap: usize,
+    // percentile of the similarities of adjacent sentences below which the semantic strategy starts a new chunk
+    #[serde(default = "default_breakpoint_percentile")]
+    pub breakpoint_percentile: f64,
+    // language of the documents, which the code strategy parses
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+fn default_breakpoint_percentile() -> f64 {
+    DEFAULT_BREAKPOINT_PERCENTILE
+}
+
+impl ChunkConfig {
+    pub fn new(strategy: ChunkStrategy, max_size: i32, overlap: i32) -> Result<Self> {
+        if max_size <= 0 {
+            return Err(anyhow!(
+                "chunk size must be greater than 0, got: {max_size}"
+            ));
+        }
+        if overlap < 0 || overlap >= max_size {
+            return Err(anyhow!(
+                "chunk overlap must be at least 0 and less than the chunk size of {max_size}, got: {overlap}"
+            ));
+        }
+        if strategy == ChunkStrategy::semantic && overlap > 0 {
+            return Err(anyhow!(
+                "chunk overlap is not supported by the semantic strategy, got: {overlap}"
+            ));
+        }
+        Ok(ChunkConfig {
+            strategy,
+            max_size: max_size as usize,
+            overlap: overlap as usize,
+            breakpoint_percentile: DEFAULT_BREAKPOINT_PERCENTILE,
+            language: None,
+        })
+    }
+
+    pub fn with_breakpoint_percentile(self, breakpoint_percentile: f64) -> Result<Self> {
+        if !(0.0..=100.0).contains(&breakpoint_percentile) {
+            return Err(anyhow!(
+                "breakpoint percentile must be between 0 and 100, got: {breakpoint_percentile}"
+            ));
+        }
+        Ok(ChunkConfig {
+            breakpoint_percentile,
+            ..self
+        })
+    }
+
+    pub fn with_language(self, language: Option<&str>) -> Result<Self> {
+        match language {
+            Some(language) if self.strategy != ChunkStrategy::code => {
+                return Err(anyhow!(
+                    "a language is only supported by the code strategy, got: {language}"
+                ));
+            }
+            Some(language) => {
+                code_language(language)?;
+            }
+            None if self.strategy == ChunkStrategy::code => {
+                return Err(anyhow!(
+                    "the code strategy requires a language, one of: {}",
+                    CODE_LANGUAGES.join(", ")
+                ));
+            }
+            None => {}
+        }
+        Ok(ChunkConfig {
+            language: language.map(String::from),
+            ..self
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    // character offsets of the chunk in the document, the end is exclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+/// splits a document into chunks of at most the configured size, with surrounding whitespace trimmed
+/// each chunk is split at the largest semantic unit of the strategy that fits, e.g. at paragraphs
+/// before lines, lines before sentences, and sentences before words
+/// markdown is split at the elements of its syntax, and code at the nodes of its syntax tree
+/// the semantic strategy needs embeddings of the sentences, see semantic_chunks, and is split as characters here
+pub fn chunk_document(document: &str, config: &ChunkConfig) -> Result<Vec<Chunk>> {
+    let chunk_config = SplitterConfig::new(config.max_size).with_overlap(config.overlap)?;
+    let chunks: Vec<(usize, &str)> = match config.strategy {
+        ChunkStrategy::characters | ChunkStrategy::semantic => TextSplitter::new(chunk_config)
+            .chunk_indices(document)
+            .collect(),
+        ChunkStrategy::tokens => TextSplitter::new(chunk_config.with_sizer(&*BPE))
+            .chunk_indices(document)
+            .collect(),
+        ChunkStrategy::markdown => MarkdownSplitter::new(chunk_config)
+            .chunk_indices(document)
+            .collect(),
+        ChunkStrategy::code => {
+            let language = config
+                .language
+                .as_deref()
+                .ok_or_else(|| anyhow!("the code strategy requires a language"))?;
+            CodeSplitter::new(code_language(language)?, chunk_config)?
+                .chunk_indices(document)
+                .collect()
+        }
+    };
+
+    // the splitters return byte offsets, which are counted as characters from one chunk to the next
+    let (mut byte_pos, mut char_pos) = (0, 0);
+    let mut result = Vec::with_capacity(chunks.len());
+    for (start, text) in chunks {
+        char_pos += document[byte_pos..start].chars().count();
+        byte_pos = start;
+        result.push(Chunk {
+            text: text.to_string(),
+            start: char_pos,
+            end: char_pos + text.chars().count(),
+        });
+    }
+    Ok(result)
+}
+
+// the tree-sitter grammar of a language of the code strategy
+fn code_language(language: &str) -> Result<tree_sitter_language::LanguageFn> {
+    match language {
+        "go" => Ok(tree_sitter_go::LANGUAGE),
+        "java" => Ok(tree_sitter_java::LANGUAGE),
+        "javascript" => Ok(tree_sitter_javascript::LANGUAGE),
+        "python" => Ok(tree_sitter_python::LANGUAGE),
+        "rust" => Ok(tree_sitter_rust::LANGUAGE),
+        "typescript" => Ok(tree_sitter_typescript::LANGUAGE_TYPESCRIPT),
+        _ => Err(anyhow!(
+            "unsupported language: {language}, expected one of: {}",
+            CODE_LANGUAGES.join(", ")
+        )),
+    }
+}
+
+/// splits a document into its sentences, with surrounding whitespace trimmed
+pub fn sentences(document: &str) -> Vec<Chunk> {
+    let mut sentences = Vec::new();
+    let mut char_pos = 0;
+    for sentence in document.split_sentence_bounds() {
+        let text = sentence.trim();
+        if !text.is_empty() {
+            let start = char_pos + sentence.chars().take_while(|c| c.is_whitespace()).count();
+            sentences.push(Chunk {
+                text: text.to_string(),
+                start,
+                end: start + text.chars().count(),
+            });
+        }
+        char_pos += sentence.chars().count();
+    }
+    sentences
+}
+
+/// groups the consecutive sentences of a document into chunks, given an embedding of each sentence
+/// a chunk ends where the cosine similarity of adjacent sentences is below the breakpoint percentile
+/// of the similarities of all adjacent sentences of the document, or before it grows larger than
+/// max_size characters. sentences that are larger than max_size are split as characters
+pub fn semantic_chunks(
+    document: &str,
+    sentences: &[Chunk],
+    embeddings: &[Vec<f64>],
+    breakpoint_percentile: f64,
+    max_size: Option<usize>,
+) -> Result<Vec<Chunk>> {
+    if sentences.len() != embeddings.len() {
+        return Err(anyhow!(
+            "expected an embedding for each of {} sentences, got: {}",
+            sentences.len(),
+            embeddings.len()
+        ));
+    }
+    let similarities: Vec<f64> = embeddings
+        .windows(2)
+        .map(|pair| cosine_similarity(&pair[0], &pair[1]))
+        .collect();
+    let breakpoint = percentile(&similarities, breakpoint_percentile);
+    let max_size = max_size.unwrap_or(usize::MAX);
+
+    // chunks are slices of the document, so that they keep the whitespace between their sentences
+    let byte_offsets: Vec<usize> = document
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(document.len()))
+        .collect();
+    let slice = |start: usize, end: usize| Chunk {
+        text: document[byte_offsets[start]..byte_offsets[end]].to_string(),
+        start,
+        end,
+    };
+
+    let mut chunks = Vec::new();
+    // character offsets of the sentences of the current chunk
+    let mut current: Option<(usize, usize)> = None;
+    for (i, sentence) in sentences.iter().enumerate() {
+        if let Some((start, end)) = current.take() {
+            if similarities[i - 1] >= breakpoint && sentence.end - start <= max_size {
+                current = Some((start, sentence.end));
+                continue;
+            }
+            chunks.push(slice(start, end));
+        }
+        if sentence.end - sentence.start <= max_size {
+            current = Some((sentence.start, sentence.end));
+            continue;
+        }
+        let config = ChunkConfig {
+            strategy: ChunkStrategy::characters,
+            max_size,
+            overlap: 0,
+            breakpoint_percentile: DEFAULT_BREAKPOINT_PERCENTILE,
+            language: None,
+        };
+        chunks.extend(
+            chunk_document(&sentence.text, &config)?
+                .into_iter()
+                .map(|c| Chunk {
+                    start: sentence.start + c.start,
+                    end: sentence.start + c.end,
+                    ..c
+                }),
+        );
+    }
+    if let Some((start, end)) = current {
+        chunks.push(slice(start, end));
+    }
+    Ok(chunks)
+}
+
+// zero for a zero vector, e.g. the embedding of an empty input
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+// the given percentile of the values, interpolated between the closest ranks
+fn percentile(values: &[f64], percentile: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_texts(
+        document: &str,
+        strategy: ChunkStrategy,
+        max_size: i32,
+        overlap: i32,
+    ) -> Vec<String> {
+        let config = ChunkConfig::new(strategy, max_size, overlap).unwrap();
+        chunk_document(document, &config)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_characters() {
+        let document = "This is a test string that will be chunked into smaller pieces.";
+        assert_eq!(
+            chunk_texts(document, ChunkStrategy::characters, 10, 0),
+            vec![
+                "This is a",
+                "test",
+                "string",
+                "that will",
+                "be chunked",
+                "into",
+                "smaller",
+                "pieces."
+            ]
+        );
+        assert!(chunk_texts("", ChunkStrategy::characters, 10, 0).is_empty());
+        assert!(chunk_texts(" \n\n ", ChunkStrategy::characters, 10, 0).is_empty());
+
+        // paragraphs are kept whole when they fit
+        let document = "The first paragraph.\n\nThe second one.\nIt has two lines.";
+        assert_eq!(
+            chunk_texts(document, ChunkStrategy::characters, 40, 0),
+            vec!["The first paragraph.", "The second one.\nIt has two lines."]
+        );
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let document = "  Ünïcödé text is split.\n\nOffsets count characters, not bytes.";
+        let config = ChunkConfig::new(ChunkStrategy::characters, 40, 0).unwrap();
+        let chunks = chunk_document(document, &config).unwrap();
+        assert_eq!(chunks.len(), 2);
+        for chunk in chunks {
+            let text: String = document
+                .chars()
+                .skip(chunk.start)
+                .take(chunk.end - chunk.start)
+                .collect();
+            assert_eq!(text, chunk.text);
+        }
+    }
+
+    #[test]
+    fn test_chunk_overlap() {
+        assert_eq!(
+            chunk_texts(
+                "one two three four five six",
+                ChunkStrategy::characters,
+                13,
+                5
+            ),
+            vec!["one two three", "three four", "four five six"]
+        );
+        assert!(ChunkConfig::new(ChunkStrategy::characters, 10, 10).is_err());
+        assert!(ChunkConfig::new(ChunkStrategy::characters, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_chunk_tokens() {
+        let document = "hello world ".repeat(20);
+        let chunks = chunk_texts(&document, ChunkStrategy::tokens, 4, 0);
+        assert_eq!(chunks.len(), 10);
+        for chunk in chunks {
+            assert_eq!(chunk, "hello world hello world");
+            assert!(BPE.encode_ordinary(&chunk).len() <= 4);
+        }
+    }
+
+    #[test]
+    fn test_chunk_markdown() {
+        let document = "# A\ntext a\n## B\ntext b\n# C\ntext c\n```\n# not a heading\n```";
+        assert_eq!(
+            chunk_texts(document, ChunkStrategy::markdown, 25, 0),
+            vec![
+                "# A\ntext a\n## B\ntext b",
+                "# C\ntext c",
+                "```\n# not a heading\n```"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sentences() {
+        let document = " First sentence.  Second one?\n\nÜnïcödé third! ";
+        let sentences = sentences(document);
+        let texts: Vec<&str> = sentences.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["First sentence.", "Second one?", "Ünïcödé third!"]
+        );
+        for sentence in sentences {
+            let text: String = document
+                .chars()
+                .skip(sentence.start)
+                .take(sentence.end - sentence.start)
+                .collect();
+            assert_eq!(text, sentence.text);
+        }
+    }
+
+    #[test]
+    fn test_semantic_chunks() {
+        let document = "Cats purr. Cats meow. Rust is fast. Rust is safe. Tea is hot.";
+        let sentences = sentences(document);
+        let embeddings = vec![
+            vec![1.0, 0.1, 0.0],
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.1, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let texts =
+            |chunks: Vec<Chunk>| -> Vec<String> { chunks.into_iter().map(|c| c.text).collect() };
+
+        // the two topic changes are the least similar of the four pairs of adjacent sentences
+        let chunks = semantic_chunks(document, &sentences, &embeddings, 50.0, None).unwrap();
+        assert_eq!(
+            texts(chunks),
+            vec![
+                "Cats purr. Cats meow.",
+                "Rust is fast. Rust is safe.",
+                "Tea is hot."
+            ]
+        );
+        // no similarity is below the lowest one
+        let chunks = semantic_chunks(document, &sentences, &embeddings, 0.0, None).unwrap();
+        assert_eq!(texts(chunks), vec![document]);
+        // chunks also end before they grow too large, and large sentences are split
+        let chunks = semantic_chunks(document, &sentences, &embeddings, 0.0, Some(11)).unwrap();
+        assert_eq!(
+            texts(chunks),
+            vec![
+                "Cats purr.",
+                "Cats meow.",
+                "Rust is",
+                "fast.",
+                "Rust is",
+                "safe.",
+                "Tea is hot."
+            ]
+        );
+        let chunks = semantic_chunks(document, &sentences, &embeddings, 0.0, Some(11)).unwrap();
+        assert_eq!((chunks[2].start, chunks[3].start), (22, 30));
+
+        assert!(semantic_chunks(document, &sentences, &embeddings[1..], 50.0, None).is_err());
+        assert!(ChunkConfig::new(ChunkStrategy::semantic, 100, 10).is_err());
+        let config = ChunkConfig::new(ChunkStrategy::semantic, 100, 0).unwrap();
+        assert!(config.clone().with_breakpoint_percentile(101.0).is_err());
+        assert_eq!(
+            config
+                .with_breakpoint_percentile(25.0)
+                .unwrap()
+                .breakpoint_percentile,
+            25.0
+        );
+    }
+
+    #[test]
+    fn test_chunk_code() {
+        let document = "fn a() {\n    let x = 1;\n\n    x\n}\nfn b() {}\n";
+        let config = ChunkConfig::new(ChunkStrategy::code, 40, 0)
+            .unwrap()
+            .with_language(Some("rust"))
+            .unwrap();
+        let texts: Vec<String> = chunk_document(document, &config)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.text)
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["fn a() {\n    let x = 1;\n\n    x\n}", "fn b() {}"]
+        );
+        // without the syntax tree, the blank line in the function is the largest boundary
+        assert_eq!(
+            chunk_texts(document, ChunkStrategy::characters, 40, 0),
+            vec!["fn a() {\n    let x = 1;", "x\n}\nfn b() {}"]
+        );
+
+        let config = ChunkConfig::new(ChunkStrategy::code, 40, 0).unwrap();
+        assert!(config.clone().with_language(None).is_err());
+        assert!(config.with_language(Some("cobol")).is_err());
+        let config = ChunkConfig::new(ChunkStrategy::markdown, 40, 0).unwrap();
+        assert!(config.with_language(Some("rust")).is_err());
+    }
+}
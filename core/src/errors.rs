@@ -1,3 +1,4 @@
+use crate::transformers::http_handler::{is_retryable_error, is_retryable_status};
 use anyhow::Error as AnyhowError;
 use ollama_rs::error::OllamaError;
 use sqlx::error::Error as DbError;
@@ -27,4 +28,35 @@ pub enum VectorizeError {
     ModelNotFound(String),
     #[error("ollama error: {0}")]
     OllamaError(#[from] OllamaError),
+    #[error(
+        "Failed to call method '{method}', received response with status code:{status} and body: {body}"
+    )]
+    HttpStatus {
+        method: &'static str,
+        status: u16,
+        body: String,
+    },
+}
+
+impl VectorizeError {
+    // status code of a failed request to an embedding provider
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            VectorizeError::HttpStatus { status, .. } => Some(*status),
+            VectorizeError::Reqwest(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
+        }
+    }
+
+    // whether retrying the same request could succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            VectorizeError::HttpStatus { status, .. } => {
+                reqwest::StatusCode::from_u16(*status).is_ok_and(is_retryable_status)
+            }
+            VectorizeError::Reqwest(e) => is_retryable_error(e),
+            VectorizeError::ModelNotFound(_) => false,
+            _ => true,
+        }
+    }
 }
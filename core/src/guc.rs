@@ -16,6 +16,14 @@ pub enum VectorizeGuc {
     PortkeyServiceUrl,
     VoyageApiKey,
     VoyageServiceUrl,
+    AzureOpenAIServiceUrl,
+    AzureOpenAIKey,
+    AzureOpenAIApiVersion,
+    GeminiApiKey,
+    GeminiServiceUrl,
+    TeiServiceUrl,
+    TeiApiKey,
+    LocalModelDir,
     TextIndexType,
 }
 
@@ -24,11 +32,20 @@ pub struct ModelGucConfig {
     pub api_key: Option<String>,
     pub service_url: Option<String>,
     pub virtual_key: Option<String>,
+    // api version of the provider, only used by Azure OpenAI
+    pub api_version: Option<String>,
+    // input truncation and embedding normalization flags, only used by TEI
+    pub truncate: Option<bool>,
+    pub normalize: Option<bool>,
+    // set when the model is served by a provider registered in vectorize.model_providers
+    pub custom_provider: Option<CustomProviderConfig>,
 }
 
 use sqlx::PgPool;
 
-use crate::types::ModelSource;
+use crate::transformers::generic::env_interpolate_string;
+use crate::transformers::providers::custom::CustomProviderConfig;
+use crate::types::{Model, ModelSource};
 pub async fn get_guc(guc: VectorizeGuc, pool: &PgPool) -> Option<String> {
     let guc_name = match guc {
         VectorizeGuc::Host => "host",
@@ -46,6 +63,14 @@ pub async fn get_guc(guc: VectorizeGuc, pool: &PgPool) -> Option<String> {
         VectorizeGuc::PortkeyServiceUrl => "portkey_service_url",
         VectorizeGuc::VoyageApiKey => "voyage_api_key",
         VectorizeGuc::VoyageServiceUrl => "voyage_service_url",
+        VectorizeGuc::AzureOpenAIServiceUrl => "azure_openai_service_url",
+        VectorizeGuc::AzureOpenAIKey => "azure_openai_key",
+        VectorizeGuc::AzureOpenAIApiVersion => "azure_openai_api_version",
+        VectorizeGuc::GeminiApiKey => "gemini_api_key",
+        VectorizeGuc::GeminiServiceUrl => "gemini_service_url",
+        VectorizeGuc::TeiServiceUrl => "tei_service_url",
+        VectorizeGuc::TeiApiKey => "tei_api_key",
+        VectorizeGuc::LocalModelDir => "local_model_dir",
         VectorizeGuc::TextIndexType => "experimental_fts_index_type",
     };
     let query = format!("SHOW vectorize.{}", guc_name);
@@ -57,42 +82,234 @@ pub async fn get_guc(guc: VectorizeGuc, pool: &PgPool) -> Option<String> {
     Some(row.0)
 }
 
-pub async fn get_guc_configs(model_source: &ModelSource, pool: &PgPool) -> ModelGucConfig {
-    match model_source {
+// boolean GUCs are read with current_setting, which is NULL when the GUC is not defined
+async fn get_bool_guc(name: &str, pool: &PgPool) -> Option<bool> {
+    sqlx::query_scalar("SELECT current_setting($1, true)::bool")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .expect("failed to fetch GUC value")
+}
+
+pub async fn get_guc_configs(model: &Model, pool: &PgPool) -> anyhow::Result<ModelGucConfig> {
+    if let Some(guc_configs) = get_custom_provider_configs(model, pool).await? {
+        return Ok(guc_configs);
+    }
+    Ok(match model.source {
         ModelSource::OpenAI => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::OpenAIKey, pool).await,
             service_url: get_guc(VectorizeGuc::OpenAIServiceUrl, pool).await,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Tembo => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::TemboAIKey, pool).await,
             service_url: get_guc(VectorizeGuc::TemboServiceUrl, pool).await,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::SentenceTransformers => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::EmbeddingServiceApiKey, pool).await,
             service_url: get_guc(VectorizeGuc::EmbeddingServiceUrl, pool).await,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Cohere => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::CohereApiKey, pool).await,
             service_url: None,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Ollama => ModelGucConfig {
             api_key: None,
             service_url: get_guc(VectorizeGuc::OllamaServiceUrl, pool).await,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Portkey => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::PortkeyApiKey, pool).await,
             service_url: get_guc(VectorizeGuc::PortkeyServiceUrl, pool).await,
             virtual_key: get_guc(VectorizeGuc::PortkeyVirtualKey, pool).await,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Voyage => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::VoyageApiKey, pool).await,
             service_url: get_guc(VectorizeGuc::VoyageServiceUrl, pool).await,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Azure => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::AzureOpenAIKey, pool).await,
+            service_url: get_guc(VectorizeGuc::AzureOpenAIServiceUrl, pool).await,
+            virtual_key: None,
+            api_version: get_guc(VectorizeGuc::AzureOpenAIApiVersion, pool).await,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Gemini => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::GeminiApiKey, pool).await,
+            service_url: get_guc(VectorizeGuc::GeminiServiceUrl, pool).await,
+            virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Tei => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::TeiApiKey, pool).await,
+            service_url: get_guc(VectorizeGuc::TeiServiceUrl, pool).await,
+            virtual_key: None,
+            api_version: None,
+            truncate: get_bool_guc("vectorize.tei_truncate", pool).await,
+            normalize: get_bool_guc("vectorize.tei_normalize", pool).await,
+            custom_provider: None,
+        },
+        ModelSource::Local => ModelGucConfig {
+            api_key: None,
+            service_url: get_guc(VectorizeGuc::LocalModelDir, pool).await,
+            virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
+    })
+}
+
+// the configuration of the provider registered in vectorize.model_providers for the model, if any
+// the api key is read from the GUC that the provider references
+async fn get_custom_provider_configs(
+    model: &Model,
+    pool: &PgPool,
+) -> anyhow::Result<Option<ModelGucConfig>> {
+    let Some(name) = model.custom_provider_name() else {
+        return Ok(None);
+    };
+    #[allow(clippy::type_complexity)]
+    let row: Option<(
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    )> = sqlx::query_as(
+        "SELECT base_url, api_format, auth_header, auth_scheme, current_setting(api_key_guc, true)
+            FROM vectorize.model_providers
+            WHERE name = $1",
+    )
+    .bind(&name)
+    .fetch_optional(pool)
+    .await?;
+    let Some((base_url, api_format, auth_header, auth_scheme, api_key)) = row else {
+        return Ok(None);
+    };
+    custom_provider_configs(
+        name,
+        base_url,
+        &api_format,
+        auth_header,
+        auth_scheme,
+        api_key,
+    )
+    .map(Some)
+}
+
+// builds the configuration of a registered provider from its vectorize.model_providers entry
+// and the value of its api key GUC, the same way for the extension and the worker
+// environment variables in the api key, e.g. `${MY_API_KEY}`, are interpolated as they are in GUCs
+pub fn custom_provider_configs(
+    name: String,
+    base_url: String,
+    api_format: &str,
+    auth_header: Option<String>,
+    auth_scheme: Option<String>,
+    api_key: Option<String>,
+) -> anyhow::Result<ModelGucConfig> {
+    let api_format = api_format
+        .parse()
+        .map_err(|e| anyhow::anyhow!("invalid model provider {name}: {e}"))?;
+    let api_key = api_key
+        .filter(|k| !k.is_empty())
+        .map(|k| env_interpolate_string(&k))
+        .transpose()?;
+    Ok(ModelGucConfig {
+        api_key,
+        service_url: Some(base_url.clone()),
+        virtual_key: None,
+        api_version: None,
+        truncate: None,
+        normalize: None,
+        custom_provider: Some(CustomProviderConfig {
+            name,
+            base_url,
+            api_format,
+            auth_header,
+            auth_scheme,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_provider_configs() {
+        std::env::set_var("VECTORIZE_TEST_PROVIDER_KEY", "secret");
+        let configs = custom_provider_configs(
+            "myprovider".to_string(),
+            "http://localhost:8000".to_string(),
+            "openai",
+            None,
+            None,
+            Some("${VECTORIZE_TEST_PROVIDER_KEY}".to_string()),
+        )
+        .unwrap();
+        assert_eq!(configs.api_key, Some("secret".to_string()));
+        assert_eq!(
+            configs.service_url,
+            Some("http://localhost:8000".to_string())
+        );
+
+        let missing_env = custom_provider_configs(
+            "myprovider".to_string(),
+            "http://localhost:8000".to_string(),
+            "openai",
+            None,
+            None,
+            Some("${VECTORIZE_TEST_PROVIDER_MISSING}".to_string()),
+        );
+        assert!(missing_env.is_err());
+        let invalid_format = custom_provider_configs(
+            "myprovider".to_string(),
+            "http://localhost:8000".to_string(),
+            "soap",
+            None,
+            None,
+            None,
+        );
+        assert!(invalid_format.is_err());
     }
 }
@@ -1,3 +1,4 @@
+pub mod chunking;
 pub mod errors;
 pub mod guc;
 pub mod transformers;
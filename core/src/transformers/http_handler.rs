@@ -1,18 +1,23 @@
 use crate::errors::VectorizeError;
 use crate::transformers::types::{Inputs, PairedEmbeddings};
+use crate::worker::base::from_env_default;
+
+use log::warn;
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 pub async fn handle_response<T: for<'de> serde::Deserialize<'de>>(
     resp: reqwest::Response,
     method: &'static str,
 ) -> Result<T, VectorizeError> {
     if !resp.status().is_success() {
-        let errmsg = format!(
-            "Failed to call method '{}', received response with status code:{} and body: {}",
+        return Err(VectorizeError::HttpStatus {
             method,
-            resp.status(),
-            resp.text().await?
-        );
-        return Err(anyhow::anyhow!(errmsg)).map_err(VectorizeError::from);
+            status: resp.status().as_u16(),
+            body: resp.text().await?,
+        });
     }
     let value = resp.json::<T>().await?;
     Ok(value)
@@ -29,3 +34,278 @@ pub fn merge_input_output(inputs: Vec<Inputs>, values: Vec<Vec<f64>>) -> Vec<Pai
         })
         .collect()
 }
+
+// limits for retrying failed requests to embedding providers
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    // retries after the first attempt
+    pub max_retries: u32,
+    // delay before the first retry, doubled on every following retry
+    pub base_delay: Duration,
+    // upper bound for any single delay, including delays requested by Retry-After
+    pub max_delay: Duration,
+    // upper bound for all attempts and delays together, including the time of each request
+    // kept below the 180s visibility timeout of the queue, so that a message is not
+    // redelivered to another worker while its requests are still being retried
+    pub max_elapsed: Duration,
+}
+
+impl RetryConfig {
+    pub fn from_env() -> RetryConfig {
+        RetryConfig {
+            max_retries: parse_env("VECTORIZE_HTTP_MAX_RETRIES", 3),
+            base_delay: Duration::from_millis(parse_env("VECTORIZE_HTTP_RETRY_BASE_MS", 500)),
+            max_delay: Duration::from_millis(parse_env("VECTORIZE_HTTP_RETRY_MAX_MS", 30000)),
+            max_elapsed: Duration::from_millis(parse_env("VECTORIZE_HTTP_RETRY_TOTAL_MS", 150000)),
+        }
+    }
+
+    // for requests made while a query waits, e.g. embedding a search query
+    // a single short retry, since the delay can not be cancelled and is not bounded by statement_timeout
+    // the total is left at the request timeout, so that a slow first request, e.g. while
+    // vector-serve downloads a model, is not cut short
+    pub fn interactive() -> RetryConfig {
+        RetryConfig {
+            max_retries: 1,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(2),
+            max_elapsed: Duration::from_secs(120),
+        }
+    }
+
+    // jittered exponential backoff, a random delay up to base_delay * 2^attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        ceiling.mul_f64(jitter)
+    }
+}
+
+// malformed values fall back to the default, rather than failing every request
+fn parse_env<T: FromStr + ToString>(key: &str, default: T) -> T {
+    let value = from_env_default(key, &default.to_string());
+    value.parse().unwrap_or_else(|_| {
+        warn!(
+            "invalid value for {key}: {value:?}, using default: {}",
+            default.to_string()
+        );
+        default
+    })
+}
+
+// rate limits, server errors and timeouts are worth retrying
+// client errors such as 400 and 401 will fail the same way again
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect()
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    // a date in the past means the request can be retried right away
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()
+        .and_then(parse_retry_after)
+}
+
+/// sends a request, retrying rate limited, failed and timed out requests with jittered exponential backoff
+/// the final response is returned as-is, so non-retryable statuses are surfaced by handle_response
+/// every attempt is cut short at max_elapsed since the first one, and a retry is only made when its delay ends before then
+pub async fn send_with_retry(
+    request: RequestBuilder,
+    retry: &RetryConfig,
+) -> Result<Response, VectorizeError> {
+    let deadline = Instant::now() + retry.max_elapsed;
+    let mut attempt: u32 = 0;
+    loop {
+        let (client, req) = request
+            .try_clone()
+            .ok_or_else(|| anyhow::anyhow!("request body does not support retries"))?
+            .build_split();
+        let mut req = req?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let timeout = req.timeout().map_or(remaining, |t| (*t).min(remaining));
+        *req.timeout_mut() = Some(timeout);
+        let result = client.execute(req).await;
+        let delay = match &result {
+            Ok(resp) if is_retryable_status(resp.status()) => {
+                warn!(
+                    "request to {} failed with status {}",
+                    resp.url(),
+                    resp.status()
+                );
+                retry_after(resp)
+                    .map(|d| d.min(retry.max_delay))
+                    .unwrap_or_else(|| retry.backoff(attempt))
+            }
+            Err(e) if is_retryable_error(e) => {
+                warn!("request failed: {}", e);
+                retry.backoff(attempt)
+            }
+            _ => return Ok(result?),
+        };
+        if attempt >= retry.max_retries {
+            return Ok(result?);
+        }
+        if Instant::now() + delay >= deadline {
+            warn!(
+                "not retrying, retries would exceed the total retry time of {:?}",
+                retry.max_elapsed
+            );
+            return Ok(result?);
+        }
+        warn!("retry {} of {}", attempt + 1, retry.max_retries);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+// serves the given raw responses, one per connection
+// the raw requests are sent to the returned receiver, for assertions on the url, headers and body
+#[cfg(test)]
+pub(crate) async fn mock_server(
+    responses: Vec<String>,
+) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 16384];
+            let n = socket.read(&mut buf).await.unwrap();
+            let _ = tx.send(String::from_utf8_lossy(&buf[..n]).to_string());
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+        }
+    });
+    (format!("http://{addr}"), rx)
+}
+
+// a 200 response with a JSON body, for mock_server
+#[cfg(test)]
+pub(crate) fn ok_response(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> RetryConfig {
+        RetryConfig {
+            max_retries: 2,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            max_elapsed: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let config = test_config();
+        for attempt in 0..10 {
+            let ceiling = (config.base_delay * 2_u32.pow(attempt)).min(config.max_delay);
+            let delay = config.backoff(attempt);
+            assert!(delay <= ceiling);
+            assert!(delay >= ceiling / 2);
+        }
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    async fn serve(responses: Vec<&'static str>) -> String {
+        let (url, _requests) = mock_server(responses.into_iter().map(String::from).collect()).await;
+        url
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_retries_rate_limits() {
+        let url = serve(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ])
+        .await;
+        let request = reqwest::Client::new().post(&url).body("{}");
+        let response = send_with_retry(request, &test_config()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_stops_at_max_elapsed() {
+        let url = serve(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ])
+        .await;
+        let config = RetryConfig {
+            max_delay: Duration::from_secs(1),
+            max_elapsed: Duration::from_millis(500),
+            ..test_config()
+        };
+        let request = reqwest::Client::new().post(&url).body("{}");
+        let response = send_with_retry(request, &config).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_parse_env_falls_back_to_default() {
+        std::env::set_var("VECTORIZE_TEST_PARSE_ENV", "three");
+        assert_eq!(parse_env("VECTORIZE_TEST_PARSE_ENV", 3_u32), 3);
+        std::env::set_var("VECTORIZE_TEST_PARSE_ENV", "5");
+        assert_eq!(parse_env("VECTORIZE_TEST_PARSE_ENV", 3_u32), 5);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_does_not_retry_fatal_errors() {
+        let url = serve(vec![
+            "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+        let request = reqwest::Client::new().post(&url).body("{}");
+        let response = send_with_retry(request, &test_config()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,193 @@
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::openai::OpenAIEmbeddingResponse;
+use super::{
+    ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::transformers::providers;
+use crate::types::InputType;
+use async_trait::async_trait;
+use std::env;
+
+pub const AZURE_API_VERSION: &str = "2024-02-01";
+pub const MAX_BATCH_SIZE: usize = 2048;
+
+// Azure OpenAI serves models from deployments, so the model name is the name of the deployment
+pub struct AzureOpenAIProvider {
+    // resource endpoint, e.g. https://my-resource.openai.azure.com
+    pub url: String,
+    pub api_key: String,
+    pub api_version: String,
+    pub retry: RetryConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AzureEmbeddingBody {
+    pub input: Vec<String>,
+    // deployment names do not identify the model, so this is sent whenever it is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+}
+
+impl AzureOpenAIProvider {
+    pub fn new(
+        url: Option<String>,
+        api_key: Option<String>,
+        api_version: Option<String>,
+        retry: RetryConfig,
+    ) -> Self {
+        let final_url = match url {
+            Some(url) => url,
+            None => env::var("AZURE_OPENAI_ENDPOINT").expect("AZURE_OPENAI_ENDPOINT not set"),
+        };
+        let final_api_key = match api_key {
+            Some(api_key) => api_key,
+            None => env::var("AZURE_OPENAI_API_KEY").expect("AZURE_OPENAI_API_KEY not set"),
+        };
+        AzureOpenAIProvider {
+            url: final_url.trim_end_matches('/').to_string(),
+            api_key: final_api_key,
+            api_version: api_version.unwrap_or_else(|| AZURE_API_VERSION.to_string()),
+            retry,
+        }
+    }
+
+    fn deployment_request(&self, client: &Client, deployment: &str, path: &str) -> RequestBuilder {
+        let url = format!(
+            "{}/openai/deployments/{deployment}/{path}?api-version={}",
+            self.url, self.api_version
+        );
+        client
+            .post(url)
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("api-key", &self.api_key)
+    }
+
+    pub async fn generate_response(
+        &self,
+        deployment: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        let client = Client::new();
+        let message = serde_json::json!({
+            "messages": messages,
+        });
+        let req = self
+            .deployment_request(&client, &deployment, "chat/completions")
+            .json(&message);
+        let response = send_with_retry(req, &self.retry).await?;
+        let chat_response = handle_response::<ChatResponse>(response, "chat").await?;
+        Ok(chat_response.choices[0].message.content.clone())
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for AzureOpenAIProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = Client::new();
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for chunk in providers::split_vector(request.input.clone(), MAX_BATCH_SIZE) {
+            let req = self
+                .deployment_request(&client, &request.model, "embeddings")
+                .json(&AzureEmbeddingBody {
+                    input: chunk,
+                    dimensions: request.dimensions,
+                });
+            let response = send_with_retry(req, &self.retry).await?;
+            let embeddings =
+                handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.data.into_iter().map(|x| x.embedding));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        // deployments can be named anything, so determine the dim from an embedding
+        let req = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+        let embedding = self.generate_embedding(&req).await?;
+        let dim = embedding.embeddings[0].len();
+        Ok(dim as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::http_handler::{mock_server, ok_response};
+
+    #[tokio::test]
+    async fn test_azure_embeddings() {
+        let (url, mut requests) = mock_server(vec![ok_response(
+            r#"{"model": "text-embedding-3-small", "data": [{"index": 0, "embedding": [0.1, 0.2, 0.3]}]}"#,
+        )])
+        .await;
+        let provider = AzureOpenAIProvider::new(
+            Some(url),
+            Some("secret".to_string()),
+            None,
+            RetryConfig::from_env(),
+        );
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: "my-embeddings".to_string(),
+            input_type: InputType::Document,
+            dimensions: Some(256),
+        };
+
+        let embeddings = provider.generate_embedding(&request).await.unwrap();
+        assert_eq!(embeddings.embeddings, vec![vec![0.1, 0.2, 0.3]]);
+
+        let raw_request = requests.recv().await.unwrap();
+        assert!(raw_request.starts_with(&format!(
+            "POST /openai/deployments/my-embeddings/embeddings?api-version={AZURE_API_VERSION} "
+        )));
+        assert!(raw_request.contains("api-key: secret"));
+        assert!(raw_request.contains(r#"{"input":["hello world"],"dimensions":256}"#));
+    }
+
+    #[tokio::test]
+    async fn test_azure_chat() {
+        let (url, mut requests) = mock_server(vec![ok_response(
+            r#"{"choices": [{"message": {"role": "assistant", "content": "hi there"}}]}"#,
+        )])
+        .await;
+        let provider = AzureOpenAIProvider::new(
+            Some(format!("{url}/")),
+            Some("secret".to_string()),
+            Some("2024-06-01".to_string()),
+            RetryConfig::from_env(),
+        );
+        let messages = vec![ChatMessageRequest {
+            role: "user".to_string(),
+            content: "hello".to_string(),
+        }];
+
+        let response = provider
+            .generate_response("my-chat".to_string(), &messages)
+            .await
+            .unwrap();
+        assert_eq!(response, "hi there");
+
+        let raw_request = requests.recv().await.unwrap();
+        assert!(raw_request.starts_with(
+            "POST /openai/deployments/my-chat/chat/completions?api-version=2024-06-01 "
+        ));
+        assert!(raw_request.contains("api-key: secret"));
+    }
+}
@@ -1,9 +1,13 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use super::{
+    EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse, GenericRerankRequest,
+    GenericRerankResponse, RerankProvider,
+};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::types::InputType;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -28,10 +32,11 @@ lazy_static! {
 pub struct CohereProvider {
     pub url: String,
     pub api_key: String,
+    pub retry: RetryConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct CohereEmbeddingBody {
+pub(crate) struct CohereEmbeddingBody {
     model: String,
     texts: Vec<String>,
     input_type: String,
@@ -43,22 +48,36 @@ impl From<GenericEmbeddingRequest> for CohereEmbeddingBody {
         CohereEmbeddingBody {
             model: request.model,
             texts: request.input,
-            input_type: "search_document".to_string(),
+            input_type: match request.input_type {
+                InputType::Document => "search_document".to_string(),
+                InputType::Query => "search_query".to_string(),
+            },
             truncate: "END".to_string(),
         }
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct CohereEmbeddingResponse {
+pub(crate) struct CohereRerankBody {
     model: String,
-    texts: Vec<String>,
-    input_type: String,
-    truncate: String,
+    query: String,
+    documents: Vec<String>,
+    return_documents: bool,
+}
+
+impl From<GenericRerankRequest> for CohereRerankBody {
+    fn from(request: GenericRerankRequest) -> Self {
+        CohereRerankBody {
+            model: request.model,
+            query: request.query,
+            documents: request.documents,
+            return_documents: false,
+        }
+    }
 }
 
 impl CohereProvider {
-    pub fn new(url: Option<String>, api_key: Option<String>) -> Self {
+    pub fn new(url: Option<String>, api_key: Option<String>, retry: RetryConfig) -> Self {
         let final_url = match url {
             Some(url) => url,
             None => COHERE_BASE_URL.to_string(),
@@ -70,6 +89,7 @@ impl CohereProvider {
         CohereProvider {
             url: final_url,
             api_key: final_api_key,
+            retry,
         }
     }
 }
@@ -85,15 +105,14 @@ impl EmbeddingProvider for CohereProvider {
         let payload = CohereEmbeddingBody::from(request.clone());
         let payload_val = serde_json::to_value(payload)?;
         let embeddings_url = format!("{}/embed", self.url);
-        let response = client
+        let req = client
             .post(&embeddings_url)
             .timeout(std::time::Duration::from_secs(120_u64))
             .header("Accept", "application/json")
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload_val)
-            .send()
-            .await?;
+            .json(&payload_val);
+        let response = send_with_retry(req, &self.retry).await?;
 
         let embeddings =
             handle_response::<GenericEmbeddingResponse>(response, "embeddings").await?;
@@ -108,6 +127,30 @@ impl EmbeddingProvider for CohereProvider {
     }
 }
 
+#[async_trait]
+impl RerankProvider for CohereProvider {
+    async fn rerank<'a>(
+        &self,
+        request: &'a GenericRerankRequest,
+    ) -> Result<GenericRerankResponse, VectorizeError> {
+        let client = Client::new();
+
+        let payload = CohereRerankBody::from(request.clone());
+        let rerank_url = format!("{}/rerank", self.url);
+        let req = client
+            .post(&rerank_url)
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&payload);
+        let response = send_with_retry(req, &self.retry).await?;
+
+        let results = handle_response::<GenericRerankResponse>(response, "rerank").await?;
+        Ok(results)
+    }
+}
+
 #[cfg(test)]
 mod integration_tests {
     use super::*;
@@ -116,10 +159,16 @@ mod integration_tests {
     #[ignore]
     #[async_test]
     async fn test_generate_embedding() {
-        let provider = CohereProvider::new(Some(COHERE_BASE_URL.to_string()), None);
+        let provider = CohereProvider::new(
+            Some(COHERE_BASE_URL.to_string()),
+            None,
+            RetryConfig::from_env(),
+        );
         let request = GenericEmbeddingRequest {
             model: "embed-english-light-v3.0".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -136,4 +185,26 @@ mod integration_tests {
             "Embeddings should have dimension 384"
         );
     }
+
+    #[ignore]
+    #[async_test]
+    async fn test_rerank() {
+        let provider = CohereProvider::new(
+            Some(COHERE_BASE_URL.to_string()),
+            None,
+            RetryConfig::from_env(),
+        );
+        let request = GenericRerankRequest {
+            model: "rerank-english-v3.0".to_string(),
+            query: "what is the capital of france?".to_string(),
+            documents: vec![
+                "Berlin is the capital of Germany.".to_string(),
+                "Paris is the capital of France.".to_string(),
+            ],
+        };
+
+        let response = provider.rerank(&request).await.unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].index, 1);
+    }
 }
@@ -0,0 +1,255 @@
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::cohere::CohereEmbeddingBody;
+use super::openai::{OpenAIEmbeddingBody, OpenAIEmbeddingResponse};
+use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::transformers::providers;
+use crate::types::InputType;
+use async_trait::async_trait;
+
+pub const MAX_BATCH_SIZE: usize = 2048;
+
+// request and response shape of a registered provider's embeddings API
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiFormat {
+    OpenAI,
+    Cohere,
+    Ollama,
+}
+
+impl FromStr for ApiFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(ApiFormat::OpenAI),
+            "cohere" => Ok(ApiFormat::Cohere),
+            "ollama" => Ok(ApiFormat::Ollama),
+            _ => Err(format!("Invalid api format: {}", s)),
+        }
+    }
+}
+
+// an entry of vectorize.model_providers
+#[derive(Clone, Debug)]
+pub struct CustomProviderConfig {
+    pub name: String,
+    pub base_url: String,
+    pub api_format: ApiFormat,
+    // header that carries the api key, e.g. `Authorization` or `api-key`
+    pub auth_header: Option<String>,
+    // prefix of the api key in the auth header, e.g. `Bearer`
+    pub auth_scheme: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct OllamaEmbeddingBody {
+    model: String,
+    input: Vec<String>,
+}
+
+pub struct CustomProvider {
+    pub config: CustomProviderConfig,
+    pub api_key: Option<String>,
+    pub retry: RetryConfig,
+}
+
+impl CustomProvider {
+    pub fn new(config: CustomProviderConfig, api_key: Option<String>, retry: RetryConfig) -> Self {
+        CustomProvider {
+            config,
+            api_key,
+            retry,
+        }
+    }
+
+    // the name of the model in the provider's API, without the provider's name
+    fn model_name<'a>(&self, model: &'a str) -> &'a str {
+        model
+            .strip_prefix(&format!("{}/", self.config.name))
+            .unwrap_or(model)
+    }
+
+    fn build_request(
+        &self,
+        client: &Client,
+        model: &str,
+        input: Vec<String>,
+        input_type: InputType,
+    ) -> Result<RequestBuilder, VectorizeError> {
+        let base_url = self.config.base_url.trim_end_matches('/');
+        let model = self.model_name(model).to_string();
+        let (url, payload) = match self.config.api_format {
+            ApiFormat::OpenAI => (
+                format!("{base_url}/embeddings"),
+                // registered APIs may not support dimensions, which are applied by reduce_dimensions
+                serde_json::to_value(OpenAIEmbeddingBody {
+                    model,
+                    input,
+                    dimensions: None,
+                })?,
+            ),
+            ApiFormat::Cohere => (
+                format!("{base_url}/embed"),
+                serde_json::to_value(CohereEmbeddingBody::from(GenericEmbeddingRequest {
+                    model,
+                    input,
+                    input_type,
+                    dimensions: None,
+                }))?,
+            ),
+            ApiFormat::Ollama => (
+                format!("{base_url}/api/embed"),
+                serde_json::to_value(OllamaEmbeddingBody { model, input })?,
+            ),
+        };
+        let mut req = client
+            .post(url)
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(&payload);
+        if let (Some(header), Some(api_key)) = (&self.config.auth_header, &self.api_key) {
+            let value = match &self.config.auth_scheme {
+                Some(scheme) => format!("{scheme} {api_key}"),
+                None => api_key.clone(),
+            };
+            req = req.header(header.as_str(), value);
+        }
+        Ok(req)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for CustomProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = Client::new();
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for chunk in providers::split_vector(request.input.clone(), MAX_BATCH_SIZE) {
+            let req = self.build_request(&client, &request.model, chunk, request.input_type)?;
+            let response = send_with_retry(req, &self.retry).await?;
+            let embeddings = match self.config.api_format {
+                ApiFormat::OpenAI => {
+                    handle_response::<OpenAIEmbeddingResponse>(response, "embeddings")
+                        .await?
+                        .into()
+                }
+                ApiFormat::Cohere | ApiFormat::Ollama => {
+                    handle_response::<GenericEmbeddingResponse>(response, "embeddings").await?
+                }
+            };
+            all_embeddings.extend(embeddings.embeddings);
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        // registered providers have no model metadata, so embed a sample input
+        let req = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+        let embedding = self.generate_embedding(&req).await?;
+        let dim = embedding.embeddings[0].len();
+        Ok(dim as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(api_format: ApiFormat, auth_scheme: Option<&str>) -> CustomProvider {
+        CustomProvider::new(
+            CustomProviderConfig {
+                name: "myprovider".to_string(),
+                base_url: "http://localhost:8000/v1/".to_string(),
+                api_format,
+                auth_header: Some("api-key".to_string()),
+                auth_scheme: auth_scheme.map(|s| s.to_string()),
+            },
+            Some("secret".to_string()),
+            RetryConfig::from_env(),
+        )
+    }
+
+    fn body(req: &reqwest::Request) -> serde_json::Value {
+        serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_api_format_parsing() {
+        assert_eq!("OpenAI".parse::<ApiFormat>(), Ok(ApiFormat::OpenAI));
+        assert_eq!("ollama".parse::<ApiFormat>(), Ok(ApiFormat::Ollama));
+        assert!("grpc".parse::<ApiFormat>().is_err());
+    }
+
+    #[test]
+    fn test_openai_format_request() {
+        let req = provider(ApiFormat::OpenAI, None)
+            .build_request(
+                &Client::new(),
+                "myprovider/some-model",
+                vec!["hello".to_string()],
+                InputType::Document,
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.url().as_str(), "http://localhost:8000/v1/embeddings");
+        assert_eq!(req.headers()["api-key"], "secret");
+        assert_eq!(
+            body(&req),
+            serde_json::json!({"model": "some-model", "input": ["hello"]})
+        );
+    }
+
+    #[test]
+    fn test_cohere_format_request() {
+        let req = provider(ApiFormat::Cohere, Some("Bearer"))
+            .build_request(
+                &Client::new(),
+                "myprovider/some-model",
+                vec!["hello".to_string()],
+                InputType::Query,
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.url().as_str(), "http://localhost:8000/v1/embed");
+        assert_eq!(req.headers()["api-key"], "Bearer secret");
+        assert_eq!(body(&req)["texts"], serde_json::json!(["hello"]));
+        assert_eq!(body(&req)["model"], "some-model");
+        assert_eq!(body(&req)["input_type"], "search_query");
+    }
+
+    #[test]
+    fn test_ollama_format_request() {
+        let req = provider(ApiFormat::Ollama, None)
+            .build_request(
+                &Client::new(),
+                "myprovider/org/some-model",
+                vec!["hello".to_string()],
+                InputType::Document,
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.url().as_str(), "http://localhost:8000/v1/api/embed");
+        assert_eq!(
+            body(&req),
+            serde_json::json!({"model": "org/some-model", "input": ["hello"]})
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::transformers::providers;
+use crate::types::InputType;
+use async_trait::async_trait;
+use std::env;
+
+pub const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+// batchEmbedContents accepts at most 100 requests
+pub const MAX_BATCH_SIZE: usize = 100;
+
+// what the embeddings are optimized for
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GeminiTaskType {
+    RetrievalDocument,
+    RetrievalQuery,
+}
+
+impl From<InputType> for GeminiTaskType {
+    fn from(input_type: InputType) -> Self {
+        match input_type {
+            InputType::Document => GeminiTaskType::RetrievalDocument,
+            InputType::Query => GeminiTaskType::RetrievalQuery,
+        }
+    }
+}
+
+pub struct GeminiProvider {
+    pub url: String,
+    pub api_key: String,
+    pub retry: RetryConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeminiEmbeddingBody {
+    pub requests: Vec<GeminiEmbedContentRequest>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiEmbedContentRequest {
+    pub model: String,
+    pub content: GeminiContent,
+    pub task_type: GeminiTaskType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dimensionality: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeminiContent {
+    pub parts: Vec<GeminiPart>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeminiPart {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeminiEmbeddingResponse {
+    pub embeddings: Vec<GeminiEmbedding>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeminiEmbedding {
+    pub values: Vec<f64>,
+}
+
+impl GeminiProvider {
+    pub fn new(url: Option<String>, api_key: Option<String>, retry: RetryConfig) -> Self {
+        let final_url = match url {
+            Some(url) => url,
+            None => GEMINI_BASE_URL.to_string(),
+        };
+        let final_api_key = match api_key {
+            Some(api_key) => api_key,
+            None => env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY not set"),
+        };
+        GeminiProvider {
+            url: final_url.trim_end_matches('/').to_string(),
+            api_key: final_api_key,
+            retry,
+        }
+    }
+
+    fn embedding_body(
+        model: &str,
+        input: Vec<String>,
+        task_type: GeminiTaskType,
+        output_dimensionality: Option<u32>,
+    ) -> GeminiEmbeddingBody {
+        // older models reject outputDimensionality, their embeddings are truncated by reduce_dimensions instead
+        let output_dimensionality =
+            output_dimensionality.filter(|_| gemini_supports_output_dimensionality(model));
+        GeminiEmbeddingBody {
+            requests: input
+                .into_iter()
+                .map(|text| GeminiEmbedContentRequest {
+                    model: format!("models/{model}"),
+                    content: GeminiContent {
+                        parts: vec![GeminiPart { text }],
+                    },
+                    task_type: task_type.clone(),
+                    output_dimensionality,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for GeminiProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = Client::new();
+        let embedding_url = format!("{}/models/{}:batchEmbedContents", self.url, request.model);
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for chunk in providers::split_vector(request.input.clone(), MAX_BATCH_SIZE) {
+            let req = client
+                .post(&embedding_url)
+                .timeout(std::time::Duration::from_secs(120_u64))
+                .header("Content-Type", "application/json")
+                .header("x-goog-api-key", &self.api_key)
+                .json(&Self::embedding_body(
+                    &request.model,
+                    chunk,
+                    request.input_type.into(),
+                    request.dimensions,
+                ));
+            let response = send_with_retry(req, &self.retry).await?;
+            let embeddings =
+                handle_response::<GeminiEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.embeddings.into_iter().map(|x| x.values));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        match gemini_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => {
+                // determine embedding dim by generating an embedding and getting length of array
+                let req = GenericEmbeddingRequest {
+                    input: vec!["hello world".to_string()],
+                    model: model_name.to_string(),
+                    input_type: InputType::Document,
+                    dimensions: None,
+                };
+                let embedding = self.generate_embedding(&req).await?;
+                Ok(embedding.embeddings[0].len() as u32)
+            }
+        }
+    }
+}
+
+pub fn gemini_embedding_dim(model_name: &str) -> Option<u32> {
+    match model_name {
+        "text-embedding-004" => Some(768),
+        "embedding-001" => Some(768),
+        "gemini-embedding-001" => Some(3072),
+        _ => None,
+    }
+}
+
+// models that can shorten their embeddings to any size
+pub fn gemini_supports_output_dimensionality(model_name: &str) -> bool {
+    matches!(model_name, "text-embedding-004" | "gemini-embedding-001")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::http_handler::{mock_server, ok_response};
+
+    #[test]
+    fn test_embedding_body() {
+        let body = GeminiProvider::embedding_body(
+            "text-embedding-004",
+            vec!["hello".to_string()],
+            InputType::Query.into(),
+            Some(256),
+        );
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            serde_json::json!({
+                "requests": [{
+                    "model": "models/text-embedding-004",
+                    "content": {"parts": [{"text": "hello"}]},
+                    "taskType": "RETRIEVAL_QUERY",
+                    "outputDimensionality": 256
+                }]
+            })
+        );
+
+        // embedding-001 rejects outputDimensionality
+        let body = GeminiProvider::embedding_body(
+            "embedding-001",
+            vec!["hello".to_string()],
+            InputType::Query.into(),
+            Some(256),
+        );
+        assert!(serde_json::to_value(body).unwrap()["requests"][0]
+            .get("outputDimensionality")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gemini_embeddings() {
+        let (url, mut requests) = mock_server(vec![ok_response(
+            r#"{"embeddings": [{"values": [0.1, 0.2]}, {"values": [0.3, 0.4]}]}"#,
+        )])
+        .await;
+        let provider = GeminiProvider::new(
+            Some(url),
+            Some("secret".to_string()),
+            RetryConfig::from_env(),
+        );
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello".to_string(), "world".to_string()],
+            model: "text-embedding-004".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+
+        let embeddings = provider.generate_embedding(&request).await.unwrap();
+        assert_eq!(embeddings.embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+
+        let raw_request = requests.recv().await.unwrap();
+        assert!(raw_request.starts_with("POST /models/text-embedding-004:batchEmbedContents "));
+        assert!(raw_request.contains("x-goog-api-key: secret"));
+        assert!(raw_request.contains(r#""taskType":"RETRIEVAL_DOCUMENT""#));
+        assert!(!raw_request.contains("outputDimensionality"));
+    }
+
+    #[tokio::test]
+    async fn test_model_dim() {
+        let provider =
+            GeminiProvider::new(None, Some("secret".to_string()), RetryConfig::from_env());
+        assert_eq!(provider.model_dim("text-embedding-004").await.unwrap(), 768);
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use ort::session::{Session, SessionInputValue};
+use ort::value::Tensor;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use crate::errors::VectorizeError;
+use crate::transformers::providers;
+use crate::types::InputType;
+
+pub const LOCAL_MODEL_DIR: &str = "/var/lib/vectorize/models";
+pub const MAX_BATCH_SIZE: usize = 32;
+// used when the model's tokenizer.json does not configure truncation
+const MAX_SEQUENCE_LENGTH: usize = 512;
+
+// a sentence-transformer model exported to ONNX
+struct LocalModel {
+    session: Session,
+    tokenizer: Tokenizer,
+}
+
+lazy_static! {
+    // models are loaded once per process, keyed by the model's directory
+    static ref MODELS: Mutex<HashMap<PathBuf, Arc<Mutex<LocalModel>>>> = Mutex::new(HashMap::new());
+}
+
+// runs models in-process with ONNX Runtime
+// each model is a directory, under the model dir, that contains a model.onnx and a tokenizer.json
+pub struct LocalProvider {
+    pub model_dir: PathBuf,
+}
+
+impl LocalProvider {
+    pub fn new(model_dir: Option<String>) -> Self {
+        let final_model_dir = match model_dir.filter(|d| !d.is_empty()) {
+            Some(dir) => dir,
+            None => env::var("VECTORIZE_LOCAL_MODEL_DIR").unwrap_or(LOCAL_MODEL_DIR.to_string()),
+        };
+        LocalProvider {
+            model_dir: PathBuf::from(final_model_dir),
+        }
+    }
+
+    // the directory of a model, which must be under the model dir
+    // model names come from SQL, so names such as `../..` or absolute paths are rejected
+    fn model_path(&self, model_name: &str) -> Result<PathBuf, VectorizeError> {
+        let name = Path::new(model_name);
+        let is_relative = name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if model_name.is_empty() || !is_relative {
+            return Err(VectorizeError::ModelNotFound(format!(
+                "{model_name} is not a directory in {}",
+                self.model_dir.display()
+            )));
+        }
+        Ok(self.model_dir.join(name))
+    }
+}
+
+fn load(path: PathBuf) -> Result<Arc<Mutex<LocalModel>>, VectorizeError> {
+    let mut models = MODELS.lock().expect("local model cache poisoned");
+    if let Some(model) = models.get(&path) {
+        return Ok(model.clone());
+    }
+    if !path.is_dir() {
+        return Err(VectorizeError::ModelNotFound(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    let model = Arc::new(Mutex::new(load_model(&path)?));
+    models.insert(path, model.clone());
+    Ok(model)
+}
+
+fn load_model(path: &Path) -> Result<LocalModel, VectorizeError> {
+    let session = Session::builder()
+        .and_then(|builder| builder.commit_from_file(path.join("model.onnx")))
+        .map_err(|e| anyhow!("failed to load {}: {e}", path.display()))?;
+    let mut tokenizer = Tokenizer::from_file(path.join("tokenizer.json"))
+        .map_err(|e| anyhow!("failed to load tokenizer of {}: {e}", path.display()))?;
+    // pad every batch to its longest input
+    tokenizer.with_padding(Some(PaddingParams::default()));
+    if tokenizer.get_truncation().is_none() {
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_SEQUENCE_LENGTH,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!("failed to configure tokenizer: {e}"))?;
+    }
+    Ok(LocalModel { session, tokenizer })
+}
+
+impl LocalModel {
+    fn embed(&mut self, inputs: Vec<String>) -> Result<Vec<Vec<f64>>, VectorizeError> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs, true)
+            .map_err(|e| anyhow!("failed to tokenize inputs: {e}"))?;
+        let batch_size = encodings.len();
+        let seq_len = encodings.first().map_or(0, |e| e.get_ids().len());
+        let flatten = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|e| f(e).iter().map(|v| *v as i64))
+                .collect()
+        };
+        let attention_mask = flatten(|e| e.get_attention_mask());
+        let tensor = |values: Vec<i64>| -> Result<SessionInputValue<'static>, VectorizeError> {
+            let tensor = Tensor::from_array(([batch_size, seq_len], values))
+                .map_err(|e| anyhow!("failed to create input tensor: {e}"))?;
+            Ok(tensor.into())
+        };
+
+        let mut session_inputs: Vec<(Cow<str>, SessionInputValue)> = Vec::new();
+        for input in self.session.inputs.iter() {
+            let values = match input.name.as_str() {
+                "input_ids" => flatten(|e| e.get_ids()),
+                "attention_mask" => attention_mask.clone(),
+                "token_type_ids" => flatten(|e| e.get_type_ids()),
+                name => Err(anyhow!("unsupported model input: {name}"))?,
+            };
+            session_inputs.push((input.name.clone().into(), tensor(values)?));
+        }
+        let outputs = self
+            .session
+            .run(session_inputs)
+            .map_err(|e| anyhow!("failed to run model: {e}"))?;
+        let (shape, values) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(|e| anyhow!("failed to read model output: {e}"))?;
+        let dims: Vec<usize> = shape.iter().map(|d| *d as usize).collect();
+        let embeddings = match dims.as_slice() {
+            // token embeddings, e.g. last_hidden_state
+            [_, _, hidden_size] => mean_pool(values, &attention_mask, seq_len, *hidden_size),
+            // models exported with their pooling layer
+            [_, hidden_size] => values
+                .chunks(*hidden_size)
+                .map(|e| e.iter().map(|v| *v as f64).collect())
+                .collect(),
+            _ => Err(anyhow!("unexpected model output shape: {dims:?}"))?,
+        };
+        Ok(embeddings.into_iter().map(normalize).collect())
+    }
+}
+
+// averages the embeddings of each input's tokens, ignoring padding
+fn mean_pool(
+    token_embeddings: &[f32],
+    attention_mask: &[i64],
+    seq_len: usize,
+    hidden_size: usize,
+) -> Vec<Vec<f64>> {
+    token_embeddings
+        .chunks(seq_len * hidden_size)
+        .zip(attention_mask.chunks(seq_len))
+        .map(|(tokens, mask)| {
+            let mut sum = vec![0.0_f64; hidden_size];
+            let mut count = 0.0_f64;
+            for (token, m) in tokens.chunks(hidden_size).zip(mask) {
+                if *m == 0 {
+                    continue;
+                }
+                count += 1.0;
+                for (s, v) in sum.iter_mut().zip(token) {
+                    *s += *v as f64;
+                }
+            }
+            sum.into_iter().map(|s| s / count.max(1.0)).collect()
+        })
+        .collect()
+}
+
+fn normalize(embedding: Vec<f64>) -> Vec<f64> {
+    let norm = embedding.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return embedding;
+    }
+    embedding.into_iter().map(|v| v / norm).collect()
+}
+
+#[async_trait]
+impl EmbeddingProvider for LocalProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let path = self.model_path(&request.model)?;
+        let inputs = request.input.clone();
+        // loading and running a model is CPU bound, so it runs on the blocking thread pool
+        tokio::task::spawn_blocking(move || {
+            let model = load(path)?;
+            let mut model = model.lock().expect("local model poisoned");
+            let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(inputs.len());
+            for chunk in providers::split_vector(inputs, MAX_BATCH_SIZE) {
+                all_embeddings.extend(model.embed(chunk)?);
+            }
+            Ok(GenericEmbeddingResponse {
+                embeddings: all_embeddings,
+            })
+        })
+        .await
+        .map_err(|e| anyhow!("local model task failed: {e}"))?
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        let req = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+        let embedding = self.generate_embedding(&req).await?;
+        Ok(embedding.embeddings[0].len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean_pool_ignores_padding() {
+        // two inputs, three tokens of two dimensions each, the second input is padded
+        let tokens = [
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, //
+            1.0, 1.0, 3.0, 3.0, 100.0, 100.0,
+        ];
+        let mask = [1, 1, 1, 1, 1, 0];
+        let pooled = mean_pool(&tokens, &mask, 3, 2);
+        assert_eq!(pooled, vec![vec![3.0, 4.0], vec![2.0, 2.0]]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(vec![3.0, 4.0]), vec![0.6, 0.8]);
+        assert_eq!(normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_model() {
+        let provider = LocalProvider::new(Some("/nonexistent".to_string()));
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello".to_string()],
+            model: "all-MiniLM-L6-v2".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+        assert!(matches!(
+            provider.generate_embedding(&request).await,
+            Err(VectorizeError::ModelNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_model_path_stays_in_model_dir() {
+        let provider = LocalProvider::new(Some("/models".to_string()));
+        assert_eq!(
+            provider.model_path("all-MiniLM-L6-v2").unwrap(),
+            PathBuf::from("/models/all-MiniLM-L6-v2")
+        );
+        for name in [
+            "",
+            "../secrets",
+            "all-MiniLM-L6-v2/../..",
+            "/etc",
+            "./model",
+        ] {
+            assert!(
+                matches!(
+                    provider.model_path(name),
+                    Err(VectorizeError::ModelNotFound(_))
+                ),
+                "{name} should be rejected"
+            );
+        }
+    }
+}
@@ -1,7 +1,13 @@
+pub mod azure;
 pub mod cohere;
+pub mod custom;
+pub mod gemini;
+#[cfg(feature = "onnx")]
+pub mod local;
 pub mod ollama;
 pub mod openai;
 pub mod portkey;
+pub mod tei;
 pub mod vector_serve;
 pub mod voyage;
 
@@ -9,14 +15,17 @@ use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use super::types::Inputs;
+use super::types::{Inputs, SparseEmbedding};
 use crate::errors::VectorizeError;
+use crate::guc::ModelGucConfig;
+use crate::transformers::http_handler::RetryConfig;
 use crate::transformers::providers;
+use crate::types::InputType;
 use crate::types::Model;
 use crate::types::ModelSource;
 
 #[async_trait]
-pub trait EmbeddingProvider {
+pub trait EmbeddingProvider: Send + Sync {
     #[allow(async_fn_in_trait)]
     async fn generate_embedding<'a>(
         &self,
@@ -24,12 +33,46 @@ pub trait EmbeddingProvider {
     ) -> Result<GenericEmbeddingResponse, VectorizeError>;
     #[allow(async_fn_in_trait)]
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError>;
+    // providers of learned sparse models, e.g. SPLADE, return term weights instead of dense embeddings
+    #[allow(async_fn_in_trait)]
+    async fn generate_sparse_embedding<'a>(
+        &self,
+        _request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericSparseEmbeddingResponse, VectorizeError> {
+        Err(anyhow::anyhow!(
+            "sparse embeddings are not supported by this provider"
+        ))?
+    }
+    // size of the vocabulary of a sparse model, i.e. the dimensions of its sparse embeddings
+    #[allow(async_fn_in_trait)]
+    async fn sparse_dim(&self, _model_name: &str) -> Result<u32, VectorizeError> {
+        Err(anyhow::anyhow!(
+            "sparse embeddings are not supported by this provider"
+        ))?
+    }
+}
+
+// reorders search results by the relevance of each document to the query, e.g. with a cross-encoder
+#[async_trait]
+pub trait RerankProvider: Send + Sync {
+    #[allow(async_fn_in_trait)]
+    async fn rerank<'a>(
+        &self,
+        request: &'a GenericRerankRequest,
+    ) -> Result<GenericRerankResponse, VectorizeError>;
 }
 
 #[derive(Clone, Deserialize, Debug, Serialize)]
 pub struct GenericEmbeddingRequest {
     pub input: Vec<String>,
     pub model: String,
+    // providers that support asymmetric embeddings embed queries differently from documents
+    #[serde(default)]
+    pub input_type: InputType,
+    // requested size of the embeddings, for models trained to be truncated (Matryoshka embeddings)
+    // providers that cannot request it return full size embeddings, see reduce_dimensions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -37,49 +80,179 @@ pub struct GenericEmbeddingResponse {
     pub embeddings: Vec<Vec<f64>>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct GenericSparseEmbeddingResponse {
+    pub embeddings: Vec<SparseEmbedding>,
+}
+
+#[derive(Clone, Deserialize, Debug, Serialize)]
+pub struct GenericRerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<String>,
+}
+
+// the relevance of the document at index of the request, where higher is more relevant
+#[derive(Clone, Deserialize, Debug, PartialEq, Serialize)]
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GenericRerankResponse {
+    pub results: Vec<RerankResult>,
+}
+
 pub fn prepare_generic_embedding_request(
     model: &Model,
     inputs: &[Inputs],
+    input_type: InputType,
+    dimensions: Option<u32>,
 ) -> GenericEmbeddingRequest {
     let text_inputs = providers::openai::trim_inputs(inputs);
 
     GenericEmbeddingRequest {
         input: text_inputs,
         model: model.api_name(),
+        input_type,
+        dimensions,
     }
 }
 
+// truncates embeddings that are larger than the requested dimensions, and rescales them to unit length
+// embeddings from providers that support the dimensions of the request are already the requested size
+pub fn reduce_dimensions(embeddings: Vec<Vec<f64>>, dimensions: Option<u32>) -> Vec<Vec<f64>> {
+    let Some(dim) = dimensions.map(|d| d as usize) else {
+        return embeddings;
+    };
+    embeddings
+        .into_iter()
+        .map(|mut embedding| {
+            if embedding.len() <= dim {
+                return embedding;
+            }
+            embedding.truncate(dim);
+            let norm = embedding.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                embedding.iter_mut().for_each(|v| *v /= norm);
+            }
+            embedding
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn get_provider(
     model_source: &ModelSource,
     api_key: Option<String>,
     url: Option<String>,
     virtual_key: Option<String>,
+    api_version: Option<String>,
+    truncate: Option<bool>,
+    normalize: Option<bool>,
+    retry: RetryConfig,
 ) -> Result<Box<dyn EmbeddingProvider>, VectorizeError> {
     match model_source {
         ModelSource::OpenAI => Ok(Box::new(providers::openai::OpenAIProvider::new(
-            url, api_key,
+            url, api_key, retry,
         ))),
         ModelSource::Cohere => Ok(Box::new(providers::cohere::CohereProvider::new(
-            url, api_key,
+            url, api_key, retry,
         ))),
         ModelSource::Portkey => Ok(Box::new(providers::portkey::PortkeyProvider::new(
             url,
             api_key,
             virtual_key,
+            retry,
         ))),
         ModelSource::Voyage => Ok(Box::new(providers::voyage::VoyageProvider::new(
-            url, api_key,
+            url, api_key, retry,
         ))),
         ModelSource::SentenceTransformers => Ok(Box::new(
-            providers::vector_serve::VectorServeProvider::new(url, api_key),
+            providers::vector_serve::VectorServeProvider::new(url, api_key, retry),
         )),
-        ModelSource::Ollama => Ok(Box::new(providers::ollama::OllamaProvider::new(url))),
+        ModelSource::Ollama => Ok(Box::new(providers::ollama::OllamaProvider::new(url, retry))),
+        ModelSource::Azure => Ok(Box::new(providers::azure::AzureOpenAIProvider::new(
+            url,
+            api_key,
+            api_version,
+            retry,
+        ))),
+        ModelSource::Gemini => Ok(Box::new(providers::gemini::GeminiProvider::new(
+            url, api_key, retry,
+        ))),
+        ModelSource::Tei => Ok(Box::new(providers::tei::TeiProvider::new(
+            url, api_key, truncate, normalize, retry,
+        ))),
+        #[cfg(feature = "onnx")]
+        ModelSource::Local => Ok(Box::new(providers::local::LocalProvider::new(url))),
+        #[cfg(not(feature = "onnx"))]
+        ModelSource::Local => Err(anyhow::anyhow!(
+            "local models require vectorize to be built with the onnx feature"
+        ))?,
         ModelSource::Tembo => Err(anyhow::anyhow!(
             "Ollama/Tembo transformer not implemented yet"
         ))?,
     }
 }
 
+// the provider of a model, which is the provider registered in vectorize.model_providers when there is one
+pub fn get_model_provider(
+    model_source: &ModelSource,
+    api_key: Option<String>,
+    guc_configs: &ModelGucConfig,
+    retry: RetryConfig,
+) -> Result<Box<dyn EmbeddingProvider>, VectorizeError> {
+    match &guc_configs.custom_provider {
+        Some(config) => Ok(Box::new(providers::custom::CustomProvider::new(
+            config.clone(),
+            api_key,
+            retry,
+        ))),
+        None => get_provider(
+            model_source,
+            api_key,
+            guc_configs.service_url.clone(),
+            guc_configs.virtual_key.clone(),
+            guc_configs.api_version.clone(),
+            guc_configs.truncate,
+            guc_configs.normalize,
+            retry,
+        ),
+    }
+}
+
+// the provider of a rerank model, e.g. cohere/rerank-english-v3.0 or cross-encoder/ms-marco-MiniLM-L-6-v2
+// models that are not served by cohere or voyage are served by vector-serve's cross-encoders
+pub fn get_rerank_provider(
+    model_source: &ModelSource,
+    api_key: Option<String>,
+    guc_configs: &ModelGucConfig,
+    retry: RetryConfig,
+) -> Result<Box<dyn RerankProvider>, VectorizeError> {
+    if guc_configs.custom_provider.is_some() {
+        return Err(
+            anyhow::anyhow!("reranking is not supported by registered model providers").into(),
+        );
+    }
+    let url = guc_configs.service_url.clone();
+    match model_source {
+        ModelSource::Cohere => Ok(Box::new(providers::cohere::CohereProvider::new(
+            url, api_key, retry,
+        ))),
+        ModelSource::Voyage => Ok(Box::new(providers::voyage::VoyageProvider::new(
+            url, api_key, retry,
+        ))),
+        ModelSource::SentenceTransformers => Ok(Box::new(
+            providers::vector_serve::VectorServeProvider::new(url, api_key, retry),
+        )),
+        _ => Err(anyhow::anyhow!(
+            "reranking is not supported by {model_source} models"
+        ))?,
+    }
+}
+
 fn split_vector(vec: Vec<String>, chunk_size: usize) -> Vec<Vec<String>> {
     vec.chunks(chunk_size).map(|chunk| chunk.to_vec()).collect()
 }
@@ -104,3 +277,18 @@ struct Choice {
 struct ResponseMessage {
     content: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduce_dimensions() {
+        let embeddings = vec![vec![3.0, 4.0, 12.0], vec![0.6, 0.8]];
+        assert_eq!(reduce_dimensions(embeddings.clone(), None), embeddings);
+        assert_eq!(
+            reduce_dimensions(embeddings, Some(2)),
+            vec![vec![0.6, 0.8], vec![0.6, 0.8]]
+        );
+    }
+}
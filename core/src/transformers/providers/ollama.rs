@@ -2,12 +2,11 @@ use super::{
     ChatMessageRequest, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::types::InputType;
 use async_trait::async_trait;
-use ollama_rs::{
-    generation::completion::request::GenerationRequest,
-    generation::embeddings::request::{EmbeddingsInput, GenerateEmbeddingsRequest},
-    Ollama,
-};
+use ollama_rs::{generation::completion::request::GenerationRequest, Ollama};
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -15,16 +14,23 @@ pub const OLLAMA_BASE_URL: &str = "http://localhost:3001";
 
 pub struct OllamaProvider {
     pub instance: Ollama,
+    pub url: String,
+    pub retry: RetryConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct ModelInfo {
-    embedding_dimension: u32,
-    max_seq_len: u32,
+struct OllamaEmbeddingBody {
+    model: String,
+    input: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct OllamaEmbeddingResponse {
+    embeddings: Vec<Vec<f64>>,
 }
 
 impl OllamaProvider {
-    pub fn new(url: Option<String>) -> Self {
+    pub fn new(url: Option<String>, retry: RetryConfig) -> Self {
         let url_in = url.unwrap_or_else(|| OLLAMA_BASE_URL.to_string());
         let parsed_url = Url::parse(&url_in).unwrap_or_else(|_| panic!("invalid url: {}", url_in));
         let instance = Ollama::new(
@@ -35,7 +41,11 @@ impl OllamaProvider {
             ),
             parsed_url.port().expect("parsed port missing"),
         );
-        OllamaProvider { instance }
+        OllamaProvider {
+            instance,
+            url: url_in.trim_end_matches('/').to_string(),
+            retry,
+        }
     }
 }
 
@@ -45,20 +55,21 @@ impl EmbeddingProvider for OllamaProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let model_name = request.model.clone();
-
-        let embedding_input = EmbeddingsInput::Multiple(request.input.clone());
-        let req = GenerateEmbeddingsRequest::new(model_name.clone(), embedding_input);
-
-        let embed = self.instance.generate_embeddings(req).await?;
-
-        let embed = embed
-            .embeddings
-            .iter()
-            .map(|x| x.iter().map(|y| *y as f64).collect())
-            .collect();
-
-        Ok(GenericEmbeddingResponse { embeddings: embed })
+        let client = Client::new();
+        let req = client
+            .post(format!("{}/api/embed", self.url))
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(&OllamaEmbeddingBody {
+                model: request.model.clone(),
+                input: request.input.clone(),
+            });
+        let response = send_with_retry(req, &self.retry).await?;
+        let embeddings = handle_response::<OllamaEmbeddingResponse>(response, "embed").await?;
+        Ok(GenericEmbeddingResponse {
+            embeddings: embeddings.embeddings,
+        })
     }
 
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
@@ -66,6 +77,8 @@ impl EmbeddingProvider for OllamaProvider {
         let req = GenericEmbeddingRequest {
             input: vec!["hello world".to_string()],
             model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
         };
         let embedding = self.generate_embedding(&req).await?;
         let dim = embedding.embeddings[0].len();
@@ -112,3 +125,32 @@ pub fn ollama_embedding_dim(model_name: &str) -> i32 {
         _ => 1536,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::http_handler::{mock_server, ok_response};
+
+    #[tokio::test]
+    async fn test_ollama_embeddings_are_retried() {
+        let body = r#"{"model": "nomic-embed-text", "embeddings": [[0.5, -0.25]]}"#;
+        let (url, mut requests) = mock_server(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            ok_response(body),
+        ])
+        .await;
+        let provider = OllamaProvider::new(Some(url), RetryConfig::from_env());
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: "nomic-embed-text".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+
+        let embeddings = provider.generate_embedding(&request).await.unwrap();
+        assert_eq!(embeddings.embeddings, vec![vec![0.5, -0.25]]);
+        let embed_request = requests.recv().await.unwrap();
+        assert!(embed_request.starts_with("POST /api/embed "));
+        assert!(embed_request.contains(r#"{"model":"nomic-embed-text","input":["hello world"]}"#));
+    }
+}
@@ -6,7 +6,7 @@ use super::{
     GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
 use crate::transformers::providers;
 use crate::transformers::types::Inputs;
 use async_trait::async_trait;
@@ -18,19 +18,27 @@ pub const MAX_TOKEN_LEN: usize = 8192;
 pub struct OpenAIProvider {
     pub url: String,
     pub api_key: String,
+    pub retry: RetryConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OpenAIEmbeddingBody {
     pub model: String,
     pub input: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
 }
 
 impl From<GenericEmbeddingRequest> for OpenAIEmbeddingBody {
     fn from(request: GenericEmbeddingRequest) -> Self {
+        // older models reject the dimensions parameter
+        let dimensions = request
+            .dimensions
+            .filter(|_| openai_supports_dimensions(&request.model));
         OpenAIEmbeddingBody {
             model: request.model,
             input: request.input,
+            dimensions,
         }
     }
 }
@@ -56,7 +64,7 @@ impl From<OpenAIEmbeddingResponse> for GenericEmbeddingResponse {
 }
 
 impl OpenAIProvider {
-    pub fn new(url: Option<String>, api_key: Option<String>) -> Self {
+    pub fn new(url: Option<String>, api_key: Option<String>, retry: RetryConfig) -> Self {
         let final_url = match url {
             Some(url) => url,
             None => OPENAI_BASE_URL.to_string(),
@@ -68,6 +76,7 @@ impl OpenAIProvider {
         OpenAIProvider {
             url: final_url,
             api_key: final_api_key,
+            retry,
         }
     }
 }
@@ -87,6 +96,7 @@ impl EmbeddingProvider for OpenAIProvider {
                 .map(|chunk| OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: req.dimensions,
                 })
                 .collect()
         } else {
@@ -98,15 +108,14 @@ impl EmbeddingProvider for OpenAIProvider {
         for request_payload in todo_requests.iter() {
             let payload_val = serde_json::to_value(request_payload)?;
             let embeddings_url = format!("{}/embeddings", self.url);
-            let response = client
+            let req = client
                 .post(&embeddings_url)
                 .timeout(std::time::Duration::from_secs(120_u64))
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
                 .header("Authorization", format!("Bearer {}", self.api_key))
-                .json(&payload_val)
-                .send()
-                .await?;
+                .json(&payload_val);
+            let response = send_with_retry(req, &self.retry).await?;
 
             let embeddings =
                 handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
@@ -131,6 +140,11 @@ pub fn openai_embedding_dim(model_name: &str) -> i32 {
     }
 }
 
+// the text-embedding-3 models can shorten their embeddings to any size
+pub fn openai_supports_dimensions(model_name: &str) -> bool {
+    model_name.starts_with("text-embedding-3")
+}
+
 impl OpenAIProvider {
     pub async fn generate_response(
         &self,
@@ -181,15 +195,42 @@ pub fn trim_inputs(inputs: &[Inputs]) -> Vec<String> {
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::types::InputType;
     use tokio::test as async_test;
 
+    #[test]
+    fn test_openai_dimensions() {
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: "text-embedding-3-small".to_string(),
+            input_type: InputType::Document,
+            dimensions: Some(512),
+        };
+        let body = serde_json::to_value(OpenAIEmbeddingBody::from(request.clone())).unwrap();
+        assert_eq!(body["dimensions"], 512);
+
+        // text-embedding-ada-002 rejects the dimensions parameter
+        let request = GenericEmbeddingRequest {
+            model: "text-embedding-ada-002".to_string(),
+            ..request
+        };
+        let body = serde_json::to_value(OpenAIEmbeddingBody::from(request)).unwrap();
+        assert!(body.get("dimensions").is_none());
+    }
+
     #[ignore]
     #[async_test]
     async fn test_generate_embedding() {
-        let provider = OpenAIProvider::new(Some(OPENAI_BASE_URL.to_string()), None);
+        let provider = OpenAIProvider::new(
+            Some(OPENAI_BASE_URL.to_string()),
+            None,
+            RetryConfig::from_env(),
+        );
         let request = GenericEmbeddingRequest {
             model: "text-embedding-ada-002".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
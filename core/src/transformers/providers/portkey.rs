@@ -5,9 +5,10 @@ use super::{
     GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
 use crate::transformers::providers;
 use crate::transformers::providers::openai;
+use crate::types::InputType;
 use async_trait::async_trait;
 use std::env;
 
@@ -18,10 +19,16 @@ pub struct PortkeyProvider {
     pub url: String,
     pub api_key: String,
     pub virtual_key: String,
+    pub retry: RetryConfig,
 }
 
 impl PortkeyProvider {
-    pub fn new(url: Option<String>, api_key: Option<String>, virtual_key: Option<String>) -> Self {
+    pub fn new(
+        url: Option<String>,
+        api_key: Option<String>,
+        virtual_key: Option<String>,
+        retry: RetryConfig,
+    ) -> Self {
         let final_url = match url {
             Some(url) => url,
             None => PORTKEY_BASE_URL.to_string(),
@@ -38,6 +45,7 @@ impl PortkeyProvider {
             url: final_url,
             api_key: final_api_key,
             virtual_key: final_virtual_key,
+            retry,
         }
     }
 }
@@ -58,6 +66,7 @@ impl EmbeddingProvider for PortkeyProvider {
                 .map(|chunk| openai::OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: req.dimensions,
                 })
                 .collect()
         } else {
@@ -68,16 +77,15 @@ impl EmbeddingProvider for PortkeyProvider {
         let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(num_inputs);
         for request_payload in todo_requests.iter() {
             let payload_val = serde_json::to_value(request_payload)?;
-            let response = client
+            let req = client
                 .post(&embeddings_url)
                 .timeout(std::time::Duration::from_secs(120_u64))
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
                 .header("x-portkey-virtual-key", self.virtual_key.clone())
                 .header("x-portkey-api-key", &self.api_key)
-                .json(&payload_val)
-                .send()
-                .await?;
+                .json(&payload_val);
+            let response = send_with_retry(req, &self.retry).await?;
 
             let embeddings =
                 handle_response::<openai::OpenAIEmbeddingResponse>(response, "embeddings").await?;
@@ -93,6 +101,8 @@ impl EmbeddingProvider for PortkeyProvider {
         let req = GenericEmbeddingRequest {
             input: vec!["hello world".to_string()],
             model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
         };
         let embedding = self.generate_embedding(&req).await?;
         let dim = embedding.embeddings[0].len();
@@ -138,10 +148,17 @@ mod portkey_integration_tests {
         let portkey_api_key = env::var("PORTKEY_API_KEY").expect("PORTKEY_API_KEY not set");
         let portkey_virtual_key =
             env::var("PORTKEY_VIRTUAL_KEY_OPENAI").expect("PORTKEY_VIRTUAL_KEY_OPENAI not set");
-        let provider = PortkeyProvider::new(None, Some(portkey_api_key), Some(portkey_virtual_key));
+        let provider = PortkeyProvider::new(
+            None,
+            Some(portkey_api_key),
+            Some(portkey_virtual_key),
+            RetryConfig::from_env(),
+        );
         let request = GenericEmbeddingRequest {
             model: "text-embedding-ada-002".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
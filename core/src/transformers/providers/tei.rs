@@ -0,0 +1,335 @@
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+    GenericSparseEmbeddingResponse,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::transformers::providers;
+use crate::transformers::types::SparseEmbedding;
+use crate::types::InputType;
+use async_trait::async_trait;
+use std::env;
+use tokio::sync::OnceCell;
+
+pub const TEI_BASE_URL: &str = "http://localhost:8080";
+// the server does not report the vocabulary of the served model, which is read from its config on the Hugging Face Hub
+pub const HF_HUB_URL: &str = "https://huggingface.co";
+
+// Hugging Face Text Embeddings Inference, a server that serves a single model
+pub struct TeiProvider {
+    pub url: String,
+    pub api_key: Option<String>,
+    // truncate inputs that exceed the model's max_input_length instead of failing the request
+    pub truncate: bool,
+    // return unit length embeddings
+    pub normalize: bool,
+    // /info of the server, fetched once per provider
+    info: OnceCell<TeiInfo>,
+    pub retry: RetryConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TeiEmbeddingBody {
+    pub inputs: Vec<String>,
+    pub truncate: bool,
+    pub normalize: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TeiSparseEmbeddingBody {
+    pub inputs: Vec<String>,
+    pub truncate: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TeiSparseValue {
+    pub index: u32,
+    pub value: f64,
+}
+
+// a subset of the response of /info
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TeiInfo {
+    pub model_id: String,
+    pub max_input_length: u32,
+    pub max_client_batch_size: usize,
+}
+
+// a subset of the config.json of a model on the Hugging Face Hub
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HubModelConfig {
+    vocab_size: u32,
+}
+
+impl TeiProvider {
+    pub fn new(
+        url: Option<String>,
+        api_key: Option<String>,
+        truncate: Option<bool>,
+        normalize: Option<bool>,
+        retry: RetryConfig,
+    ) -> Self {
+        let final_url = match url {
+            Some(url) => url,
+            None => env::var("TEI_URL").unwrap_or(TEI_BASE_URL.to_string()),
+        };
+        TeiProvider {
+            url: final_url.trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|k| !k.is_empty()),
+            truncate: truncate.unwrap_or(true),
+            normalize: normalize.unwrap_or(true),
+            info: OnceCell::new(),
+            retry,
+        }
+    }
+
+    fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => req.bearer_auth(api_key),
+            None => req,
+        }
+    }
+
+    pub async fn info(&self) -> Result<&TeiInfo, VectorizeError> {
+        self.info
+            .get_or_try_init(|| async {
+                let client = Client::new();
+                let req = self.authorize(
+                    client
+                        .get(format!("{}/info", self.url))
+                        .timeout(std::time::Duration::from_secs(120_u64)),
+                );
+                let response = send_with_retry(req, &self.retry).await?;
+                handle_response::<TeiInfo>(response, "info").await
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for TeiProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        // the server rejects requests with more inputs than its max_client_batch_size
+        let batch_size = self.info().await?.max_client_batch_size.max(1);
+        let client = Client::new();
+        let embedding_url = format!("{}/embed", self.url);
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for chunk in providers::split_vector(request.input.clone(), batch_size) {
+            let req = self.authorize(
+                client
+                    .post(&embedding_url)
+                    .timeout(std::time::Duration::from_secs(120_u64))
+                    .header("Content-Type", "application/json")
+                    .json(&TeiEmbeddingBody {
+                        inputs: chunk,
+                        truncate: self.truncate,
+                        normalize: self.normalize,
+                    }),
+            );
+            let response = send_with_retry(req, &self.retry).await?;
+            // embeddings are returned as a bare array of arrays
+            let embeddings = handle_response::<Vec<Vec<f64>>>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings);
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        // /info describes the served model and its limits, but not the size of its embeddings,
+        // so embed a sample input once the server is known to be up
+        let info = self.info().await?;
+        log::debug!(
+            "TEI server at {} serves {} (max_input_length {})",
+            self.url,
+            info.model_id,
+            info.max_input_length
+        );
+        let req = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+        let embedding = self.generate_embedding(&req).await?;
+        Ok(embedding.embeddings[0].len() as u32)
+    }
+
+    // served by models with a SPLADE pooling, e.g. naver/efficient-splade-VI-BT-large-doc
+    async fn generate_sparse_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericSparseEmbeddingResponse, VectorizeError> {
+        let batch_size = self.info().await?.max_client_batch_size.max(1);
+        let client = Client::new();
+        let embedding_url = format!("{}/embed_sparse", self.url);
+        let mut all_embeddings: Vec<SparseEmbedding> = Vec::with_capacity(request.input.len());
+        for chunk in providers::split_vector(request.input.clone(), batch_size) {
+            let req = self.authorize(
+                client
+                    .post(&embedding_url)
+                    .timeout(std::time::Duration::from_secs(120_u64))
+                    .header("Content-Type", "application/json")
+                    .json(&TeiSparseEmbeddingBody {
+                        inputs: chunk,
+                        truncate: self.truncate,
+                    }),
+            );
+            let response = send_with_retry(req, &self.retry).await?;
+            // the non-zero weights of each input, as an array of index and value objects
+            let embeddings =
+                handle_response::<Vec<Vec<TeiSparseValue>>>(response, "sparse_embeddings").await?;
+            all_embeddings.extend(embeddings.into_iter().map(|weights| SparseEmbedding {
+                indices: weights.iter().map(|w| w.index).collect(),
+                values: weights.iter().map(|w| w.value).collect(),
+            }));
+        }
+        Ok(GenericSparseEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    // the size of the vocabulary of the served model, from its config on the Hugging Face Hub
+    // models that are not on the Hub, e.g. served from a local path, need the job's dimensions instead
+    async fn sparse_dim(&self, _model_name: &str) -> Result<u32, VectorizeError> {
+        let model_id = self.info().await?.model_id.clone();
+        let hub_url = env::var("HF_ENDPOINT").unwrap_or(HF_HUB_URL.to_string());
+        let req = Client::new()
+            .get(format!(
+                "{}/{model_id}/resolve/main/config.json",
+                hub_url.trim_end_matches('/')
+            ))
+            .timeout(std::time::Duration::from_secs(120_u64));
+        let config = async {
+            let response = send_with_retry(req, &self.retry).await?;
+            handle_response::<HubModelConfig>(response, "model_config").await
+        };
+        match config.await {
+            Ok(config) => Ok(config.vocab_size),
+            Err(e) => Err(anyhow::anyhow!(
+                "failed to read the vocabulary size of {model_id} from the Hugging Face Hub, set `dimensions` to the size of its vocabulary: {e}"
+            ))?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::http_handler::{mock_server, ok_response};
+
+    const INFO: &str = r#"{"model_id": "BAAI/bge-small-en-v1.5", "max_input_length": 512, "max_client_batch_size": 2, "max_batch_tokens": 16384}"#;
+
+    #[tokio::test]
+    async fn test_tei_embeddings() {
+        let (url, mut requests) = mock_server(vec![
+            ok_response(INFO),
+            ok_response("[[0.1, 0.2], [0.3, 0.4]]"),
+            ok_response("[[0.5, 0.6]]"),
+        ])
+        .await;
+        let provider = TeiProvider::new(
+            Some(url),
+            Some("secret".to_string()),
+            None,
+            Some(false),
+            RetryConfig::from_env(),
+        );
+        let request = GenericEmbeddingRequest {
+            input: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            model: "bge-small-en-v1.5".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+
+        let embeddings = provider.generate_embedding(&request).await.unwrap();
+        assert_eq!(
+            embeddings.embeddings,
+            vec![vec![0.1, 0.2], vec![0.3, 0.4], vec![0.5, 0.6]]
+        );
+
+        let info_request = requests.recv().await.unwrap();
+        assert!(info_request.starts_with("GET /info "));
+        let embed_request = requests.recv().await.unwrap();
+        assert!(embed_request.starts_with("POST /embed "));
+        assert!(embed_request.contains("authorization: Bearer secret"));
+        assert!(embed_request.contains(r#"{"inputs":["a","b"],"truncate":true,"normalize":false}"#));
+        let embed_request = requests.recv().await.unwrap();
+        assert!(embed_request.contains(r#""inputs":["c"]"#));
+    }
+
+    #[tokio::test]
+    async fn test_tei_sparse_embeddings() {
+        let (url, mut requests) = mock_server(vec![
+            ok_response(INFO),
+            ok_response(
+                r#"[[{"index": 2054, "value": 1.25}, {"index": 7, "value": 0.5}], [{"index": 3, "value": 0.1}]]"#,
+            ),
+        ])
+        .await;
+        let provider =
+            TeiProvider::new(Some(url), None, Some(false), None, RetryConfig::from_env());
+        let request = GenericEmbeddingRequest {
+            input: vec!["a".to_string(), "b".to_string()],
+            model: "splade".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+
+        let embeddings = provider.generate_sparse_embedding(&request).await.unwrap();
+        assert_eq!(
+            embeddings.embeddings,
+            vec![
+                SparseEmbedding {
+                    indices: vec![2054, 7],
+                    values: vec![1.25, 0.5],
+                },
+                SparseEmbedding {
+                    indices: vec![3],
+                    values: vec![0.1],
+                },
+            ]
+        );
+
+        let _info_request = requests.recv().await.unwrap();
+        let embed_request = requests.recv().await.unwrap();
+        assert!(embed_request.starts_with("POST /embed_sparse "));
+        assert!(embed_request.contains(r#"{"inputs":["a","b"],"truncate":false}"#));
+    }
+
+    #[tokio::test]
+    async fn test_tei_sparse_dim() {
+        let (url, mut requests) = mock_server(vec![
+            ok_response(
+                r#"{"model_id": "naver/efficient-splade-VI-BT-large-doc", "max_input_length": 512, "max_client_batch_size": 32}"#,
+            ),
+            ok_response(r#"{"architectures": ["BertForMaskedLM"], "vocab_size": 30522}"#),
+        ])
+        .await;
+        std::env::set_var("HF_ENDPOINT", &url);
+        let provider = TeiProvider::new(Some(url), None, None, None, RetryConfig::from_env());
+        assert_eq!(provider.sparse_dim("splade").await.unwrap(), 30522);
+        let _info_request = requests.recv().await.unwrap();
+        let config_request = requests.recv().await.unwrap();
+        assert!(config_request
+            .starts_with("GET /naver/efficient-splade-VI-BT-large-doc/resolve/main/config.json "));
+    }
+
+    #[tokio::test]
+    async fn test_tei_model_dim() {
+        let (url, mut requests) =
+            mock_server(vec![ok_response(INFO), ok_response("[[0.1, 0.2, 0.3]]")]).await;
+        let provider = TeiProvider::new(Some(url), None, None, None, RetryConfig::from_env());
+        assert_eq!(provider.model_dim("bge-small-en-v1.5").await.unwrap(), 3);
+        // /info is fetched once, and reused for the batch size of the sample embedding
+        assert!(requests.recv().await.unwrap().starts_with("GET /info "));
+        assert!(requests.recv().await.unwrap().starts_with("POST /embed "));
+    }
+}
@@ -1,10 +1,14 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use super::{
+    EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse, GenericRerankRequest,
+    GenericRerankResponse, GenericSparseEmbeddingResponse, RerankProvider,
+};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
 use crate::transformers::providers::openai;
+use crate::transformers::types::SparseEmbedding;
 use async_trait::async_trait;
 use std::env;
 
@@ -13,6 +17,7 @@ pub const VECTOR_SERVE_BASE_URL: &str = "http://localhost:3000/v1";
 pub struct VectorServeProvider {
     pub url: String,
     pub api_key: Option<String>,
+    pub retry: RetryConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -22,8 +27,28 @@ struct ModelInfo {
     max_seq_len: u32,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SparseEmbeddingBody {
+    input: Vec<String>,
+    model: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SparseEmbeddingObject {
+    index: usize,
+    indices: Vec<u32>,
+    values: Vec<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SparseEmbeddingResponse {
+    data: Vec<SparseEmbeddingObject>,
+    model: String,
+    vocab_size: u32,
+}
+
 impl VectorServeProvider {
-    pub fn new(url: Option<String>, api_key: Option<String>) -> Self {
+    pub fn new(url: Option<String>, api_key: Option<String>, retry: RetryConfig) -> Self {
         let final_url = match url {
             Some(url) => url,
             None => VECTOR_SERVE_BASE_URL.to_string(),
@@ -36,6 +61,7 @@ impl VectorServeProvider {
         VectorServeProvider {
             url: final_url,
             api_key: final_api_key,
+            retry,
         }
     }
 }
@@ -55,6 +81,7 @@ impl EmbeddingProvider for VectorServeProvider {
                 .map(|chunk| openai::OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: req.dimensions,
                 })
                 .collect()
         } else {
@@ -75,7 +102,7 @@ impl EmbeddingProvider for VectorServeProvider {
             if let Some(key) = &self.api_key {
                 req = req.header("Authorization", format!("Bearer {}", key));
             }
-            let response = req.send().await?;
+            let response = send_with_retry(req, &self.retry).await?;
             let embeddings =
                 handle_response::<openai::OpenAIEmbeddingResponse>(response, "embeddings").await?;
             all_embeddings.extend(embeddings.data.iter().map(|x| x.embedding.clone()));
@@ -94,27 +121,165 @@ impl EmbeddingProvider for VectorServeProvider {
         if let Some(key) = &self.api_key {
             req = req.header("Authorization", format!("Bearer {}", key));
         }
-        let response = req.send().await?;
+        let response = send_with_retry(req, &self.retry).await?;
         let model_info = handle_response::<ModelInfo>(response, "model_info").await?;
         Ok(model_info.embedding_dimension)
     }
+
+    async fn generate_sparse_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericSparseEmbeddingResponse, VectorizeError> {
+        let mut all_embeddings: Vec<SparseEmbedding> = Vec::with_capacity(request.input.len());
+        for chunk in split_vector(request.input.clone(), 2048) {
+            let response = self.sparse_embeddings(chunk, &request.model).await?;
+            all_embeddings.extend(response.data.into_iter().map(|x| SparseEmbedding {
+                indices: x.indices,
+                values: x.values,
+            }));
+        }
+        Ok(GenericSparseEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    async fn sparse_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        // the size of the vocabulary is returned along with every sparse embedding
+        let response = self
+            .sparse_embeddings(vec!["hello world".to_string()], model_name)
+            .await?;
+        Ok(response.vocab_size)
+    }
+}
+
+impl VectorServeProvider {
+    async fn sparse_embeddings(
+        &self,
+        input: Vec<String>,
+        model: &str,
+    ) -> Result<SparseEmbeddingResponse, VectorizeError> {
+        let client = Client::new();
+        let mut req = client
+            .post(format!("{}/sparse_embeddings", self.url))
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(&SparseEmbeddingBody {
+                input,
+                model: model.to_string(),
+            });
+        if let Some(key) = &self.api_key {
+            req = req.header("Authorization", format!("Bearer {}", key));
+        }
+        let response = send_with_retry(req, &self.retry).await?;
+        handle_response::<SparseEmbeddingResponse>(response, "sparse_embeddings").await
+    }
+}
+
+// vector-serve scores each document with a cross-encoder, e.g. cross-encoder/ms-marco-MiniLM-L-6-v2
+#[async_trait]
+impl RerankProvider for VectorServeProvider {
+    async fn rerank<'a>(
+        &self,
+        request: &'a GenericRerankRequest,
+    ) -> Result<GenericRerankResponse, VectorizeError> {
+        let client = Client::new();
+        let mut req = client
+            .post(format!("{}/rerank", self.url))
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(request);
+        if let Some(key) = &self.api_key {
+            req = req.header("Authorization", format!("Bearer {}", key));
+        }
+        let response = send_with_retry(req, &self.retry).await?;
+        handle_response::<GenericRerankResponse>(response, "rerank").await
+    }
 }
 
 fn split_vector(vec: Vec<String>, chunk_size: usize) -> Vec<Vec<String>> {
     vec.chunks(chunk_size).map(|chunk| chunk.to_vec()).collect()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::http_handler::{mock_server, ok_response};
+    use crate::types::InputType;
+
+    #[tokio::test]
+    async fn test_vector_serve_sparse_embeddings() {
+        let body = r#"{"data": [{"index": 0, "indices": [2054, 7], "values": [1.25, 0.5]}], "model": "naver/splade-v3", "vocab_size": 30522}"#;
+        let (url, mut requests) = mock_server(vec![ok_response(body), ok_response(body)]).await;
+        let provider = VectorServeProvider::new(
+            Some(url),
+            Some("hf_token".to_string()),
+            RetryConfig::from_env(),
+        );
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: "naver/splade-v3".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+        };
+
+        let embeddings = provider.generate_sparse_embedding(&request).await.unwrap();
+        assert_eq!(
+            embeddings.embeddings,
+            vec![SparseEmbedding {
+                indices: vec![2054, 7],
+                values: vec![1.25, 0.5],
+            }]
+        );
+        let sparse_request = requests.recv().await.unwrap();
+        assert!(sparse_request.starts_with("POST /sparse_embeddings "));
+        assert!(sparse_request.contains("authorization: Bearer hf_token"));
+        assert!(sparse_request.contains(r#"{"input":["hello world"],"model":"naver/splade-v3"}"#));
+
+        assert_eq!(provider.sparse_dim("naver/splade-v3").await.unwrap(), 30522);
+    }
+
+    #[tokio::test]
+    async fn test_vector_serve_rerank() {
+        let body = r#"{"results": [{"index": 1, "relevance_score": 8.5}, {"index": 0, "relevance_score": -3.2}], "model": "cross-encoder/ms-marco-MiniLM-L-6-v2"}"#;
+        let (url, mut requests) = mock_server(vec![ok_response(body)]).await;
+        let provider = VectorServeProvider::new(Some(url), None, RetryConfig::from_env());
+        let request = GenericRerankRequest {
+            model: "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+            query: "capital of france".to_string(),
+            documents: vec!["Berlin".to_string(), "Paris".to_string()],
+        };
+
+        let response = provider.rerank(&request).await.unwrap();
+        assert_eq!(
+            response.results.iter().map(|r| r.index).collect::<Vec<_>>(),
+            vec![1, 0]
+        );
+        let rerank_request = requests.recv().await.unwrap();
+        assert!(rerank_request.starts_with("POST /rerank "));
+        assert!(rerank_request.contains(r#""documents":["Berlin","Paris"]"#));
+    }
+}
+
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::types::InputType;
     use tokio::test as async_test;
 
     #[async_test]
     async fn test_vector_serve_embeddings() {
-        let provider = VectorServeProvider::new(Some(VECTOR_SERVE_BASE_URL.to_string()), None);
+        let provider = VectorServeProvider::new(
+            Some(VECTOR_SERVE_BASE_URL.to_string()),
+            None,
+            RetryConfig::from_env(),
+        );
         let request = GenericEmbeddingRequest {
             model: "sentence-transformers/all-MiniLM-L6-v2".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
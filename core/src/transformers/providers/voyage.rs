@@ -1,9 +1,13 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use super::{
+    EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse, GenericRerankRequest,
+    GenericRerankResponse, RerankProvider, RerankResult,
+};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{handle_response, send_with_retry, RetryConfig};
+use crate::types::InputType;
 use async_trait::async_trait;
 use std::env;
 
@@ -12,6 +16,7 @@ pub const VOYAGE_BASE_URL: &str = "https://api.voyageai.com/v1";
 pub struct VoyageProvider {
     pub url: String,
     pub api_key: String,
+    pub retry: RetryConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -19,18 +24,35 @@ pub struct VoyageEmbeddingBody {
     pub input: Vec<String>,
     pub model: String,
     pub input_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dimension: Option<u32>,
 }
 
 impl From<GenericEmbeddingRequest> for VoyageEmbeddingBody {
     fn from(request: GenericEmbeddingRequest) -> Self {
+        let output_dimension = request
+            .dimensions
+            .filter(|_| voyage_supports_output_dimension(&request.model));
         VoyageEmbeddingBody {
             input: request.input,
             model: request.model,
-            input_type: "document".to_string(),
+            input_type: match request.input_type {
+                InputType::Document => "document".to_string(),
+                InputType::Query => "query".to_string(),
+            },
+            output_dimension,
         }
     }
 }
 
+// models that can return 256, 512, 1024 or 2048 dimensional embeddings
+pub fn voyage_supports_output_dimension(model_name: &str) -> bool {
+    matches!(
+        model_name,
+        "voyage-3-large" | "voyage-3.5" | "voyage-3.5-lite" | "voyage-code-3"
+    )
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VoyageEmbeddingResponse {
     pub data: Vec<EmbeddingObject>,
@@ -49,8 +71,38 @@ impl From<VoyageEmbeddingResponse> for GenericEmbeddingResponse {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoyageRerankBody {
+    pub query: String,
+    pub documents: Vec<String>,
+    pub model: String,
+}
+
+impl From<GenericRerankRequest> for VoyageRerankBody {
+    fn from(request: GenericRerankRequest) -> Self {
+        VoyageRerankBody {
+            query: request.query,
+            documents: request.documents,
+            model: request.model,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoyageRerankResponse {
+    pub data: Vec<RerankResult>,
+}
+
+impl From<VoyageRerankResponse> for GenericRerankResponse {
+    fn from(response: VoyageRerankResponse) -> Self {
+        GenericRerankResponse {
+            results: response.data,
+        }
+    }
+}
+
 impl VoyageProvider {
-    pub fn new(url: Option<String>, api_key: Option<String>) -> Self {
+    pub fn new(url: Option<String>, api_key: Option<String>, retry: RetryConfig) -> Self {
         let final_url = match url {
             Some(url) => url,
             None => VOYAGE_BASE_URL.to_string(),
@@ -62,6 +114,7 @@ impl VoyageProvider {
         VoyageProvider {
             url: final_url,
             api_key: final_api_key,
+            retry,
         }
     }
 }
@@ -77,14 +130,13 @@ impl EmbeddingProvider for VoyageProvider {
         let req_body = VoyageEmbeddingBody::from(request.clone());
         let embedding_url = format!("{}/embeddings", self.url);
 
-        let response = client
+        let req = client
             .post(&embedding_url)
             .timeout(std::time::Duration::from_secs(120_u64))
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&req_body)
-            .send()
-            .await?;
+            .json(&req_body);
+        let response = send_with_retry(req, &self.retry).await?;
 
         let embeddings = handle_response::<VoyageEmbeddingResponse>(response, "embeddings").await?;
         Ok(GenericEmbeddingResponse {
@@ -101,6 +153,8 @@ impl EmbeddingProvider for VoyageProvider {
         let req = GenericEmbeddingRequest {
             input: vec!["hello world".to_string()],
             model: model_name.to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
         };
         let embedding = self.generate_embedding(&req).await?;
         let dim = embedding.embeddings[0].len();
@@ -108,20 +162,100 @@ impl EmbeddingProvider for VoyageProvider {
     }
 }
 
+#[async_trait]
+impl RerankProvider for VoyageProvider {
+    async fn rerank<'a>(
+        &self,
+        request: &'a GenericRerankRequest,
+    ) -> Result<GenericRerankResponse, VectorizeError> {
+        let client = Client::new();
+
+        let req_body = VoyageRerankBody::from(request.clone());
+        let rerank_url = format!("{}/rerank", self.url);
+        let req = client
+            .post(&rerank_url)
+            .timeout(std::time::Duration::from_secs(120_u64))
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&req_body);
+        let response = send_with_retry(req, &self.retry).await?;
+
+        let results = handle_response::<VoyageRerankResponse>(response, "rerank").await?;
+        Ok(results.into())
+    }
+}
+
 #[cfg(test)]
 mod integration_tests {
     use super::*;
     use std::env;
 
+    #[test]
+    fn test_voyage_input_type() {
+        let request = GenericEmbeddingRequest {
+            input: vec!["hello world".to_string()],
+            model: "voyage-3-lite".to_string(),
+            input_type: InputType::Query,
+            dimensions: Some(256),
+        };
+        let body = VoyageEmbeddingBody::from(request.clone());
+        assert_eq!(body.input_type, "query");
+        // voyage-3-lite only returns 512 dimensional embeddings
+        assert_eq!(body.output_dimension, None);
+
+        let request = GenericEmbeddingRequest {
+            model: "voyage-3.5".to_string(),
+            ..request
+        };
+        assert_eq!(
+            VoyageEmbeddingBody::from(request).output_dimension,
+            Some(256)
+        );
+    }
+
+    #[test]
+    fn test_voyage_rerank_response() {
+        let response: VoyageRerankResponse = serde_json::from_value(serde_json::json!({
+            "object": "list",
+            "data": [
+                {"relevance_score": 0.9, "index": 1},
+                {"relevance_score": 0.2, "index": 0}
+            ],
+            "model": "rerank-2",
+            "usage": {"total_tokens": 10}
+        }))
+        .unwrap();
+        let response = GenericRerankResponse::from(response);
+        assert_eq!(
+            response.results,
+            vec![
+                RerankResult {
+                    index: 1,
+                    relevance_score: 0.9
+                },
+                RerankResult {
+                    index: 0,
+                    relevance_score: 0.2
+                }
+            ]
+        );
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_voyage_ai_embedding() {
         let api_key = Some(env::var("VOYAGE_API_KEY").expect("VOYAGE_API_KEY must be set"));
-        let provider = VoyageProvider::new(Some(VOYAGE_BASE_URL.to_string()), api_key);
+        let provider = VoyageProvider::new(
+            Some(VOYAGE_BASE_URL.to_string()),
+            api_key,
+            RetryConfig::from_env(),
+        );
 
         let request = GenericEmbeddingRequest {
             input: vec!["hello world".to_string()],
             model: "voyage-3-lite".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
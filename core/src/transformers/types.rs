@@ -38,9 +38,83 @@ pub struct PairedEmbeddings {
     pub embeddings: Vec<f64>,
 }
 
+// the embedding of one chunk of a record's text
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PairedChunkEmbeddings {
+    pub primary_key: String,
+    pub chunk_index: i32,
+    pub chunk: String,
+    pub embeddings: Vec<f64>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TransformerMetadata {
     pub model: String,
     pub max_seq_len: i32,
     pub embedding_dimension: i32,
 }
+
+// pgvector indexes sparse vectors of up to 1,000 non-zero elements
+pub const SPARSEVEC_MAX_NONZERO: usize = 1000;
+
+// term weights of a learned sparse model, indexed by the term's id in the model's vocabulary
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct SparseEmbedding {
+    pub indices: Vec<u32>,
+    pub values: Vec<f64>,
+}
+
+impl SparseEmbedding {
+    // the text of a sparsevec of size dim, e.g. {1:0.5,3:0.25}/5
+    // only the highest SPARSEVEC_MAX_NONZERO weights are kept, so that the embedding can be indexed
+    pub fn to_sparsevec(&self, dim: u32) -> String {
+        let mut weights: Vec<(u32, f64)> = self
+            .indices
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+            .filter(|(_, value)| *value != 0.0)
+            .collect();
+        if weights.len() > SPARSEVEC_MAX_NONZERO {
+            weights.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+            weights.truncate(SPARSEVEC_MAX_NONZERO);
+        }
+        weights.sort_by_key(|(index, _)| *index);
+        // sparsevec indices start at 1
+        let elements: Vec<String> = weights
+            .iter()
+            .map(|(index, value)| format!("{}:{}", index + 1, value))
+            .collect();
+        format!("{{{}}}/{dim}", elements.join(","))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PairedSparseEmbeddings {
+    pub primary_key: String,
+    pub embeddings: SparseEmbedding,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_sparsevec() {
+        let embedding = SparseEmbedding {
+            indices: vec![7, 0, 3],
+            values: vec![0.25, 1.5, 0.0],
+        };
+        assert_eq!(embedding.to_sparsevec(10), "{1:1.5,8:0.25}/10");
+        assert_eq!(SparseEmbedding::default().to_sparsevec(10), "{}/10");
+
+        let embedding = SparseEmbedding {
+            indices: (0..1500).collect(),
+            values: (0..1500).map(|i| i as f64 + 1.0).collect(),
+        };
+        let sparsevec = embedding.to_sparsevec(30522);
+        assert_eq!(sparsevec.matches(':').count(), SPARSEVEC_MAX_NONZERO);
+        // the smallest weights are dropped
+        assert!(sparsevec.starts_with("{501:501,"));
+    }
+}
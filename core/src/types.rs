@@ -1,3 +1,5 @@
+use crate::chunking::ChunkConfig;
+
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::fmt;
@@ -88,6 +90,155 @@ impl From<String> for IndexDist {
     }
 }
 
+// pgvector type that a job's embeddings are stored as
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EmbeddingStorage {
+    #[default]
+    vector,
+    // half precision floats, half the size of vector
+    halfvec,
+    // stored as vector and indexed by its binary quantization
+    // candidates found with the index are re-ranked on the full vector
+    bit,
+    // only the non-zero elements are stored
+    sparsevec,
+}
+
+impl EmbeddingStorage {
+    // type of the embeddings column
+    pub fn pg_type(&self) -> &'static str {
+        match self {
+            EmbeddingStorage::vector | EmbeddingStorage::bit => "vector",
+            EmbeddingStorage::halfvec => "halfvec",
+            EmbeddingStorage::sparsevec => "sparsevec",
+        }
+    }
+
+    pub fn column_type(&self, dim: u32) -> String {
+        format!("{}({dim})", self.pg_type())
+    }
+
+    // casts an embedding, as a float array or the text of a vector, to the type of the embeddings column
+    pub fn cast(&self, expr: &str) -> String {
+        match self.pg_type() {
+            "vector" => format!("{expr}::vector"),
+            pg_type => format!("{expr}::vector::{pg_type}"),
+        }
+    }
+}
+
+impl Display for EmbeddingStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            EmbeddingStorage::vector => write!(f, "vector"),
+            EmbeddingStorage::halfvec => write!(f, "halfvec"),
+            EmbeddingStorage::bit => write!(f, "bit"),
+            EmbeddingStorage::sparsevec => write!(f, "sparsevec"),
+        }
+    }
+}
+
+impl FromStr for EmbeddingStorage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vector" => Ok(EmbeddingStorage::vector),
+            "halfvec" => Ok(EmbeddingStorage::halfvec),
+            "bit" => Ok(EmbeddingStorage::bit),
+            "sparsevec" => Ok(EmbeddingStorage::sparsevec),
+            _ => Err(format!("Invalid value for EmbeddingStorage: {}", s)),
+        }
+    }
+}
+
+// how documents are split into chunks, see chunking::chunk_document
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChunkStrategy {
+    // chunks of at most max_size characters, split at paragraphs, lines, sentences and words
+    #[default]
+    characters,
+    // as characters, but sized in cl100k_base tokens
+    tokens,
+    // also splits at markdown headings, from the top level down
+    markdown,
+    // splits source code of the job's language at the nodes of its syntax tree
+    code,
+    // groups sentences until the similarity of the embeddings of adjacent sentences drops, see chunking::semantic_chunks
+    semantic,
+}
+
+impl Display for ChunkStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ChunkStrategy::characters => write!(f, "characters"),
+            ChunkStrategy::tokens => write!(f, "tokens"),
+            ChunkStrategy::markdown => write!(f, "markdown"),
+            ChunkStrategy::code => write!(f, "code"),
+            ChunkStrategy::semantic => write!(f, "semantic"),
+        }
+    }
+}
+
+impl FromStr for ChunkStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "characters" => Ok(ChunkStrategy::characters),
+            "tokens" => Ok(ChunkStrategy::tokens),
+            "markdown" => Ok(ChunkStrategy::markdown),
+            "code" => Ok(ChunkStrategy::code),
+            "semantic" => Ok(ChunkStrategy::semantic),
+            _ => Err(format!("Invalid value for ChunkStrategy: {}", s)),
+        }
+    }
+}
+
+// how search scores a row of a chunked job from the similarity scores of its matching chunks
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChunkAggregation {
+    // the score of the best matching chunk
+    #[default]
+    max,
+    // the sum of the scores of the matching chunks, favoring rows that match in many places
+    sum,
+}
+
+impl ChunkAggregation {
+    // aggregate function over the similarity scores of a row's chunks
+    pub fn sql_function(&self) -> &'static str {
+        match self {
+            ChunkAggregation::max => "MAX",
+            ChunkAggregation::sum => "SUM",
+        }
+    }
+}
+
+impl Display for ChunkAggregation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ChunkAggregation::max => write!(f, "max"),
+            ChunkAggregation::sum => write!(f, "sum"),
+        }
+    }
+}
+
+impl FromStr for ChunkAggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(ChunkAggregation::max),
+            "sum" => Ok(ChunkAggregation::sum),
+            _ => Err(format!("Invalid value for ChunkAggregation: {}", s)),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TableMethod {
@@ -112,17 +263,173 @@ pub struct JobParams {
     #[serde(default = "default_schedule")]
     pub schedule: String,
     pub args: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diskann_search: Option<DiskAnnSearchParams>,
+    // paused jobs do not enqueue new embedding work
+    #[serde(default)]
+    pub paused: bool,
+    // when the job was paused, so that resuming it only enqueues the rows updated since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_migration: Option<ModelMigration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_prefixes: Option<InputPrefixes>,
+    // size of the job's embeddings, when smaller than the transformer's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    #[serde(default)]
+    pub storage: EmbeddingStorage,
+    // sparse jobs store the term weights of a learned sparse model, e.g. SPLADE, as a sparsevec
+    // their dimensions are the size of the model's vocabulary
+    #[serde(default)]
+    pub sparse: bool,
+    // chunked jobs embed every chunk of a row's text as its own row of the embeddings table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkConfig>,
+    #[serde(default)]
+    pub chunk_aggregation: ChunkAggregation,
+}
+
+impl JobParams {
+    // casts an embedding to the type of the job's embeddings column
+    // sparse embeddings are bound as the text of a sparsevec, dense embeddings as a float array
+    pub fn embedding_cast(&self, expr: &str) -> String {
+        if self.sparse {
+            format!("{expr}::sparsevec")
+        } else {
+            self.storage.cast(expr)
+        }
+    }
 }
 
 fn default_schedule() -> String {
     "realtime".to_string()
 }
 
+// an in-progress change of a job's transformer
+// embeddings from the new transformer are written to the job's shadow table until cutover
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ModelMigration {
+    #[serde(
+        deserialize_with = "string_to_model",
+        serialize_with = "model_to_string"
+    )]
+    pub transformer: Model,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+// the shadow table is named as the embeddings table of this pseudo-job,
+// i.e. vectorize._embeddings_{job_name}_shadow
+pub fn shadow_job_name(job_name: &str) -> String {
+    format!("{job_name}_shadow")
+}
+
+// query-time parameters for pgvectorscale's diskann index
+// unset values fall back to the pgvectorscale defaults
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiskAnnSearchParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_list_size: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_rescore: Option<i32>,
+}
+
+impl DiskAnnSearchParams {
+    pub fn is_empty(&self) -> bool {
+        self.search_list_size.is_none() && self.query_rescore.is_none()
+    }
+
+    // values set on self take precedence over values set on fallback
+    pub fn or(&self, fallback: &DiskAnnSearchParams) -> DiskAnnSearchParams {
+        DiskAnnSearchParams {
+            search_list_size: self.search_list_size.or(fallback.search_list_size),
+            query_rescore: self.query_rescore.or(fallback.query_rescore),
+        }
+    }
+
+    // the pgvectorscale GUCs to set for the current transaction
+    pub fn gucs(&self) -> Vec<(&'static str, i32)> {
+        let mut gucs = Vec::new();
+        if let Some(size) = self.search_list_size {
+            gucs.push(("diskann.query_search_list_size", size));
+        }
+        if let Some(rescore) = self.query_rescore {
+            gucs.push(("diskann.query_rescore", rescore));
+        }
+        gucs
+    }
+}
+
+// the kind of text being embedded
+// models trained for asymmetric retrieval embed queries differently from the documents they search
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    #[default]
+    Document,
+    Query,
+}
+
+impl InputType {
+    // the model name that the embeddings of this input type are stored under in vectorize.embedding_cache
+    // reduced-dimension embeddings are stored under `<model>@<dimensions>`
+    pub fn cache_key(&self, model: &Model, dimensions: Option<u32>) -> String {
+        let model_key = match dimensions {
+            Some(dim) => format!("{}@{dim}", model.fullname),
+            None => model.fullname.clone(),
+        };
+        match self {
+            InputType::Document => model_key,
+            InputType::Query => format!("{model_key}#query"),
+        }
+    }
+}
+
+// text prepended to inputs before they are embedded, for models that expect
+// instructions such as `query: ` and `passage: ` instead of an input type
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InputPrefixes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<String>,
+}
+
+impl InputPrefixes {
+    pub fn is_empty(&self) -> bool {
+        self.query.is_none() && self.document.is_none()
+    }
+
+    // values set on self take precedence over values set on fallback
+    pub fn or(&self, fallback: &InputPrefixes) -> InputPrefixes {
+        InputPrefixes {
+            query: self.query.clone().or(fallback.query.clone()),
+            document: self.document.clone().or(fallback.document.clone()),
+        }
+    }
+
+    pub fn apply(&self, input_type: InputType, input: &str) -> String {
+        let prefix = match input_type {
+            InputType::Document => &self.document,
+            InputType::Query => &self.query,
+        };
+        match prefix {
+            Some(prefix) => format!("{prefix}{input}"),
+            None => input.to_string(),
+        }
+    }
+}
+
 // schema for all messages that hit pgmq
 #[derive(Clone, Deserialize, Debug, Serialize)]
 pub struct JobMessage {
     pub job_name: String,
     pub record_ids: Vec<String>,
+    // backfill for the shadow table of a model migration
+    #[serde(default)]
+    pub shadow: bool,
 }
 
 // schema for every job
@@ -140,7 +447,7 @@ pub struct VectorizeMeta {
     pub params: serde_json::Value,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Model {
     pub source: ModelSource,
     // the model's namespace + model name
@@ -178,6 +485,25 @@ impl Model {
             ModelSource::Cohere => self.name.clone(),
             ModelSource::Portkey => self.name.clone(),
             ModelSource::Voyage => self.name.clone(),
+            ModelSource::Azure => self.name.clone(),
+            ModelSource::Gemini => self.name.clone(),
+            ModelSource::Tei => self.name.clone(),
+            ModelSource::Local => self.name.clone(),
+        }
+    }
+}
+
+impl Model {
+    // the name of the vectorize.model_providers entry that may serve the model
+    // e.g. `myprovider` for `myprovider/some-model`
+    // models with a built-in source are never served by a registered provider
+    pub fn custom_provider_name(&self) -> Option<String> {
+        if self.source != ModelSource::SentenceTransformers {
+            return None;
+        }
+        match self.fullname.split_once('/') {
+            Some((prefix, _)) if prefix != "sentence-transformers" => Some(prefix.to_lowercase()),
+            _ => None,
         }
     }
 }
@@ -256,6 +582,10 @@ pub enum ModelSource {
     Cohere,
     Portkey,
     Voyage,
+    Azure,
+    Gemini,
+    Tei,
+    Local,
 }
 
 impl FromStr for ModelSource {
@@ -270,6 +600,10 @@ impl FromStr for ModelSource {
             "cohere" => Ok(ModelSource::Cohere),
             "portkey" => Ok(ModelSource::Portkey),
             "voyage" => Ok(ModelSource::Voyage),
+            "azure" => Ok(ModelSource::Azure),
+            "gemini" => Ok(ModelSource::Gemini),
+            "tei" => Ok(ModelSource::Tei),
+            "local" => Ok(ModelSource::Local),
             _ => Ok(ModelSource::SentenceTransformers),
         }
     }
@@ -285,6 +619,10 @@ impl Display for ModelSource {
             ModelSource::Cohere => write!(f, "cohere"),
             ModelSource::Portkey => write!(f, "portkey"),
             ModelSource::Voyage => write!(f, "voyage"),
+            ModelSource::Azure => write!(f, "azure"),
+            ModelSource::Gemini => write!(f, "gemini"),
+            ModelSource::Tei => write!(f, "tei"),
+            ModelSource::Local => write!(f, "local"),
         }
     }
 }
@@ -299,6 +637,10 @@ impl From<String> for ModelSource {
             "cohere" => ModelSource::Cohere,
             "portkey" => ModelSource::Portkey,
             "voyage" => ModelSource::Voyage,
+            "azure" => ModelSource::Azure,
+            "gemini" => ModelSource::Gemini,
+            "tei" => ModelSource::Tei,
+            "local" => ModelSource::Local,
             // other cases are assumed to be private sentence-transformer compatible model
             // and can be hot-loaded
             _ => ModelSource::SentenceTransformers,
@@ -329,6 +671,104 @@ mod model_tests {
         assert_eq!(model.api_name(), "voyage-3-lite");
     }
 
+    #[test]
+    fn test_azure_parsing() {
+        let model = Model::new("azure/my-embeddings").unwrap();
+        assert_eq!(model.source, ModelSource::Azure);
+        assert_eq!(model.fullname, "azure/my-embeddings");
+        assert_eq!(model.name, "my-embeddings");
+        assert_eq!(model.api_name(), "my-embeddings");
+        assert_eq!(model.custom_provider_name(), None);
+    }
+
+    #[test]
+    fn test_gemini_parsing() {
+        let model = Model::new("gemini/text-embedding-004").unwrap();
+        assert_eq!(model.source, ModelSource::Gemini);
+        assert_eq!(model.fullname, "gemini/text-embedding-004");
+        assert_eq!(model.name, "text-embedding-004");
+        assert_eq!(model.api_name(), "text-embedding-004");
+    }
+
+    #[test]
+    fn test_tei_parsing() {
+        let model = Model::new("tei/BAAI/bge-small-en-v1.5").unwrap();
+        assert_eq!(model.source, ModelSource::Tei);
+        assert_eq!(model.fullname, "tei/BAAI/bge-small-en-v1.5");
+        assert_eq!(model.name, "bge-small-en-v1.5");
+        assert_eq!(model.custom_provider_name(), None);
+    }
+
+    #[test]
+    fn test_input_prefixes() {
+        let prefixes = InputPrefixes {
+            query: Some("query: ".to_string()),
+            document: None,
+        };
+        assert_eq!(
+            prefixes.apply(InputType::Query, "red apples"),
+            "query: red apples"
+        );
+        assert_eq!(
+            prefixes.apply(InputType::Document, "red apples"),
+            "red apples"
+        );
+
+        let merged = InputPrefixes {
+            query: None,
+            document: Some("passage: ".to_string()),
+        }
+        .or(&prefixes);
+        assert_eq!(merged.query.as_deref(), Some("query: "));
+        assert_eq!(merged.document.as_deref(), Some("passage: "));
+    }
+
+    #[test]
+    fn test_input_type_cache_key() {
+        let model = Model::new("cohere/embed-english-v3.0").unwrap();
+        assert_eq!(
+            InputType::Document.cache_key(&model, None),
+            "cohere/embed-english-v3.0"
+        );
+        assert_eq!(
+            InputType::Query.cache_key(&model, None),
+            "cohere/embed-english-v3.0#query"
+        );
+        assert_eq!(
+            InputType::Query.cache_key(&model, Some(256)),
+            "cohere/embed-english-v3.0@256#query"
+        );
+    }
+
+    #[test]
+    fn test_embedding_storage() {
+        assert_eq!(EmbeddingStorage::vector.column_type(384), "vector(384)");
+        assert_eq!(EmbeddingStorage::bit.column_type(384), "vector(384)");
+        assert_eq!(EmbeddingStorage::halfvec.cast("$1"), "$1::vector::halfvec");
+        assert_eq!(EmbeddingStorage::bit.cast("$1"), "$1::vector");
+        // jobs created before storage was configurable store vectors
+        let params: JobParams = serde_json::from_value(serde_json::json!({
+            "schema": "public",
+            "relation": "products",
+            "columns": ["description"],
+            "update_time_col": null,
+            "table_method": "join",
+            "primary_key": "product_id",
+            "pkey_type": "integer",
+            "args": null
+        }))
+        .unwrap();
+        assert_eq!(params.storage, EmbeddingStorage::vector);
+    }
+
+    #[test]
+    fn test_local_parsing() {
+        let model = Model::new("local/all-MiniLM-L6-v2").unwrap();
+        assert_eq!(model.source, ModelSource::Local);
+        assert_eq!(model.fullname, "local/all-MiniLM-L6-v2");
+        assert_eq!(model.api_name(), "all-MiniLM-L6-v2");
+    }
+
     #[test]
     fn test_tembo_parsing() {
         let model = Model::new("tembo/meta-llama/Meta-Llama-3-8B-Instruct").unwrap();
@@ -345,6 +785,17 @@ mod model_tests {
         assert_eq!(model.name, "wizardlm2:7b");
     }
 
+    #[test]
+    fn test_custom_provider_name() {
+        let model = Model::new("myprovider/some-model").unwrap();
+        assert_eq!(model.source, ModelSource::SentenceTransformers);
+        assert_eq!(model.custom_provider_name(), Some("myprovider".to_string()));
+        let model = Model::new("sentence-transformers/all-MiniLM-L6-v2").unwrap();
+        assert_eq!(model.custom_provider_name(), None);
+        let model = Model::new("openai/text-embedding-3-small").unwrap();
+        assert_eq!(model.custom_provider_name(), None);
+    }
+
     #[test]
     fn test_legacy_fullname() {
         let model = Model::new("text-embedding-ada-002").unwrap();
@@ -398,6 +849,74 @@ mod model_tests {
         assert_eq!(model.name, "text-embedding-ada-002");
     }
 
+    #[test]
+    fn test_diskann_search_params_precedence() {
+        let per_job = DiskAnnSearchParams {
+            search_list_size: Some(200),
+            query_rescore: Some(50),
+        };
+        let per_call = DiskAnnSearchParams {
+            search_list_size: None,
+            query_rescore: Some(400),
+        };
+        let merged = per_call.or(&per_job);
+        assert_eq!(merged.search_list_size, Some(200));
+        assert_eq!(merged.query_rescore, Some(400));
+        assert_eq!(
+            merged.gucs(),
+            vec![
+                ("diskann.query_search_list_size", 200),
+                ("diskann.query_rescore", 400)
+            ]
+        );
+        assert!(DiskAnnSearchParams::default().is_empty());
+        assert!(DiskAnnSearchParams::default().gucs().is_empty());
+    }
+
+    #[test]
+    fn test_job_params_without_diskann_search() {
+        // jobs created before diskann_search existed must still deserialize
+        let params = serde_json::json!({
+            "schema": "public",
+            "relation": "products",
+            "columns": ["product_name"],
+            "update_time_col": null,
+            "table_method": "join",
+            "primary_key": "product_id",
+            "pkey_type": "integer",
+            "api_key": null,
+            "schedule": "realtime",
+            "args": null
+        });
+        let job_params: JobParams = serde_json::from_value(params).unwrap();
+        assert!(job_params.diskann_search.is_none());
+        assert!(!job_params.paused);
+        assert!(job_params.model_migration.is_none());
+        assert!(job_params.chunking.is_none());
+        assert_eq!(job_params.chunk_aggregation, ChunkAggregation::max);
+    }
+
+    #[test]
+    fn test_model_migration_serde() {
+        let migration = ModelMigration {
+            transformer: Model::new("openai/text-embedding-3-small").unwrap(),
+            api_key: None,
+        };
+        let value = serde_json::to_value(&migration).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"transformer": "openai/text-embedding-3-small"})
+        );
+        let roundtrip: ModelMigration = serde_json::from_value(value).unwrap();
+        assert_eq!(roundtrip, migration);
+
+        // messages enqueued before shadow existed
+        let msg: JobMessage =
+            serde_json::from_value(serde_json::json!({"job_name": "j", "record_ids": ["1"]}))
+                .unwrap();
+        assert!(!msg.shadow);
+    }
+
     #[test]
     fn test_private_hf_sentence_transformer() {
         let model = Model::new("chuckhend/private-model").unwrap();
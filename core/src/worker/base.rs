@@ -1,15 +1,21 @@
-use crate::errors::DatabaseError;
+use crate::chunking::{self, Chunk, ChunkConfig};
+use crate::errors::{DatabaseError, VectorizeError};
 use crate::guc;
-use crate::transformers::types::Inputs;
+use crate::transformers::types::{
+    Inputs, PairedChunkEmbeddings, PairedEmbeddings, PairedSparseEmbeddings,
+};
 use crate::transformers::{http_handler, providers};
-use crate::types::{JobMessage, JobParams};
+use crate::types::{
+    shadow_job_name, ChunkStrategy, InputType, JobMessage, JobParams, Model, TableMethod,
+};
 use crate::worker::ops;
 
-use log::error;
+use log::{error, info};
 use pgmq::{Message, PGMQueueExt};
 use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
 use std::env;
-use tiktoken_rs::cl100k_base;
+use tiktoken_rs::{cl100k_base, CoreBPE};
 
 use crate::types::VectorizeMeta;
 
@@ -57,19 +63,72 @@ pub async fn poll_job(
     let read_ct: i32 = msg.read_ct;
     let msg_id: i64 = msg.msg_id;
     if read_ct <= config.max_retries {
-        execute_job(conn, msg).await?;
+        if let Err(e) = execute_job(conn, msg.clone()).await {
+            if read_ct < config.max_retries && is_retryable(&e) {
+                // message becomes visible again and is retried
+                return Err(e);
+            }
+            error!(
+                "message failed and will not be retried, moving msg_id: {} to dead letter: {:?}",
+                msg_id, e
+            );
+            dead_letter(conn, &config.queue_name, &msg, Some(&e)).await?;
+            finalize_if_backfilled(conn, &config.queue_name, &msg, false).await?;
+            return Ok(Some(()));
+        }
     } else {
+        // the previous attempt did not complete, e.g. the worker was restarted
         error!(
-            "message exceeds max retry of {}, archiving msg_id: {}",
+            "message exceeds max retry of {}, moving msg_id: {} to dead letter",
             config.max_retries, msg_id
         );
+        dead_letter(conn, &config.queue_name, &msg, None).await?;
+        finalize_if_backfilled(conn, &config.queue_name, &msg, false).await?;
+        return Ok(Some(()));
     }
 
     queue.archive(&config.queue_name, msg_id).await?;
+    finalize_if_backfilled(conn, &config.queue_name, &msg, true).await?;
 
     Ok(Some(()))
 }
 
+/// whether a failed job could succeed on a later attempt
+/// errors such as an invalid API key fail the same way on every retry
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<VectorizeError>())
+        .is_none_or(|e| e.is_retryable())
+}
+
+/// records a message that exhausted its retries in vectorize.dead_letter, and removes it from the queue
+pub async fn dead_letter(
+    conn: &Pool<Postgres>,
+    queue_name: &str,
+    msg: &Message<JobMessage>,
+    error: Option<&anyhow::Error>,
+) -> Result<()> {
+    let provider = get_vectorize_meta(&msg.message.job_name, conn)
+        .await
+        .ok()
+        .map(|meta| meta.transformer.source.to_string());
+    let http_status = error.and_then(|e| {
+        e.chain()
+            .find_map(|cause| cause.downcast_ref::<VectorizeError>())
+            .and_then(|e| e.http_status())
+    });
+    ops::insert_dead_letter(
+        conn,
+        queue_name,
+        msg,
+        error.map(|e| format!("{e:#}")),
+        provider,
+        http_status,
+    )
+    .await
+}
+
 pub struct Config {
     pub database_url: String,
     pub queue_name: String,
@@ -138,42 +197,22 @@ pub async fn get_vectorize_meta(
 /// processes a single job from the queue
 pub async fn execute_job(dbclient: &Pool<Postgres>, msg: Message<JobMessage>) -> Result<()> {
     let job_meta = get_vectorize_meta(&msg.message.job_name, dbclient).await?;
-    let mut job_params: JobParams = serde_json::from_value(job_meta.params.clone())?;
+    let job_params: JobParams = serde_json::from_value(job_meta.params.clone())?;
     let bpe = cl100k_base().unwrap();
 
-    let guc_configs = guc::get_guc_configs(&job_meta.transformer.source, dbclient).await;
-    // if api_key found in GUC, then use that and re-assign
-    if let Some(k) = guc_configs.api_key {
-        job_params.api_key = Some(k);
+    if msg.message.shadow && job_params.model_migration.is_none() {
+        // migration was completed or abandoned after this message was enqueued
+        return Ok(());
     }
 
-    let provider = providers::get_provider(
-        &job_meta.transformer.source,
-        job_params.api_key.clone(),
-        guc_configs.service_url,
-        guc_configs.virtual_key,
-    )?;
-
-    let cols = collapse_to_csv(&job_params.columns);
-
-    let job_records_query = format!(
-        "
-    SELECT
-        {primary_key}::text as record_id,
-        {cols} as input_text
-    FROM {schema}.{relation}
-    WHERE {primary_key} = ANY ($1::{pk_type}[])",
-        primary_key = job_params.primary_key,
-        cols = cols,
-        schema = job_params.schema,
-        relation = job_params.relation,
-        pk_type = job_params.pkey_type
-    );
+    let job_records_query = job_records_query(&job_meta.name, &job_params);
 
     #[derive(sqlx::FromRow)]
     struct Res {
         record_id: String,
         input_text: String,
+        content_hash: String,
+        existing_hash: Option<String>,
     }
 
     let job_records: Vec<Res> = sqlx::query_as(&job_records_query)
@@ -181,6 +220,25 @@ pub async fn execute_job(dbclient: &Pool<Postgres>, msg: Message<JobMessage>) ->
         .fetch_all(dbclient)
         .await?;
 
+    // rows whose input text is unchanged since they were last embedded are not sent to the provider
+    // shadow table backfills always embed, since the hashes belong to the current embeddings
+    let (job_records, unchanged): (Vec<Res>, Vec<Res>) = job_records.into_iter().partition(|row| {
+        msg.message.shadow || row.existing_hash.as_ref() != Some(&row.content_hash)
+    });
+    if !unchanged.is_empty() {
+        let record_ids: Vec<String> = unchanged.into_iter().map(|row| row.record_id).collect();
+        ops::touch_embeddings(dbclient, &job_meta.name, &job_params, &record_ids).await?;
+        ops::record_skipped_rows(dbclient, &job_meta.name, record_ids.len() as i64).await?;
+    }
+    if job_records.is_empty() && !msg.message.shadow {
+        return Ok(());
+    }
+
+    let content_hashes: HashMap<String, String> = job_records
+        .iter()
+        .map(|row| (row.record_id.clone(), row.content_hash.clone()))
+        .collect();
+
     let inputs: Vec<Inputs> = job_records
         .iter()
         .map(|row| {
@@ -193,29 +251,550 @@ pub async fn execute_job(dbclient: &Pool<Postgres>, msg: Message<JobMessage>) ->
         })
         .collect();
 
-    let embedding_request =
-        providers::prepare_generic_embedding_request(&job_meta.transformer, &inputs);
-
-    let embeddings = provider.generate_embedding(&embedding_request).await?;
+    // sparse jobs are join jobs, and their transformer can not be changed
+    if job_params.sparse {
+        let paired_embeddings = generate_sparse_embeddings(
+            dbclient,
+            &job_meta.name,
+            &job_meta.transformer,
+            job_params.api_key.clone(),
+            &job_params,
+            inputs,
+        )
+        .await?;
+        ops::upsert_sparse_embedding_table(
+            dbclient,
+            &job_meta.name,
+            &job_params,
+            paired_embeddings,
+            &content_hashes,
+        )
+        .await?;
+        return Ok(());
+    }
 
-    let paired_embeddings = http_handler::merge_input_output(inputs, embeddings.embeddings);
-    match job_params.clone().table_method {
-        crate::types::TableMethod::append => {
-            ops::update_embeddings(
+    // chunked jobs are join jobs, whose embeddings tables hold a row for each chunk of a record
+    if job_params.chunking.is_some() {
+        if !msg.message.shadow {
+            let chunks = chunk_job_inputs(
+                dbclient,
+                &job_meta.name,
+                &job_meta.transformer,
+                job_params.api_key.clone(),
+                &job_params,
+                &inputs,
+                &bpe,
+            )
+            .await?;
+            let paired_embeddings = generate_chunk_embeddings(
+                dbclient,
+                &job_meta.name,
+                &job_meta.transformer,
+                job_params.api_key.clone(),
+                &job_params,
+                &chunks,
+            )
+            .await?;
+            ops::upsert_chunk_embedding_table(
+                dbclient,
+                &job_meta.name,
+                &job_params,
+                paired_embeddings,
+                &content_hashes,
+            )
+            .await?;
+        }
+        if let Some(migration) = &job_params.model_migration {
+            let chunks = chunk_job_inputs(
+                dbclient,
+                &job_meta.name,
+                &migration.transformer,
+                migration.api_key.clone(),
+                &job_params,
+                &inputs,
+                &bpe,
+            )
+            .await?;
+            let paired_embeddings = generate_chunk_embeddings(
                 dbclient,
-                &job_params.schema,
-                &job_params.relation,
-                &job_meta.clone().name,
-                &job_params.primary_key,
-                &job_params.pkey_type,
+                &job_meta.name,
+                &migration.transformer,
+                migration.api_key.clone(),
+                &job_params,
+                &chunks,
+            )
+            .await?;
+            ops::upsert_chunk_embedding_table(
+                dbclient,
+                &shadow_job_name(&job_meta.name),
+                &job_params,
                 paired_embeddings,
+                &content_hashes,
             )
             .await?;
         }
-        crate::types::TableMethod::join => {
-            ops::upsert_embedding_table(dbclient, &job_meta.name, &job_params, paired_embeddings)
+        return Ok(());
+    }
+
+    if !msg.message.shadow {
+        let paired_embeddings = generate_embeddings(
+            dbclient,
+            &job_meta.name,
+            &job_meta.transformer,
+            job_params.api_key.clone(),
+            &job_params,
+            inputs.clone(),
+        )
+        .await?;
+        match job_params.clone().table_method {
+            crate::types::TableMethod::append => {
+                ops::update_embeddings(
+                    dbclient,
+                    &job_params.schema,
+                    &job_params.relation,
+                    &job_meta.clone().name,
+                    &job_params.primary_key,
+                    &job_params.pkey_type,
+                    &job_params.storage,
+                    paired_embeddings,
+                    &content_hashes,
+                )
+                .await?;
+            }
+            crate::types::TableMethod::join => {
+                ops::upsert_embedding_table(
+                    dbclient,
+                    &job_meta.name,
+                    &job_params,
+                    paired_embeddings,
+                    &content_hashes,
+                )
                 .await?
+            }
         }
     }
+
+    // during a model migration, changes are also written to the shadow table
+    if let Some(migration) = &job_params.model_migration {
+        let paired_embeddings = generate_embeddings(
+            dbclient,
+            &job_meta.name,
+            &migration.transformer,
+            migration.api_key.clone(),
+            &job_params,
+            inputs,
+        )
+        .await?;
+        ops::upsert_embedding_table(
+            dbclient,
+            &shadow_job_name(&job_meta.name),
+            &job_params,
+            paired_embeddings,
+            &content_hashes,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// swaps in the shadow table of a model migration once its last backfill message has left the queue
+/// called after the message is archived, deleted or dead-lettered, so that when several workers finish
+/// the last messages at once, the worker that removes the last one always finds none pending
+/// missing rows are only enqueued again after a processed message, since a dead-lettered row would
+/// fail again, and is replayed with vectorize.replay_dead_letters() or dropped with vectorize.abort_model_change()
+pub async fn finalize_if_backfilled(
+    dbclient: &Pool<Postgres>,
+    queue_name: &str,
+    msg: &Message<JobMessage>,
+    processed: bool,
+) -> Result<()> {
+    let job_name = &msg.message.job_name;
+    if msg.message.shadow && !ops::shadow_backfill_pending(dbclient, queue_name, job_name).await? {
+        ops::finalize_model_change(dbclient, job_name, processed).await?;
+    }
     Ok(())
 }
+
+// a chunk of the text of one of a job's records
+struct RecordChunk {
+    record_id: String,
+    chunk_index: i32,
+    chunk: String,
+    token_estimate: i32,
+}
+
+// splits the text of each record into chunks with the job's chunking strategy
+// the semantic strategy embeds the sentences of the records with the transformer, to find where to cut
+async fn chunk_job_inputs(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    job_params: &JobParams,
+    inputs: &[Inputs],
+    bpe: &CoreBPE,
+) -> Result<Vec<RecordChunk>> {
+    let config = job_params
+        .chunking
+        .as_ref()
+        .ok_or_else(|| anyhow!("job is not chunked: {job_name}"))?;
+    if config.strategy != ChunkStrategy::semantic {
+        return chunk_inputs(inputs, config, bpe);
+    }
+
+    // every sentence is embedded as its own input, in the order of the sentences of all records
+    let sentences: Vec<Vec<Chunk>> = inputs
+        .iter()
+        .map(|input| chunking::sentences(&input.inputs))
+        .collect();
+    let sentence_inputs: Vec<Inputs> = sentences
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(position, sentence)| Inputs {
+            record_id: position.to_string(),
+            inputs: sentence.text.clone(),
+            token_estimate: bpe.encode_with_special_tokens(&sentence.text).len() as i32,
+        })
+        .collect();
+    let mut embeddings =
+        generate_sentence_embeddings(dbclient, job_name, transformer, api_key, sentence_inputs)
+            .await?
+            .into_iter();
+
+    let mut chunks = Vec::new();
+    for (input, sentences) in inputs.iter().zip(&sentences) {
+        let sentence_embeddings: Vec<Vec<f64>> =
+            embeddings.by_ref().take(sentences.len()).collect();
+        let texts = chunking::semantic_chunks(
+            &input.inputs,
+            sentences,
+            &sentence_embeddings,
+            config.breakpoint_percentile,
+            Some(config.max_size),
+        )?
+        .into_iter()
+        .map(|c| c.text)
+        .collect();
+        chunks.extend(record_chunks(&input.record_id, texts, bpe));
+    }
+    Ok(chunks)
+}
+
+// embeds sentences to compare them, within the rate limits of the provider and the job
+// the embeddings are not stored, so they skip the cache, the job's input prefixes and its dimensions
+async fn generate_sentence_embeddings(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    inputs: Vec<Inputs>,
+) -> Result<Vec<Vec<f64>>> {
+    if inputs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let tokens: i32 = inputs.iter().map(|i| i.token_estimate).sum();
+    wait_for_rate_limit(dbclient, job_name, transformer, tokens).await?;
+
+    let guc_configs = guc::get_guc_configs(transformer, dbclient).await?;
+    let api_key = guc_configs.api_key.clone().or(api_key);
+    let provider = providers::get_model_provider(
+        &transformer.source,
+        api_key,
+        &guc_configs,
+        http_handler::RetryConfig::from_env(),
+    )?;
+
+    let embedding_request = providers::prepare_generic_embedding_request(
+        transformer,
+        &inputs,
+        InputType::Document,
+        None,
+    );
+    let response = provider.generate_embedding(&embedding_request).await?;
+    Ok(response.embeddings)
+}
+
+// splits the text of each record into chunks
+fn chunk_inputs(
+    inputs: &[Inputs],
+    config: &ChunkConfig,
+    bpe: &CoreBPE,
+) -> Result<Vec<RecordChunk>> {
+    let mut chunks = Vec::new();
+    for input in inputs {
+        let texts = chunking::chunk_document(&input.inputs, config)?
+            .into_iter()
+            .map(|c| c.text)
+            .collect();
+        chunks.extend(record_chunks(&input.record_id, texts, bpe));
+    }
+    Ok(chunks)
+}
+
+// a record without text is kept as a single empty chunk, so that it is not picked up again as missing
+fn record_chunks(record_id: &str, mut texts: Vec<String>, bpe: &CoreBPE) -> Vec<RecordChunk> {
+    if texts.is_empty() {
+        texts.push(String::new());
+    }
+    texts
+        .into_iter()
+        .enumerate()
+        .map(|(chunk_index, chunk)| RecordChunk {
+            record_id: record_id.to_string(),
+            chunk_index: chunk_index as i32,
+            token_estimate: bpe.encode_with_special_tokens(&chunk).len() as i32,
+            chunk,
+        })
+        .collect()
+}
+
+// embeds every chunk as its own input, identified by its position in chunks
+async fn generate_chunk_embeddings(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    job_params: &JobParams,
+    chunks: &[RecordChunk],
+) -> Result<Vec<PairedChunkEmbeddings>> {
+    let inputs: Vec<Inputs> = chunks
+        .iter()
+        .enumerate()
+        .map(|(position, chunk)| Inputs {
+            record_id: position.to_string(),
+            inputs: chunk.chunk.clone(),
+            token_estimate: chunk.token_estimate,
+        })
+        .collect();
+    let paired_embeddings =
+        generate_embeddings(dbclient, job_name, transformer, api_key, job_params, inputs).await?;
+    paired_embeddings
+        .into_iter()
+        .map(|pair| {
+            let chunk = pair
+                .primary_key
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| chunks.get(position))
+                .ok_or_else(|| anyhow!("embedding for unknown chunk: {}", pair.primary_key))?;
+            Ok(PairedChunkEmbeddings {
+                primary_key: chunk.record_id.clone(),
+                chunk_index: chunk.chunk_index,
+                chunk: chunk.chunk.clone(),
+                embeddings: pair.embeddings,
+            })
+        })
+        .collect()
+}
+
+// selects the input text of the job's records, along with a hash of the text
+// and the hash of the text that the current embeddings were generated from
+fn job_records_query(job_name: &str, job_params: &JobParams) -> String {
+    let cols = collapse_to_csv(&job_params.columns);
+    let existing_hash = match job_params.table_method {
+        TableMethod::append => format!("t0.{job_name}_content_hash"),
+        // every chunk of a record of a chunked job has the hash of the record's text
+        TableMethod::join => format!(
+            "(SELECT t1.content_hash FROM vectorize._embeddings_{job_name} t1 WHERE t1.{primary_key} = t0.{primary_key} LIMIT 1)",
+            primary_key = job_params.primary_key,
+        ),
+    };
+    // the document prefix is part of the hash, so changing it re-embeds every record
+    let hashed_text = match job_params
+        .input_prefixes
+        .as_ref()
+        .and_then(|p| p.document.as_ref())
+    {
+        Some(prefix) => format!("'{}' || {cols}", prefix.replace('\'', "''")),
+        None => cols.clone(),
+    };
+    format!(
+        "
+    SELECT
+        t0.{primary_key}::text as record_id,
+        {cols} as input_text,
+        md5({hashed_text}) as content_hash,
+        {existing_hash} as existing_hash
+    FROM {schema}.{relation} t0
+    WHERE t0.{primary_key} = ANY ($1::{pk_type}[])",
+        primary_key = job_params.primary_key,
+        schema = job_params.schema,
+        relation = job_params.relation,
+        pk_type = job_params.pkey_type
+    )
+}
+
+// embeds the inputs with the given transformer, within the rate limits of its provider and the job
+// inputs found in the embedding cache are not sent to the provider
+async fn generate_embeddings(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    job_params: &JobParams,
+    inputs: Vec<Inputs>,
+) -> Result<Vec<PairedEmbeddings>> {
+    let input_prefixes = job_params.input_prefixes.clone().unwrap_or_default();
+    let inputs: Vec<Inputs> = inputs
+        .into_iter()
+        .map(|i| Inputs {
+            inputs: input_prefixes.apply(InputType::Document, &i.inputs),
+            ..i
+        })
+        .collect();
+    let cache_key = InputType::Document.cache_key(transformer, job_params.dimensions);
+    let texts: Vec<String> = inputs.iter().map(|i| i.inputs.clone()).collect();
+    let mut cached = ops::get_cached_embeddings(dbclient, &cache_key, &texts).await?;
+    let (hits, misses): (Vec<_>, Vec<_>) = inputs
+        .into_iter()
+        .enumerate()
+        .partition(|(idx, _)| cached.contains_key(idx));
+    let mut paired_embeddings: Vec<PairedEmbeddings> = hits
+        .into_iter()
+        .map(|(idx, input)| PairedEmbeddings {
+            primary_key: input.record_id,
+            embeddings: cached.remove(&idx).expect("cached embedding"),
+        })
+        .collect();
+    let inputs: Vec<Inputs> = misses.into_iter().map(|(_, input)| input).collect();
+    if inputs.is_empty() {
+        return Ok(paired_embeddings);
+    }
+
+    let tokens: i32 = inputs.iter().map(|i| i.token_estimate).sum();
+    wait_for_rate_limit(dbclient, job_name, transformer, tokens).await?;
+
+    let guc_configs = guc::get_guc_configs(transformer, dbclient).await?;
+    // if api_key found in GUC, then use that and re-assign
+    let api_key = guc_configs.api_key.clone().or(api_key);
+
+    let provider = providers::get_model_provider(
+        &transformer.source,
+        api_key,
+        &guc_configs,
+        http_handler::RetryConfig::from_env(),
+    )?;
+
+    let embedding_request = providers::prepare_generic_embedding_request(
+        transformer,
+        &inputs,
+        InputType::Document,
+        job_params.dimensions,
+    );
+
+    let response = provider.generate_embedding(&embedding_request).await?;
+    let embeddings = providers::reduce_dimensions(response.embeddings, job_params.dimensions);
+    let texts: Vec<String> = inputs.iter().map(|i| i.inputs.clone()).collect();
+    ops::cache_embeddings(dbclient, &cache_key, &texts, &embeddings).await?;
+    paired_embeddings.extend(http_handler::merge_input_output(inputs, embeddings));
+    Ok(paired_embeddings)
+}
+
+// embeds the inputs with the learned sparse model of a sparse job, within the rate limits of its provider and the job
+// sparse embeddings are not cached
+async fn generate_sparse_embeddings(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    job_params: &JobParams,
+    inputs: Vec<Inputs>,
+) -> Result<Vec<PairedSparseEmbeddings>> {
+    let input_prefixes = job_params.input_prefixes.clone().unwrap_or_default();
+    let inputs: Vec<Inputs> = inputs
+        .into_iter()
+        .map(|i| Inputs {
+            inputs: input_prefixes.apply(InputType::Document, &i.inputs),
+            ..i
+        })
+        .collect();
+    if inputs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let tokens: i32 = inputs.iter().map(|i| i.token_estimate).sum();
+    wait_for_rate_limit(dbclient, job_name, transformer, tokens).await?;
+
+    let guc_configs = guc::get_guc_configs(transformer, dbclient).await?;
+    let api_key = guc_configs.api_key.clone().or(api_key);
+    let provider = providers::get_model_provider(
+        &transformer.source,
+        api_key,
+        &guc_configs,
+        http_handler::RetryConfig::from_env(),
+    )?;
+
+    let embedding_request = providers::prepare_generic_embedding_request(
+        transformer,
+        &inputs,
+        InputType::Document,
+        None,
+    );
+    let response = provider
+        .generate_sparse_embedding(&embedding_request)
+        .await?;
+    Ok(inputs
+        .into_iter()
+        .zip(response.embeddings)
+        .map(|(input, embeddings)| PairedSparseEmbeddings {
+            primary_key: input.record_id,
+            embeddings,
+        })
+        .collect())
+}
+
+// blocks until the provider of the transformer and the job have capacity for the tokens
+async fn wait_for_rate_limit(
+    dbclient: &Pool<Postgres>,
+    job_name: &str,
+    transformer: &Model,
+    tokens: i32,
+) -> Result<()> {
+    loop {
+        let wait =
+            ops::acquire_rate_limit(dbclient, &transformer.source.to_string(), job_name, tokens)
+                .await?;
+        if wait <= 0.0 {
+            return Ok(());
+        }
+        info!("rate limit reached for job: {job_name}, waiting {wait:.2}s");
+        tokio::time::sleep(std::time::Duration::from_secs_f64(wait)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_inputs() {
+        let inputs = vec![
+            Inputs {
+                record_id: "1".to_string(),
+                inputs: "first chunk second chunk".to_string(),
+                token_estimate: 4,
+            },
+            Inputs {
+                record_id: "2".to_string(),
+                inputs: "".to_string(),
+                token_estimate: 0,
+            },
+        ];
+        let config = ChunkConfig::new(ChunkStrategy::characters, 12, 0).unwrap();
+        let chunks = chunk_inputs(&inputs, &config, &cl100k_base().unwrap()).unwrap();
+        let chunks: Vec<(&str, i32, &str)> = chunks
+            .iter()
+            .map(|c| (c.record_id.as_str(), c.chunk_index, c.chunk.as_str()))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("1", 0, "first chunk"),
+                ("1", 1, "second chunk"),
+                ("2", 0, "")
+            ]
+        );
+    }
+}
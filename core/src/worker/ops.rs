@@ -1,8 +1,10 @@
-use crate::transformers::types::PairedEmbeddings;
-use crate::types;
+use crate::transformers::types::{PairedChunkEmbeddings, PairedEmbeddings, PairedSparseEmbeddings};
+use crate::types::{self, EmbeddingStorage, JobMessage};
 use anyhow::Result;
+use pgmq::Message;
 use serde_json::to_string;
 use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
 use std::fmt::Write;
 
 pub async fn upsert_embedding_table(
@@ -10,11 +12,48 @@ pub async fn upsert_embedding_table(
     project: &str,
     job_params: &types::JobParams,
     embeddings: Vec<PairedEmbeddings>,
+    content_hashes: &HashMap<String, String>,
 ) -> Result<()> {
-    let (query, bindings) = build_upsert_query(project, job_params, embeddings);
+    let embeddings = embeddings
+        .into_iter()
+        .map(|pair| {
+            let embedding =
+                serde_json::to_string(&pair.embeddings).expect("failed to serialize embedding");
+            (pair.primary_key, embedding)
+        })
+        .collect();
+    execute_upsert(conn, project, job_params, embeddings, content_hashes).await
+}
+
+// sparse embeddings are written as the text of a sparsevec of the job's dimensions
+pub async fn upsert_sparse_embedding_table(
+    conn: &Pool<Postgres>,
+    project: &str,
+    job_params: &types::JobParams,
+    embeddings: Vec<PairedSparseEmbeddings>,
+    content_hashes: &HashMap<String, String>,
+) -> Result<()> {
+    let dim = job_params
+        .dimensions
+        .ok_or_else(|| anyhow::anyhow!("sparse job {project} has no dimensions"))?;
+    let embeddings = embeddings
+        .into_iter()
+        .map(|pair| (pair.primary_key, pair.embeddings.to_sparsevec(dim)))
+        .collect();
+    execute_upsert(conn, project, job_params, embeddings, content_hashes).await
+}
+
+async fn execute_upsert(
+    conn: &Pool<Postgres>,
+    project: &str,
+    job_params: &types::JobParams,
+    embeddings: Vec<(String, String)>,
+    content_hashes: &HashMap<String, String>,
+) -> Result<()> {
+    let (query, bindings) = build_upsert_query(project, job_params, embeddings, content_hashes);
     let mut q = sqlx::query(&query);
-    for (record_id, embeddings) in bindings {
-        q = q.bind(record_id).bind(embeddings);
+    for (record_id, embeddings, content_hash) in bindings {
+        q = q.bind(record_id).bind(embeddings).bind(content_hash);
     }
     match q.execute(conn).await {
         Ok(_) => Ok(()),
@@ -23,12 +62,13 @@ pub async fn upsert_embedding_table(
 }
 
 // returns query and bindings
-// only compatible with pg-vector data types
+// only compatible with pg-vector data types, embeddings are the text of a pg-vector value
 fn build_upsert_query(
     project: &str,
     job_params: &types::JobParams,
-    embeddings: Vec<PairedEmbeddings>,
-) -> (String, Vec<(String, String)>) {
+    embeddings: Vec<(String, String)>,
+    content_hashes: &HashMap<String, String>,
+) -> (String, Vec<(String, String, Option<String>)>) {
     let join_key = &job_params.primary_key;
     let schema = match &job_params.table_method {
         types::TableMethod::append => job_params.schema.clone(),
@@ -36,36 +76,108 @@ fn build_upsert_query(
     };
     let mut query = format!(
         "
-        INSERT INTO {schema}._embeddings_{project} ({join_key}, embeddings) VALUES",
+        INSERT INTO {schema}._embeddings_{project} ({join_key}, embeddings, content_hash) VALUES",
         schema = schema,
         join_key = join_key,
     );
-    let mut bindings: Vec<(String, String)> = Vec::new();
+    let mut bindings: Vec<(String, String, Option<String>)> = Vec::new();
 
-    for (index, pair) in embeddings.into_iter().enumerate() {
+    for (index, (primary_key, embedding)) in embeddings.into_iter().enumerate() {
         if index > 0 {
             query.push(',');
         }
         query.push_str(&format!(
-            " (${}::{}, ${}::vector)",
-            2 * index + 1,
+            " (${}::{}, {}, ${})",
+            3 * index + 1,
             job_params.pkey_type,
-            2 * index + 2
+            job_params.embedding_cast(&format!("${}", 3 * index + 2)),
+            3 * index + 3
         ));
 
-        let embedding =
-            serde_json::to_string(&pair.embeddings).expect("failed to serialize embedding");
-        bindings.push((pair.primary_key, embedding));
+        let content_hash = content_hashes.get(&primary_key).cloned();
+        bindings.push((primary_key, embedding, content_hash));
     }
     let upsert = format!(
         " ON CONFLICT ({join_key})
-        DO UPDATE SET embeddings = EXCLUDED.embeddings, updated_at = NOW();",
+        DO UPDATE SET embeddings = EXCLUDED.embeddings, content_hash = EXCLUDED.content_hash, updated_at = NOW();",
         join_key = join_key
     );
     query.push_str(&upsert);
     (query, bindings)
 }
 
+// rows of chunk embeddings inserted per statement, within the limit on the number of bind parameters
+const CHUNK_INSERT_BATCH_SIZE: usize = 1000;
+
+// replaces the chunks of the records with their new chunk embeddings
+// a record's text can be split into fewer chunks than before, so its previous chunks are removed first
+pub async fn upsert_chunk_embedding_table(
+    conn: &Pool<Postgres>,
+    project: &str,
+    job_params: &types::JobParams,
+    embeddings: Vec<PairedChunkEmbeddings>,
+    content_hashes: &HashMap<String, String>,
+) -> Result<()> {
+    let record_ids: Vec<&String> = content_hashes.keys().collect();
+    let mut tx = conn.begin().await?;
+    sqlx::query(&format!(
+        "DELETE FROM vectorize._embeddings_{project} WHERE {pkey} = ANY ($1::{pkey_type}[]);",
+        pkey = job_params.primary_key,
+        pkey_type = job_params.pkey_type,
+    ))
+    .bind(record_ids)
+    .execute(&mut *tx)
+    .await?;
+
+    for batch in embeddings.chunks(CHUNK_INSERT_BATCH_SIZE) {
+        let query = build_chunk_insert_query(project, job_params, batch.len());
+        let mut q = sqlx::query(&query);
+        for pair in batch {
+            let embedding =
+                serde_json::to_string(&pair.embeddings).expect("failed to serialize embedding");
+            q = q
+                .bind(&pair.primary_key)
+                .bind(pair.chunk_index)
+                .bind(&pair.chunk)
+                .bind(embedding)
+                .bind(content_hashes.get(&pair.primary_key));
+        }
+        q.execute(&mut *tx)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to execute query: {}", e))?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// inserts rows of (primary key, chunk index, chunk, embedding, content hash) into a chunked job's embeddings table
+fn build_chunk_insert_query(
+    project: &str,
+    job_params: &types::JobParams,
+    num_rows: usize,
+) -> String {
+    let values = (0..num_rows)
+        .map(|row| {
+            let param = |col: usize| format!("${}", 5 * row + col);
+            format!(
+                "({}::{}, {}, {}, {}, {})",
+                param(1),
+                job_params.pkey_type,
+                param(2),
+                param(3),
+                job_params.embedding_cast(&param(4)),
+                param(5)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "INSERT INTO vectorize._embeddings_{project} ({pkey}, chunk_index, chunk, embeddings, content_hash) VALUES {values};",
+        pkey = job_params.primary_key,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn update_embeddings(
     pool: &Pool<Postgres>,
     schema: &str,
@@ -73,16 +185,41 @@ pub async fn update_embeddings(
     project: &str,
     pkey: &str,
     pkey_type: &str,
+    storage: &EmbeddingStorage,
     embeddings: Vec<PairedEmbeddings>,
+    content_hashes: &HashMap<String, String>,
 ) -> anyhow::Result<()> {
     if embeddings.len() > 10 {
-        bulk_update_embeddings(pool, schema, table, project, pkey, pkey_type, embeddings).await
+        bulk_update_embeddings(
+            pool,
+            schema,
+            table,
+            project,
+            pkey,
+            pkey_type,
+            storage,
+            embeddings,
+            content_hashes,
+        )
+        .await
     } else {
-        update_append_table(pool, embeddings, schema, table, project, pkey, pkey_type).await
+        update_append_table(
+            pool,
+            embeddings,
+            content_hashes,
+            schema,
+            table,
+            project,
+            pkey,
+            pkey_type,
+            storage,
+        )
+        .await
     }
 }
 
 // creates a temporary table, inserts all new values into the temporary table, and then performs an update by join
+#[allow(clippy::too_many_arguments)]
 async fn bulk_update_embeddings(
     pool: &Pool<Postgres>,
     schema: &str,
@@ -90,7 +227,9 @@ async fn bulk_update_embeddings(
     project: &str,
     pkey: &str,
     pkey_type: &str,
+    storage: &EmbeddingStorage,
     embeddings: Vec<PairedEmbeddings>,
+    content_hashes: &HashMap<String, String>,
 ) -> anyhow::Result<()> {
     let mut tx = pool.begin().await?;
 
@@ -99,50 +238,59 @@ async fn bulk_update_embeddings(
     let temp_table_query = format!(
         "CREATE TEMP TABLE IF NOT EXISTS {tmp_table} (
             pkey {pkey_type} PRIMARY KEY,
-            embeddings vector
+            embeddings vector,
+            content_hash TEXT
         ) ON COMMIT DROP;", // note, dropping on commit
     );
 
     sqlx::query(&temp_table_query).execute(&mut *tx).await?;
 
     // insert all new values into the temporary table
-    let mut insert_query = format!("INSERT INTO {tmp_table} (pkey, embeddings) VALUES ");
-    let mut params: Vec<(String, String)> = Vec::new();
+    let mut insert_query =
+        format!("INSERT INTO {tmp_table} (pkey, embeddings, content_hash) VALUES ");
+    let mut params: Vec<(String, String, Option<String>)> = Vec::new();
 
     for embed in &embeddings {
         let embedding_json = to_string(&embed.embeddings).expect("failed to serialize embedding");
-        params.push((embed.primary_key.to_string(), embedding_json));
+        let content_hash = content_hashes.get(&embed.primary_key).cloned();
+        params.push((embed.primary_key.to_string(), embedding_json, content_hash));
     }
 
     // Constructing query values part and collecting bind parameters
-    for (i, (_pkey, _embedding)) in params.iter().enumerate() {
+    for (i, _) in params.iter().enumerate() {
         if i > 0 {
             insert_query.push_str(", ");
         }
         write!(
             &mut insert_query,
-            "(${}::{}, ${}::vector)",
-            i * 2 + 1,
+            "(${}::{}, ${}::vector, ${})",
+            i * 3 + 1,
             pkey_type,
-            i * 2 + 2
+            i * 3 + 2,
+            i * 3 + 3
         )
         .expect("Failed to write to query string");
     }
 
     let mut insert_statement = sqlx::query(&insert_query);
 
-    for (pkey, embedding) in params {
-        insert_statement = insert_statement.bind(pkey).bind(embedding);
+    for (pkey, embedding, content_hash) in params {
+        insert_statement = insert_statement
+            .bind(pkey)
+            .bind(embedding)
+            .bind(content_hash);
     }
     // insert to the temp table
     insert_statement.execute(&mut *tx).await?;
 
     let update_query = format!(
         "UPDATE {schema}.{table} SET
-            {project}_embeddings = temp.embeddings,
+            {project}_embeddings = {embeddings},
+            {project}_content_hash = temp.content_hash,
             {project}_updated_at = (NOW())
         FROM {tmp_table} temp
-        WHERE {schema}.{table}.{pkey}::{pkey_type} = temp.pkey::{pkey_type};"
+        WHERE {schema}.{table}.{pkey}::{pkey_type} = temp.pkey::{pkey_type};",
+        embeddings = storage.cast("temp.embeddings"),
     );
 
     sqlx::query(&update_query).execute(&mut *tx).await?;
@@ -151,14 +299,17 @@ async fn bulk_update_embeddings(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn update_append_table(
     pool: &Pool<Postgres>,
     embeddings: Vec<PairedEmbeddings>,
+    content_hashes: &HashMap<String, String>,
     schema: &str,
     table: &str,
     project: &str,
     pkey: &str,
     pkey_type: &str,
+    storage: &EmbeddingStorage,
 ) -> anyhow::Result<()> {
     for embed in embeddings {
         // Serialize the Vec<f64> to a JSON string
@@ -168,15 +319,19 @@ async fn update_append_table(
             "
             UPDATE {schema}.{table}
             SET 
-                {project}_embeddings = $1::vector,
+                {project}_embeddings = {embeddings},
+                {project}_content_hash = $3,
                 {project}_updated_at = (NOW())
             WHERE {pkey} = $2::{pkey_type}
-        "
+        ",
+            embeddings = storage.cast("$1"),
         );
+        let content_hash = content_hashes.get(&embed.primary_key).cloned();
         // Prepare and execute the update statement for this pair within the transaction
         sqlx::query(&update_query)
             .bind(embedding)
             .bind(embed.primary_key)
+            .bind(content_hash)
             .execute(pool)
             .await?;
     }
@@ -188,3 +343,216 @@ pub async fn init_extension(pool: &Pool<Postgres>) -> anyhow::Result<()> {
     sqlx::query(query).execute(pool).await?;
     Ok(())
 }
+
+// whether the queue holds shadow table backfill messages for the job
+pub async fn shadow_backfill_pending(
+    pool: &Pool<Postgres>,
+    queue_name: &str,
+    job_name: &str,
+) -> anyhow::Result<bool> {
+    let query = format!(
+        "SELECT EXISTS (
+            SELECT 1 FROM pgmq.q_{queue_name}
+            WHERE message->>'job_name' = $1
+            AND (message->>'shadow')::boolean
+        );"
+    );
+    let pending: bool = sqlx::query_scalar(&query)
+        .bind(job_name)
+        .fetch_one(pool)
+        .await?;
+    Ok(pending)
+}
+
+// swaps the shadow table in when every row has embeddings from the new transformer
+// returns whether the cutover happened
+pub async fn finalize_model_change(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    enqueue_missing: bool,
+) -> anyhow::Result<bool> {
+    let done: bool = sqlx::query_scalar("SELECT vectorize.finalize_model_change($1, $2);")
+        .bind(job_name)
+        .bind(enqueue_missing)
+        .fetch_one(pool)
+        .await?;
+    Ok(done)
+}
+
+// moves a message that exhausted its retries from the queue to vectorize.dead_letter
+pub async fn insert_dead_letter(
+    pool: &Pool<Postgres>,
+    queue_name: &str,
+    msg: &Message<JobMessage>,
+    error: Option<String>,
+    provider: Option<String>,
+    http_status: Option<u16>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO vectorize.dead_letter
+            (msg_id, job_name, message, read_ct, enqueued_at, error, provider, http_status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8);",
+    )
+    .bind(msg.msg_id)
+    .bind(&msg.message.job_name)
+    .bind(serde_json::to_value(&msg.message)?)
+    .bind(msg.read_ct)
+    .bind(msg.enqueued_at)
+    .bind(error)
+    .bind(provider)
+    .bind(http_status.map(i32::from))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("SELECT pgmq.delete($1, $2);")
+        .bind(queue_name)
+        .bind(msg.msg_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// takes one request and the given tokens from the rate limits of the provider and the job
+// returns the seconds to wait before trying again, or 0 when the budget was taken
+pub async fn acquire_rate_limit(
+    pool: &Pool<Postgres>,
+    provider: &str,
+    job_name: &str,
+    tokens: i32,
+) -> anyhow::Result<f64> {
+    let wait: f64 = sqlx::query_scalar("SELECT vectorize._acquire_rate_limit($1, $2, 1, $3);")
+        .bind(provider)
+        .bind(job_name)
+        .bind(tokens)
+        .fetch_one(pool)
+        .await?;
+    Ok(wait)
+}
+
+// marks rows whose input text is unchanged as up to date, without re-embedding them
+// keeps scheduled jobs from picking the rows up again on their next run
+pub async fn touch_embeddings(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    job_params: &types::JobParams,
+    record_ids: &[String],
+) -> anyhow::Result<()> {
+    let query = match job_params.table_method {
+        types::TableMethod::append => format!(
+            "UPDATE {schema}.{table} SET {job_name}_updated_at = NOW()
+            WHERE {pkey} = ANY ($1::{pkey_type}[]);",
+            schema = job_params.schema,
+            table = job_params.relation,
+            pkey = job_params.primary_key,
+            pkey_type = job_params.pkey_type,
+        ),
+        types::TableMethod::join => format!(
+            "UPDATE vectorize._embeddings_{job_name} SET updated_at = NOW()
+            WHERE {pkey} = ANY ($1::{pkey_type}[]);",
+            pkey = job_params.primary_key,
+            pkey_type = job_params.pkey_type,
+        ),
+    };
+    sqlx::query(&query).bind(record_ids).execute(pool).await?;
+    Ok(())
+}
+
+// adds to the count of rows that were skipped because their input text did not change
+pub async fn record_skipped_rows(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    skipped_rows: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO vectorize.job_stats (job_name, skipped_rows) VALUES ($1, $2)
+        ON CONFLICT (job_name) DO UPDATE
+        SET skipped_rows = vectorize.job_stats.skipped_rows + EXCLUDED.skipped_rows;",
+    )
+    .bind(job_name)
+    .bind(skipped_rows)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// embeddings of the inputs found in vectorize.embedding_cache, keyed by their position in inputs
+// always empty when the cache is disabled
+pub async fn get_cached_embeddings(
+    pool: &Pool<Postgres>,
+    model: &str,
+    inputs: &[String],
+) -> anyhow::Result<HashMap<usize, Vec<f64>>> {
+    let rows: Vec<(i32, Vec<f64>)> =
+        sqlx::query_as("SELECT idx, embedding FROM vectorize._embedding_cache_get($1, $2);")
+            .bind(model)
+            .bind(inputs)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(idx, embedding)| (idx as usize - 1, embedding))
+        .collect())
+}
+
+// stores the embeddings of the inputs in vectorize.embedding_cache, when the cache is enabled
+pub async fn cache_embeddings(
+    pool: &Pool<Postgres>,
+    model: &str,
+    inputs: &[String],
+    embeddings: &[Vec<f64>],
+) -> anyhow::Result<()> {
+    sqlx::query("SELECT vectorize._embedding_cache_put($1, $2, $3);")
+        .bind(model)
+        .bind(inputs)
+        .bind(serde_json::to_value(embeddings)?)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert_query_casts_to_storage() {
+        let job_params = types::JobParams {
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            storage: EmbeddingStorage::halfvec,
+            ..Default::default()
+        };
+        let embeddings = vec![("1".to_string(), "[0.5,0.25]".to_string())];
+        let (query, bindings) =
+            build_upsert_query("myjob", &job_params, embeddings.clone(), &HashMap::new());
+        assert!(query.contains("INSERT INTO vectorize._embeddings_myjob"));
+        assert!(query.contains("($1::integer, $2::vector::halfvec, $3)"));
+        assert_eq!(bindings[0].1, "[0.5,0.25]");
+
+        // sparse embeddings are bound as the text of a sparsevec
+        let job_params = types::JobParams {
+            storage: EmbeddingStorage::sparsevec,
+            sparse: true,
+            ..job_params
+        };
+        let (query, _) = build_upsert_query("myjob", &job_params, embeddings, &HashMap::new());
+        assert!(query.contains("($1::integer, $2::sparsevec, $3)"));
+    }
+
+    #[test]
+    fn test_chunk_insert_query() {
+        let job_params = types::JobParams {
+            primary_key: "doc_id".to_string(),
+            pkey_type: "text".to_string(),
+            storage: EmbeddingStorage::halfvec,
+            ..Default::default()
+        };
+        let query = build_chunk_insert_query("myjob", &job_params, 2);
+        assert_eq!(
+            query,
+            "INSERT INTO vectorize._embeddings_myjob (doc_id, chunk_index, chunk, embeddings, content_hash) VALUES \
+            ($1::text, $2, $3, $4::vector::halfvec, $5), ($6::text, $7, $8, $9::vector::halfvec, $10);"
+        );
+    }
+}
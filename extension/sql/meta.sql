@@ -85,4 +85,356 @@ BEGIN
     ;
 
 END;
-$$ LANGUAGE plpgsql;
\ No newline at end of file
+$$ LANGUAGE plpgsql;
+
+--- called by the delete trigger on the source table of a join job
+--- removes the embeddings of the deleted rows, including from a model migration's shadow table
+CREATE OR REPLACE FUNCTION vectorize._handle_source_delete()
+RETURNS TRIGGER AS $$
+DECLARE
+    job_name TEXT := TG_ARGV[0];
+    pkey TEXT := TG_ARGV[1];
+    embeddings_table TEXT;
+BEGIN
+    FOREACH embeddings_table IN ARRAY ARRAY['_embeddings_' || job_name, '_embeddings_' || job_name || '_shadow'] LOOP
+        IF to_regclass(format('vectorize.%I', embeddings_table)) IS NOT NULL THEN
+            EXECUTE format(
+                'DELETE FROM vectorize.%I e USING old_table d WHERE e.%I = d.%I',
+                embeddings_table, pkey, pkey
+            );
+        END IF;
+    END LOOP;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+--- called by the truncate trigger on the source table of a join job
+--- rows reloaded into the source table are embedded again by the job's schedule
+CREATE OR REPLACE FUNCTION vectorize._handle_source_truncate()
+RETURNS TRIGGER AS $$
+DECLARE
+    job_name TEXT := TG_ARGV[0];
+    embeddings_table TEXT;
+BEGIN
+    FOREACH embeddings_table IN ARRAY ARRAY['_embeddings_' || job_name, '_embeddings_' || job_name || '_shadow'] LOOP
+        IF to_regclass(format('vectorize.%I', embeddings_table)) IS NOT NULL THEN
+            EXECUTE format('TRUNCATE vectorize.%I', embeddings_table);
+        END IF;
+    END LOOP;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+--- messages that exhausted their retries, moved here from the vectorize_jobs queue by the worker
+CREATE TABLE IF NOT EXISTS vectorize.dead_letter (
+    id bigserial PRIMARY KEY,
+    msg_id BIGINT NOT NULL,
+    job_name TEXT NOT NULL,
+    message jsonb NOT NULL,
+    read_ct INT NOT NULL,
+    enqueued_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    error TEXT,
+    provider TEXT,
+    http_status INT,
+    failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS dead_letter_job_name_idx ON vectorize.dead_letter (job_name);
+
+--- lists dead letters, optionally for a single job
+CREATE OR REPLACE FUNCTION vectorize.dead_letters(
+    job_name TEXT DEFAULT NULL
+) RETURNS SETOF vectorize.dead_letter AS $$
+    SELECT *
+    FROM vectorize.dead_letter d
+    WHERE dead_letters.job_name IS NULL OR d.job_name = dead_letters.job_name
+    ORDER BY d.failed_at;
+$$ LANGUAGE sql STABLE;
+
+--- sends dead letters back to the vectorize_jobs queue and removes them from vectorize.dead_letter
+--- replays every dead letter when both arguments are NULL
+CREATE OR REPLACE FUNCTION vectorize.replay_dead_letters(
+    job_name TEXT DEFAULT NULL,
+    ids BIGINT[] DEFAULT NULL
+) RETURNS BIGINT AS $$
+DECLARE
+    replayed BIGINT;
+BEGIN
+    WITH replay AS (
+        DELETE FROM vectorize.dead_letter d
+        WHERE (replay_dead_letters.job_name IS NULL OR d.job_name = replay_dead_letters.job_name)
+        AND (replay_dead_letters.ids IS NULL OR d.id = ANY(replay_dead_letters.ids))
+        RETURNING d.message
+    ), sent AS (
+        SELECT pgmq.send('vectorize_jobs', replay.message) FROM replay
+    )
+    SELECT COUNT(*) INTO replayed FROM sent;
+    RETURN replayed;
+END;
+$$ LANGUAGE plpgsql;
+
+--- token buckets shared by every worker process
+--- each bucket refills continuously up to its per-minute limit
+CREATE TABLE IF NOT EXISTS vectorize.rate_limit (
+    scope TEXT NOT NULL CHECK (scope IN ('provider', 'job')),
+    name TEXT NOT NULL,
+    requests_per_minute INT CHECK (requests_per_minute > 0),
+    tokens_per_minute INT CHECK (tokens_per_minute > 0),
+    available_requests DOUBLE PRECISION NOT NULL DEFAULT 0,
+    available_tokens DOUBLE PRECISION NOT NULL DEFAULT 0,
+    refilled_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+    PRIMARY KEY (scope, name)
+);
+
+--- sets the rate limit of a model provider, e.g. 'openai', or of a single job
+--- a NULL limit is not enforced, and setting both limits to NULL removes the rate limit
+CREATE OR REPLACE FUNCTION vectorize.set_rate_limit(
+    provider TEXT DEFAULT NULL,
+    job_name TEXT DEFAULT NULL,
+    requests_per_minute INT DEFAULT NULL,
+    tokens_per_minute INT DEFAULT NULL
+) RETURNS VOID AS $$
+DECLARE
+    limit_scope TEXT;
+    limit_name TEXT;
+BEGIN
+    IF (provider IS NULL) = (job_name IS NULL) THEN
+        RAISE EXCEPTION 'exactly one of provider or job_name is required';
+    END IF;
+    limit_scope := CASE WHEN provider IS NULL THEN 'job' ELSE 'provider' END;
+    limit_name := COALESCE(lower(provider), job_name);
+
+    IF set_rate_limit.requests_per_minute IS NULL AND set_rate_limit.tokens_per_minute IS NULL THEN
+        DELETE FROM vectorize.rate_limit r WHERE r.scope = limit_scope AND r.name = limit_name;
+        RETURN;
+    END IF;
+
+    INSERT INTO vectorize.rate_limit AS r
+        (scope, name, requests_per_minute, tokens_per_minute, available_requests, available_tokens, refilled_at)
+    VALUES (
+        limit_scope,
+        limit_name,
+        set_rate_limit.requests_per_minute,
+        set_rate_limit.tokens_per_minute,
+        COALESCE(set_rate_limit.requests_per_minute, 0),
+        COALESCE(set_rate_limit.tokens_per_minute, 0),
+        clock_timestamp()
+    )
+    ON CONFLICT (scope, name) DO UPDATE SET
+        requests_per_minute = EXCLUDED.requests_per_minute,
+        tokens_per_minute = EXCLUDED.tokens_per_minute,
+        available_requests = LEAST(r.available_requests, COALESCE(EXCLUDED.requests_per_minute, 0)),
+        available_tokens = LEAST(r.available_tokens, COALESCE(EXCLUDED.tokens_per_minute, 0));
+END;
+$$ LANGUAGE plpgsql;
+
+--- called by the worker before each request to a model provider
+--- takes requests and tokens from the provider's and the job's buckets, but only when both have enough
+--- returns 0 when the budget was taken, otherwise the seconds to wait before trying again
+CREATE OR REPLACE FUNCTION vectorize._acquire_rate_limit(
+    provider TEXT,
+    job_name TEXT,
+    requests INT,
+    tokens INT
+) RETURNS DOUBLE PRECISION AS $$
+DECLARE
+    bucket RECORD;
+    ts TIMESTAMP WITH TIME ZONE := clock_timestamp();
+    elapsed DOUBLE PRECISION;
+    available DOUBLE PRECISION;
+    needed DOUBLE PRECISION;
+    wait DOUBLE PRECISION := 0;
+BEGIN
+    -- rows are locked in a consistent order so that concurrent workers do not deadlock
+    FOR bucket IN
+        SELECT * FROM vectorize.rate_limit r
+        WHERE (r.scope = 'provider' AND r.name = lower(provider))
+        OR (r.scope = 'job' AND r.name = _acquire_rate_limit.job_name)
+        ORDER BY r.scope, r.name
+        FOR UPDATE
+    LOOP
+        elapsed := extract(epoch FROM ts - bucket.refilled_at);
+        IF bucket.requests_per_minute IS NOT NULL THEN
+            available := LEAST(bucket.requests_per_minute, bucket.available_requests + elapsed * bucket.requests_per_minute / 60.0);
+            -- a batch larger than the whole budget waits for a full bucket
+            needed := LEAST(requests, bucket.requests_per_minute);
+            wait := GREATEST(wait, (needed - available) * 60.0 / bucket.requests_per_minute);
+        END IF;
+        IF bucket.tokens_per_minute IS NOT NULL THEN
+            available := LEAST(bucket.tokens_per_minute, bucket.available_tokens + elapsed * bucket.tokens_per_minute / 60.0);
+            needed := LEAST(tokens, bucket.tokens_per_minute);
+            wait := GREATEST(wait, (needed - available) * 60.0 / bucket.tokens_per_minute);
+        END IF;
+    END LOOP;
+
+    IF wait > 0 THEN
+        RETURN wait;
+    END IF;
+
+    UPDATE vectorize.rate_limit r SET
+        available_requests = CASE WHEN r.requests_per_minute IS NULL THEN 0 ELSE
+            LEAST(r.requests_per_minute, r.available_requests + extract(epoch FROM ts - r.refilled_at) * r.requests_per_minute / 60.0)
+            - LEAST(requests, r.requests_per_minute)
+        END,
+        available_tokens = CASE WHEN r.tokens_per_minute IS NULL THEN 0 ELSE
+            LEAST(r.tokens_per_minute, r.available_tokens + extract(epoch FROM ts - r.refilled_at) * r.tokens_per_minute / 60.0)
+            - LEAST(tokens, r.tokens_per_minute)
+        END,
+        refilled_at = ts
+    WHERE (r.scope = 'provider' AND r.name = lower(provider))
+    OR (r.scope = 'job' AND r.name = _acquire_rate_limit.job_name);
+    RETURN 0;
+END;
+$$ LANGUAGE plpgsql;
+
+--- counters of the work done by the workers for each job
+CREATE TABLE IF NOT EXISTS vectorize.job_stats (
+    job_name TEXT PRIMARY KEY REFERENCES vectorize.job (name) ON DELETE CASCADE,
+    skipped_rows BIGINT NOT NULL DEFAULT 0
+);
+
+--- embeddings of previously seen input text, shared by search, encode and the workers
+--- enabled with vectorize.embedding_cache, and bounded by vectorize.embedding_cache_ttl_sec and vectorize.embedding_cache_max_size
+CREATE UNLOGGED TABLE IF NOT EXISTS vectorize.embedding_cache (
+    model TEXT NOT NULL,
+    input_hash TEXT NOT NULL,
+    embedding DOUBLE PRECISION[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (model, input_hash)
+);
+CREATE INDEX IF NOT EXISTS embedding_cache_last_used_at_idx ON vectorize.embedding_cache (last_used_at);
+
+CREATE TABLE IF NOT EXISTS vectorize.embedding_cache_stats (
+    model TEXT PRIMARY KEY,
+    hits BIGINT NOT NULL DEFAULT 0,
+    misses BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE OR REPLACE FUNCTION vectorize._embedding_cache_enabled() RETURNS BOOLEAN AS $$
+    SELECT COALESCE(NULLIF(current_setting('vectorize.embedding_cache', true), '')::BOOLEAN, false);
+$$ LANGUAGE sql;
+
+--- returns the cached embeddings of the inputs, by their 1-based position in the array
+--- cache misses are not returned
+CREATE OR REPLACE FUNCTION vectorize._embedding_cache_get(
+    model TEXT,
+    inputs TEXT[]
+) RETURNS TABLE (idx INT, embedding DOUBLE PRECISION[]) AS $$
+DECLARE
+    ttl INT := COALESCE(NULLIF(current_setting('vectorize.embedding_cache_ttl_sec', true), '')::INT, 604800);
+    hit_ct BIGINT := 0;
+BEGIN
+    IF NOT vectorize._embedding_cache_enabled() THEN
+        RETURN;
+    END IF;
+    -- the cache is unlogged, so a replica can not read it at all
+    IF pg_is_in_recovery() THEN
+        RETURN;
+    END IF;
+    -- e.g. searches in a read-only transaction, which can read the cache but not update it
+    IF current_setting('transaction_read_only') = 'on' THEN
+        RETURN QUERY
+        SELECT i.ord::INT, c.embedding
+        FROM unnest(inputs) WITH ORDINALITY AS i(input, ord)
+        INNER JOIN vectorize.embedding_cache c
+            ON c.model = _embedding_cache_get.model AND c.input_hash = md5(i.input)
+        WHERE c.created_at > NOW() - make_interval(secs => ttl);
+        RETURN;
+    END IF;
+    FOR idx, embedding IN
+        UPDATE vectorize.embedding_cache c
+        SET last_used_at = NOW()
+        FROM unnest(inputs) WITH ORDINALITY AS i(input, ord)
+        WHERE c.model = _embedding_cache_get.model
+        AND c.input_hash = md5(i.input)
+        AND c.created_at > NOW() - make_interval(secs => ttl)
+        RETURNING i.ord::INT, c.embedding
+    LOOP
+        hit_ct := hit_ct + 1;
+        RETURN NEXT;
+    END LOOP;
+    INSERT INTO vectorize.embedding_cache_stats AS s (model, hits, misses)
+    VALUES (_embedding_cache_get.model, hit_ct, cardinality(inputs) - hit_ct)
+    ON CONFLICT ON CONSTRAINT embedding_cache_stats_pkey DO UPDATE
+    SET hits = s.hits + EXCLUDED.hits, misses = s.misses + EXCLUDED.misses;
+END;
+$$ LANGUAGE plpgsql;
+
+--- stores the embeddings of the inputs, a JSON array with one array of floats per input
+--- then evicts entries in a batch when the cache has grown beyond the max size
+CREATE OR REPLACE FUNCTION vectorize._embedding_cache_put(
+    model TEXT,
+    inputs TEXT[],
+    embeddings JSONB
+) RETURNS VOID AS $$
+DECLARE
+    ttl INT := COALESCE(NULLIF(current_setting('vectorize.embedding_cache_ttl_sec', true), '')::INT, 604800);
+    max_size BIGINT := COALESCE(NULLIF(current_setting('vectorize.embedding_cache_max_size', true), '')::BIGINT, 100000);
+BEGIN
+    IF NOT vectorize._embedding_cache_enabled()
+        OR pg_is_in_recovery()
+        OR current_setting('transaction_read_only') = 'on' THEN
+        RETURN;
+    END IF;
+    INSERT INTO vectorize.embedding_cache AS c (model, input_hash, embedding)
+    SELECT DISTINCT ON (md5(i.input))
+        _embedding_cache_put.model,
+        md5(i.input),
+        ARRAY(SELECT jsonb_array_elements_text(e.embedding)::DOUBLE PRECISION)
+    FROM unnest(inputs) WITH ORDINALITY AS i(input, ord)
+    INNER JOIN jsonb_array_elements(embeddings) WITH ORDINALITY AS e(embedding, ord) ON e.ord = i.ord
+    ORDER BY md5(i.input)
+    ON CONFLICT ON CONSTRAINT embedding_cache_pkey DO UPDATE
+    SET embedding = EXCLUDED.embedding, created_at = NOW(), last_used_at = NOW();
+
+    -- the size of the cache is the row estimate that autovacuum keeps up to date, so that puts do not scan the cache
+    -- once it exceeds max_size, expired entries and then the least recently used entries are removed down to 90% of it
+    IF (SELECT reltuples FROM pg_class WHERE oid = 'vectorize.embedding_cache'::regclass) > max_size THEN
+        DELETE FROM vectorize.embedding_cache
+        WHERE created_at <= NOW() - make_interval(secs => ttl);
+        DELETE FROM vectorize.embedding_cache
+        WHERE ctid IN (
+            SELECT ctid FROM vectorize.embedding_cache
+            ORDER BY last_used_at DESC
+            OFFSET (max_size * 0.9)::BIGINT
+        );
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+--- removes the cached embeddings of a model, or of every model when model is NULL
+--- returns the number of removed embeddings
+CREATE OR REPLACE FUNCTION vectorize.purge_embedding_cache(
+    model TEXT DEFAULT NULL
+) RETURNS BIGINT AS $$
+DECLARE
+    removed BIGINT;
+BEGIN
+    -- query embeddings are cached under `<model>#query`, and reduced-dimension embeddings under `<model>@<dimensions>`
+    DELETE FROM vectorize.embedding_cache c
+    WHERE purge_embedding_cache.model IS NULL
+        OR c.model = purge_embedding_cache.model
+        OR c.model = purge_embedding_cache.model || '#query'
+        OR starts_with(c.model, purge_embedding_cache.model || '@');
+    GET DIAGNOSTICS removed = ROW_COUNT;
+    RETURN removed;
+END;
+$$ LANGUAGE plpgsql;
+
+--- embedding providers that are configured here instead of built into the extension
+--- a transformer named `<name>/<model>` is served by the provider `<name>`
+CREATE TABLE IF NOT EXISTS vectorize.model_providers (
+    name TEXT PRIMARY KEY CHECK (
+        name ~ '^[a-z0-9_-]+$'
+        AND name NOT IN ('openai', 'sentence-transformers', 'ollama', 'tembo', 'cohere', 'portkey', 'voyage', 'azure', 'gemini', 'tei', 'local')
+    ),
+    base_url TEXT NOT NULL,
+    -- request and response shape of the provider's embeddings API
+    api_format TEXT NOT NULL DEFAULT 'openai' CHECK (api_format IN ('openai', 'cohere', 'ollama')),
+    -- the api key is sent as `<auth_header>: <auth_scheme> <api key>`, or without a scheme when it is NULL
+    auth_header TEXT DEFAULT 'Authorization',
+    auth_scheme TEXT DEFAULT 'Bearer',
+    -- name of the GUC that holds the api key, e.g. 'myprovider.api_key'
+    api_key_guc TEXT
+);
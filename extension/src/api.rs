@@ -2,78 +2,102 @@ use crate::chat::ops::{call_chat, call_chat_completions};
 use crate::chat::types::RenderedPrompt;
 use crate::guc::get_guc_configs;
 use crate::init::{init_cron, VECTORIZE_QUEUE};
-use crate::job::{create_event_trigger, create_trigger_handler};
+use crate::job::{self, create_event_trigger, create_trigger_handler};
 use crate::search::{self, init_table};
-use crate::transformers::generic::env_interpolate_string;
-use crate::transformers::transform;
+use crate::transformers::{transform, transform_batch};
 use crate::types;
 use crate::util::get_vectorize_meta_spi;
 use text_splitter::TextSplitter;
-use vectorize_core::types::{JobParams, Model};
-
-use anyhow::Result;
+use vectorize_core::chunking::{self, ChunkConfig};
+use vectorize_core::transformers::generic::env_interpolate_string;
+use vectorize_core::types::{
+    ChunkAggregation, ChunkStrategy, DiskAnnSearchParams, EmbeddingStorage, InputPrefixes,
+    InputType, JobParams, Model,
+};
+
+use anyhow::{anyhow, Result};
 use pgrx::prelude::*;
 
+/// splits the text in a column of every row of a table into chunks, and inserts them into the output table
+/// each chunk records the primary key of its row, its position in the row, and its character offsets in the text
 #[pg_extern]
 fn chunk_table(
     input_table: &str,
     column_name: &str,
-    primary_key: &str, // Add primary_key parameter
+    primary_key: &str,
     max_chunk_size: default!(i32, 1000),
     output_table: default!(&str, "'chunked_data'"),
+    strategy: default!(types::ChunkStrategy, "'characters'"),
+    chunk_overlap: default!(i32, 0),
+    // language of the column, which the code strategy parses, e.g. 'python'
+    language: default!(Option<String>, "NULL"),
 ) -> Result<String> {
-    let max_chunk_size = max_chunk_size as usize;
-
-    // Retrieve rows from the input table, ensuring column existence
-    let query = format!(
-        "SELECT {}, {} FROM {}",
-        primary_key, column_name, input_table
-    ); // Use primary_key instead of hardcoding "id"
-
-    // Reverting back to use get_two
-    let (id_opt, text_opt): (Option<i32>, Option<String>) = Spi::get_two(&query)?;
-    let rows = vec![(id_opt, text_opt)]; // Wrap in a vector if needed
-
-    // Prepare to hold chunked rows
-    let mut chunked_rows: Vec<(i32, i32, String)> = Vec::new(); // (original_id, chunk_index, chunk)
-
-    // Chunk the data and keep track of the original id and chunk index
-    for (id_opt, text_opt) in rows {
-        // Only process rows where both id and text exist
-        if let (Some(id), Some(text)) = (id_opt, text_opt.map(|s| s.to_string())) {
-            let chunks = chunk_text(
-                &text,
-                max_chunk_size.try_into().expect("failed usize conversion"),
-            );
-            for (index, chunk) in chunks.iter().enumerate() {
-                chunked_rows.push((id, index as i32, chunk.clone())); // Add chunk index
-            }
-        }
-    }
+    // fail before creating the output table when the chunk config is invalid
+    let config = chunk_config(strategy, max_chunk_size, chunk_overlap, language.as_deref())?;
+
+    let pkey_type: String = Spi::get_one_with_args(
+        "
+        SELECT format_type(atttypid, atttypmod)
+        FROM pg_attribute
+        WHERE attrelid = $1::regclass
+        AND attname = $2
+        AND NOT attisdropped
+        ",
+        &[input_table.into(), primary_key.into()],
+    )?
+    .ok_or_else(|| anyhow!("primary key {primary_key} not found in table {input_table}"))?;
 
-    // Create output table with an additional column for chunk index
     let create_table_query = format!(
-        "CREATE TABLE IF NOT EXISTS {} (id SERIAL PRIMARY KEY, original_id INT, chunk_index INT, chunk TEXT)",
-        output_table
+        "CREATE TABLE IF NOT EXISTS {output_table} (
+            id SERIAL PRIMARY KEY,
+            original_id {pkey_type},
+            chunk_index INT,
+            chunk TEXT,
+            start_offset INT,
+            end_offset INT
+        )"
     );
     Spi::run(&create_table_query)
-        .map_err(|e| anyhow::anyhow!("Failed to create table {}: {}", output_table, e))?;
-
-    // Insert chunked rows into output table
-    for (original_id, chunk_index, chunk) in chunked_rows {
-        let insert_query = format!(
-            "INSERT INTO {} (original_id, chunk_index, chunk) VALUES ($1, $2, $3)",
-            output_table
-        );
-        Spi::run_with_args(
+        .map_err(|e| anyhow!("Failed to create table {}: {}", output_table, e))?;
+    // output tables created before chunks recorded their offsets are missing the offset columns
+    let add_offsets_query = format!(
+        "ALTER TABLE {output_table}
+            ADD COLUMN IF NOT EXISTS start_offset INT,
+            ADD COLUMN IF NOT EXISTS end_offset INT"
+    );
+    Spi::run(&add_offsets_query).map_err(|e| {
+        anyhow!(
+            "Failed to add offset columns to table {}: {}",
+            output_table,
+            e
+        )
+    })?;
+
+    // chunked in a single statement, so rows are not read into the extension
+    let insert_query = format!(
+        "INSERT INTO {output_table} (original_id, chunk_index, chunk, start_offset, end_offset)
+        SELECT t.{primary_key}, c.chunk_index, c.chunk, c.start_offset, c.end_offset
+        FROM {input_table} t,
+        LATERAL vectorize.chunk_document(t.{column_name}::text, $1::vectorize.ChunkStrategy, $2, $3, $4) c
+        WHERE t.{column_name} IS NOT NULL
+        ORDER BY t.{primary_key}, c.chunk_index"
+    );
+    let inserted = Spi::connect_mut(|c| {
+        c.update(
             &insert_query,
-            &[original_id.into(), chunk_index.into(), chunk.into()],
-        )?;
-    }
+            None,
+            &[
+                config.strategy.to_string().into(),
+                max_chunk_size.into(),
+                chunk_overlap.into(),
+                language.into(),
+            ],
+        )
+        .map(|rows| rows.len())
+    })?;
 
     Ok(format!(
-        "Chunked data inserted into table: {}",
-        output_table
+        "Inserted {inserted} chunk(s) into table: {output_table}"
     ))
 }
 
@@ -91,14 +115,46 @@ fn table(
     table_method: default!(types::TableMethod, "'join'"),
     // cron-like for a cron based update model, or 'realtime' for a trigger-based
     schedule: default!(&str, "'* * * * *'"),
+    // default query-time parameters for vsc_diskann_cosine jobs
+    diskann_search_list_size: default!(Option<i32>, "NULL"),
+    diskann_query_rescore: default!(Option<i32>, "NULL"),
+    // prepended to search queries and to source rows before they are embedded, e.g. 'query: ' and 'passage: '
+    query_prefix: default!(Option<String>, "NULL"),
+    document_prefix: default!(Option<String>, "NULL"),
+    // size of the embeddings, for transformers whose embeddings can be shortened, e.g. openai/text-embedding-3-small
+    dimensions: default!(Option<i32>, "NULL"),
+    storage: default!(types::EmbeddingStorage, "'vector'"),
+    // embeds the columns with a learned sparse model, e.g. SPLADE, for use as the sparse job of hybrid_search
+    sparse: default!(bool, false),
+    // splits the text of each row into chunks of at most this size, which are embedded separately
+    max_chunk_size: default!(Option<i32>, "NULL"),
+    chunk_strategy: default!(types::ChunkStrategy, "'characters'"),
+    chunk_overlap: default!(i32, 0),
+    // percentile of the similarities of adjacent sentences below which the semantic strategy cuts
+    chunk_breakpoint_percentile: default!(f64, 10.0),
+    // how search scores a row from its matching chunks
+    chunk_aggregation: default!(types::ChunkAggregation, "'max'"),
+    // language of the columns, which the code strategy parses, e.g. 'python'
+    chunk_language: default!(Option<String>, "NULL"),
 ) -> Result<String> {
     let model = Model::new(transformer)?;
+    let chunking = max_chunk_size
+        .map(|size| {
+            ChunkConfig::new(chunk_strategy.into(), size, chunk_overlap)?
+                .with_breakpoint_percentile(chunk_breakpoint_percentile)?
+                .with_language(chunk_language.as_deref())
+        })
+        .transpose()?;
     let update_time_col = if schedule == "realtime" {
         // updates are based on triggers in the realtime configuration
         None
     } else {
         Some(update_col)
     };
+    let diskann_search = DiskAnnSearchParams {
+        search_list_size: diskann_search_list_size,
+        query_rescore: diskann_query_rescore,
+    };
     init_table(
         job_name,
         schema,
@@ -110,9 +166,20 @@ fn table(
         &model,
         table_method.into(),
         schedule,
+        diskann_search,
+        InputPrefixes {
+            query: query_prefix,
+            document: document_prefix,
+        },
+        dimensions,
+        storage.into(),
+        sparse,
+        chunking,
+        chunk_aggregation.into(),
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 #[pg_extern]
 fn search(
     job_name: String,
@@ -121,7 +188,19 @@ fn search(
     return_columns: default!(Vec<String>, "ARRAY['*']::text[]"),
     num_results: default!(i32, 10),
     where_sql: default!(Option<String>, "NULL"),
+    // overrides the job's diskann query-time parameters for this call
+    diskann_search_list_size: default!(Option<i32>, "NULL"),
+    diskann_query_rescore: default!(Option<i32>, "NULL"),
+    // reorders the results by their relevance to the query, e.g. 'cohere/rerank-english-v3.0'
+    rerank_model: default!(Option<String>, "NULL"),
+    // diversifies the results with maximal marginal relevance, from 0 (most diverse) to 1 (most relevant)
+    mmr_lambda: default!(Option<f64>, "NULL"),
 ) -> Result<TableIterator<'static, (name!(search_results, pgrx::JsonB),)>> {
+    let diskann_search = DiskAnnSearchParams {
+        search_list_size: diskann_search_list_size,
+        query_rescore: diskann_query_rescore,
+    };
+    let rerank_model = rerank_model.as_deref().map(Model::new).transpose()?;
     let search_results = search::search(
         &job_name,
         &query,
@@ -129,6 +208,9 @@ fn search(
         return_columns,
         num_results,
         where_sql,
+        &diskann_search,
+        rerank_model.as_ref(),
+        mmr_lambda,
     )?;
     Ok(TableIterator::new(search_results.into_iter().map(|r| (r,))))
 }
@@ -136,6 +218,7 @@ fn search(
 /// EXPERIMENTAL: Hybrid search
 ///
 /// This function is experimental and may change in future versions.
+#[allow(clippy::too_many_arguments)]
 #[pg_extern]
 fn hybrid_search(
     job_name: String,
@@ -144,6 +227,10 @@ fn hybrid_search(
     return_columns: default!(Vec<String>, "ARRAY['*']::text[]"),
     num_results: default!(i32, 10),
     where_sql: default!(Option<String>, "NULL"),
+    // a sparse job on the same table, fused instead of full-text search
+    sparse_job_name: default!(Option<String>, "NULL"),
+    // diversifies the fused results with maximal marginal relevance, see search()
+    mmr_lambda: default!(Option<f64>, "NULL"),
 ) -> Result<TableIterator<'static, (name!(search_results, pgrx::JsonB),)>> {
     let search_results = search::hybrid_search(
         &job_name,
@@ -152,6 +239,8 @@ fn hybrid_search(
         return_columns,
         num_results,
         where_sql,
+        sparse_job_name,
+        mmr_lambda,
     )?;
     Ok(TableIterator::new(search_results.into_iter().map(|r| (r,))))
 }
@@ -163,7 +252,7 @@ fn transform_embeddings(
     api_key: default!(Option<String>, "NULL"),
 ) -> Result<Vec<f64>> {
     let model = Model::new(&model_name)?;
-    Ok(transform(input, &model, api_key).remove(0))
+    Ok(transform(input, &model, api_key, InputType::Document, None).remove(0))
 }
 
 #[pg_extern]
@@ -173,7 +262,7 @@ fn encode(
     api_key: default!(Option<String>, "NULL"),
 ) -> Result<Vec<f64>> {
     let model = Model::new(&model)?;
-    Ok(transform(input, &model, api_key).remove(0))
+    Ok(transform(input, &model, api_key, InputType::Document, None).remove(0))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -207,10 +296,18 @@ fn init_rag(
         &transformer_model,
         table_method.into(),
         schedule,
+        DiskAnnSearchParams::default(),
+        InputPrefixes::default(),
+        None,
+        EmbeddingStorage::default(),
+        false,
+        None,
+        ChunkAggregation::default(),
     )
 }
 
 /// creates a table indexed with embeddings for chat completion workloads
+#[allow(clippy::too_many_arguments)]
 #[pg_extern]
 fn rag(
     job_name: &str,
@@ -223,8 +320,13 @@ fn rag(
     num_context: default!(i32, 2),
     // truncates context to fit the model's context window
     force_trim: default!(bool, false),
+    // reorders the search results by their relevance to the query before they are used as context
+    rerank_model: default!(Option<String>, "NULL"),
+    // diversifies the search results with maximal marginal relevance before they are used as context
+    mmr_lambda: default!(Option<f64>, "NULL"),
 ) -> Result<TableIterator<'static, (name!(chat_results, pgrx::JsonB),)>> {
     let model = Model::new(&chat_model)?;
+    let rerank_model = rerank_model.as_deref().map(Model::new).transpose()?;
     let resp = call_chat(
         job_name,
         query,
@@ -233,6 +335,8 @@ fn rag(
         api_key,
         num_context,
         force_trim,
+        rerank_model.as_ref(),
+        mmr_lambda,
     )?;
     let iter = vec![(pgrx::JsonB(serde_json::to_value(resp)?),)];
     Ok(TableIterator::new(iter))
@@ -249,7 +353,7 @@ fn generate(
         sys_rendered: "".to_string(),
         user_rendered: input.to_string(),
     };
-    let mut guc_configs = get_guc_configs(&model.source);
+    let mut guc_configs = get_guc_configs(&model);
     if let Some(api_key) = api_key {
         guc_configs.api_key = Some(api_key);
     }
@@ -281,6 +385,129 @@ fn chunk_text(document: &str, max_characters: i64) -> Vec<String> {
     splitter.chunks(document).map(|s| s.to_string()).collect()
 }
 
+/// Splits a document into chunks of at most max_chunk_size characters, or tokens for the tokens strategy
+///
+/// The code strategy parses the document as source code of the given language, e.g. 'python'
+///
+/// Offsets are in characters, so `substr(document, start_offset + 1, end_offset - start_offset)` is the chunk
+///
+/// # Example
+///
+/// ```sql
+/// SELECT * FROM vectorize.chunk_document(E'# Intro\nSome text.\n# Usage\nMore text.', 'markdown', 20);
+/// ```
+#[pg_extern(immutable, parallel_safe)]
+fn chunk_document(
+    document: &str,
+    strategy: default!(types::ChunkStrategy, "'characters'"),
+    max_chunk_size: default!(i32, 1000),
+    chunk_overlap: default!(i32, 0),
+    language: default!(Option<String>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(chunk_index, i32),
+            name!(chunk, String),
+            name!(start_offset, i32),
+            name!(end_offset, i32),
+        ),
+    >,
+> {
+    let config = chunk_config(strategy, max_chunk_size, chunk_overlap, language.as_deref())?;
+    let chunks = chunking::chunk_document(document, &config)?;
+    Ok(TableIterator::new(chunks.into_iter().enumerate().map(
+        |(index, chunk)| {
+            (
+                index as i32,
+                chunk.text,
+                chunk.start as i32,
+                chunk.end as i32,
+            )
+        },
+    )))
+}
+
+// the semantic strategy needs a model to embed sentences, which only jobs and chunk_semantic() have
+fn chunk_config(
+    strategy: types::ChunkStrategy,
+    max_chunk_size: i32,
+    chunk_overlap: i32,
+    language: Option<&str>,
+) -> Result<ChunkConfig> {
+    let config = ChunkConfig::new(strategy.into(), max_chunk_size, chunk_overlap)?
+        .with_language(language)?;
+    if config.strategy == ChunkStrategy::semantic {
+        return Err(anyhow!(
+            "the semantic strategy embeds sentences with a model, use vectorize.chunk_semantic() instead"
+        ));
+    }
+    Ok(config)
+}
+
+/// Splits a document into chunks of consecutive sentences on the same topic
+///
+/// Every sentence is embedded with the model, and a chunk ends where the similarity of adjacent
+/// sentences is below the threshold percentile of the similarities of all adjacent sentences,
+/// so a higher threshold makes more and smaller chunks. Chunks also end before they grow larger
+/// than max_chunk_size characters.
+///
+/// # Example
+///
+/// ```sql
+/// SELECT * FROM vectorize.chunk_semantic(
+///     'Cats purr when they are content. Most cats sleep all day. Rust compiles to native code.',
+///     model => 'openai/text-embedding-3-small',
+///     threshold => 25
+/// );
+/// ```
+#[pg_extern]
+fn chunk_semantic(
+    document: &str,
+    model: default!(String, "'sentence-transformers/all-MiniLM-L6-v2'"),
+    threshold: default!(f64, 10.0),
+    max_chunk_size: default!(Option<i32>, "NULL"),
+    api_key: default!(Option<String>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(chunk_index, i32),
+            name!(chunk, String),
+            name!(start_offset, i32),
+            name!(end_offset, i32),
+        ),
+    >,
+> {
+    let model = Model::new(&model)?;
+    let config = ChunkConfig::new(
+        ChunkStrategy::semantic,
+        max_chunk_size.unwrap_or(i32::MAX),
+        0,
+    )?
+    .with_breakpoint_percentile(threshold)?;
+    let sentences = chunking::sentences(document);
+    let texts: Vec<String> = sentences.iter().map(|s| s.text.clone()).collect();
+    let embeddings = transform_batch(&texts, &model, api_key, InputType::Document);
+    let chunks = chunking::semantic_chunks(
+        document,
+        &sentences,
+        &embeddings,
+        config.breakpoint_percentile,
+        Some(config.max_size),
+    )?;
+    Ok(TableIterator::new(chunks.into_iter().enumerate().map(
+        |(index, chunk)| {
+            (
+                index as i32,
+                chunk.text,
+                chunk.start as i32,
+                chunk.end as i32,
+            )
+        },
+    )))
+}
+
 #[pg_extern]
 fn import_embeddings(
     job_name: &str,
@@ -291,6 +518,11 @@ fn import_embeddings(
     // Get project metadata
     let meta = get_vectorize_meta_spi(job_name)?;
     let job_params: JobParams = serde_json::from_value(meta.params.clone())?;
+    if job_params.chunking.is_some() {
+        return Err(anyhow!(
+            "embeddings can not be imported into chunked job `{job_name}`"
+        ));
+    }
 
     // Process rows based on table method
     let count = if job_params.table_method == vectorize_core::types::TableMethod::join {
@@ -396,6 +628,13 @@ fn table_from(
         &model,
         table_method.into(),
         "manual", // Use manual schedule initially to prevent immediate job creation
+        DiskAnnSearchParams::default(),
+        InputPrefixes::default(),
+        None,
+        EmbeddingStorage::default(),
+        false,
+        None,
+        ChunkAggregation::default(),
     )?;
 
     // Import the embeddings
@@ -422,3 +661,131 @@ fn table_from(
         schedule
     ))
 }
+
+/// drops a job, along with its triggers or cron schedule, embeddings and queued messages
+#[pg_extern]
+fn drop_job(job_name: &str) -> Result<String> {
+    job::drop_job(job_name)?;
+    Ok(format!("Successfully dropped job: {job_name}"))
+}
+
+/// stops a job from enqueueing new embedding work
+#[pg_extern]
+fn pause_job(job_name: &str) -> Result<String> {
+    job::pause_job(job_name)?;
+    Ok(format!("Successfully paused job: {job_name}"))
+}
+
+/// resumes a paused job, and enqueues rows changed while it was paused
+#[pg_extern]
+fn resume_job(job_name: &str) -> Result<String> {
+    job::resume_job(job_name)?;
+    Ok(format!("Successfully resumed job: {job_name}"))
+}
+
+/// updates an existing job; arguments left NULL are unchanged
+#[pg_extern]
+fn alter_job(
+    job_name: &str,
+    schedule: default!(Option<String>, "NULL"),
+    columns: default!(Option<Vec<String>>, "NULL"),
+    update_col: default!(Option<String>, "NULL"),
+    diskann_search_list_size: default!(Option<i32>, "NULL"),
+    diskann_query_rescore: default!(Option<i32>, "NULL"),
+    // an empty string removes the prefix
+    query_prefix: default!(Option<String>, "NULL"),
+    document_prefix: default!(Option<String>, "NULL"),
+) -> Result<String> {
+    let diskann_search = DiskAnnSearchParams {
+        search_list_size: diskann_search_list_size,
+        query_rescore: diskann_query_rescore,
+    };
+    let input_prefixes = InputPrefixes {
+        query: query_prefix,
+        document: document_prefix,
+    };
+    job::alter_job(
+        job_name,
+        schedule,
+        columns,
+        update_col,
+        diskann_search,
+        input_prefixes,
+    )?;
+    Ok(format!("Successfully altered job: {job_name}"))
+}
+
+/// re-embeds a job with a new transformer, then swaps the new embeddings in once complete
+#[pg_extern]
+fn change_model(job_name: &str, new_transformer: &str) -> Result<String> {
+    let model = Model::new(new_transformer)?;
+    job::change_model(job_name, &model)?;
+    Ok(format!(
+        "Started changing job {job_name} to transformer: {new_transformer}"
+    ))
+}
+
+/// completes a model change when the new embeddings are ready
+/// called by the worker, returns whether the new embeddings were swapped in
+/// missing rows are enqueued again unless enqueue_missing is false
+#[pg_extern]
+fn finalize_model_change(job_name: &str, enqueue_missing: default!(bool, true)) -> Result<bool> {
+    job::finalize_model_change(job_name, enqueue_missing)
+}
+
+/// abandons a model change that can not complete, and keeps the job's current transformer
+#[pg_extern]
+fn abort_model_change(job_name: &str) -> Result<String> {
+    if !job::abort_model_change(job_name)? {
+        return Err(anyhow!("job `{job_name}` is not changing its transformer"));
+    }
+    Ok(format!("Aborted the model change of job: {job_name}"))
+}
+
+/// reconciles a job's embeddings with its source table, e.g. after a bulk reload
+#[pg_extern]
+fn resync(job_name: &str) -> Result<String> {
+    let (removed, enqueued) = job::resync(job_name)?;
+    Ok(format!(
+        "Resynced job {job_name}: removed {removed} orphaned embedding(s), enqueued {enqueued} row(s)"
+    ))
+}
+
+/// reports the progress of every job, or of a single job
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn job_status(
+    job_name: default!(Option<String>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(name, String),
+            name!(total_rows, i64),
+            name!(embedded_rows, i64),
+            name!(stale_rows, Option<i64>),
+            name!(skipped_rows, i64),
+            name!(queued_messages, i64),
+            name!(failed_messages, i64),
+            name!(last_success_at, Option<TimestampWithTimeZone>),
+            name!(last_error, Option<String>),
+            name!(paused, bool),
+        ),
+    >,
+> {
+    let statuses = job::job_status(job_name.as_deref())?;
+    Ok(TableIterator::new(statuses.into_iter().map(|s| {
+        (
+            s.job_name,
+            s.total_rows,
+            s.embedded_rows,
+            s.stale_rows,
+            s.skipped_rows,
+            s.queued_messages,
+            s.failed_messages,
+            s.last_success_at,
+            s.last_error,
+            s.paused,
+        )
+    })))
+}
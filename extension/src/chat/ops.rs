@@ -6,6 +6,8 @@ use anyhow::{anyhow, Result};
 use handlebars::Handlebars;
 use pgrx::prelude::*;
 use vectorize_core::guc::ModelGucConfig;
+use vectorize_core::transformers::http_handler::RetryConfig;
+use vectorize_core::transformers::providers::azure::AzureOpenAIProvider;
 use vectorize_core::transformers::providers::ollama::OllamaProvider;
 use vectorize_core::transformers::providers::openai::OpenAIProvider;
 use vectorize_core::transformers::providers::portkey::PortkeyProvider;
@@ -15,8 +17,9 @@ use vectorize_core::types::ModelSource;
 
 use crate::chat::types::{ChatResponse, ContextualSearch, PromptTemplate, RenderedPrompt};
 use tiktoken_rs::{get_bpe_from_model, model::get_context_size, CoreBPE};
-use vectorize_core::types::{JobParams, VectorizeMeta};
+use vectorize_core::types::{DiskAnnSearchParams, JobParams, VectorizeMeta};
 
+#[allow(clippy::too_many_arguments)]
 pub fn call_chat(
     job_name: &str,
     query: &str,
@@ -25,6 +28,8 @@ pub fn call_chat(
     api_key: Option<String>,
     num_context: i32,
     force_trim: bool,
+    rerank_model: Option<&Model>,
+    mmr_lambda: Option<f64>,
 ) -> Result<ChatResponse> {
     // get job metadata
     let project_meta: VectorizeMeta = get_vectorize_meta_spi(job_name)?;
@@ -32,6 +37,9 @@ pub fn call_chat(
     let job_params = serde_json::from_value::<JobParams>(project_meta.params.clone())
         .unwrap_or_else(|e| error!("failed to deserialize job params: {}", e));
 
+    let guc_configs = guc::get_guc_configs(chat_model);
+    check_chat_provider(chat_model, &guc_configs)?;
+
     // for various token count estimations
     let bpe = match chat_model.source {
         ModelSource::Ollama => {
@@ -45,8 +53,12 @@ pub fn call_chat(
             // Using gpt-3.5-turbo tokenizer as placeholder for Llama3-8B-Instruct
             get_bpe_from_model("gpt-3.5-turbo").expect("failed to get BPE from model")
         }
-        ModelSource::SentenceTransformers | ModelSource::Cohere => {
-            error!("SentenceTransformers and Cohere not yet supported for chat completions")
+        ModelSource::SentenceTransformers
+        | ModelSource::Cohere
+        | ModelSource::Gemini
+        | ModelSource::Tei
+        | ModelSource::Local => {
+            error!("SentenceTransformers, Cohere, Gemini, TEI and local models not yet supported for chat completions")
         }
         ModelSource::Portkey => {
             get_bpe_from_model(&chat_model.name).expect("failed to get BPE from model")
@@ -54,6 +66,12 @@ pub fn call_chat(
         ModelSource::Voyage => {
             get_bpe_from_model(&chat_model.name).expect("failed to get BPE from model")
         }
+        ModelSource::Azure => {
+            // deployments are often named after their model, otherwise fall back to the gpt-3.5-turbo tokenizer
+            get_bpe_from_model(&chat_model.name)
+                .or_else(|_| get_bpe_from_model("gpt-3.5-turbo"))
+                .expect("failed to get BPE from model")
+        }
     };
 
     // can only be 1 column in a chat job, for now, so safe to grab first element
@@ -61,7 +79,17 @@ pub fn call_chat(
     let pk = job_params.primary_key;
     let columns = vec![pk.clone(), content_column.clone()];
 
-    let raw_search = search::search(job_name, query, api_key.clone(), columns, num_context, None)?;
+    let raw_search = search::search(
+        job_name,
+        query,
+        api_key.clone(),
+        columns,
+        num_context,
+        None,
+        &DiskAnnSearchParams::default(),
+        rerank_model,
+        mmr_lambda,
+    )?;
 
     let mut search_results: Vec<ContextualSearch> = Vec::new();
     for s in raw_search {
@@ -120,7 +148,6 @@ pub fn call_chat(
     )?;
 
     // http request to chat completions
-    let guc_configs = guc::get_guc_configs(&chat_model.source);
     let chat_response = call_chat_completions(rendered_prompt, chat_model, &guc_configs)?;
 
     Ok(ChatResponse {
@@ -139,11 +166,24 @@ fn render_user_message(user_prompt_template: &str, context: &str, query: &str) -
     Ok(user_rendered)
 }
 
+// providers registered in vectorize.model_providers only serve embeddings
+fn check_chat_provider(model: &Model, guc_configs: &ModelGucConfig) -> Result<()> {
+    match &guc_configs.custom_provider {
+        Some(provider) => Err(anyhow!(
+            "model provider {} does not support chat completions, model: {}",
+            provider.name,
+            model.fullname
+        )),
+        None => Ok(()),
+    }
+}
+
 pub fn call_chat_completions(
     prompts: RenderedPrompt,
     model: &Model,
     guc_configs: &ModelGucConfig,
 ) -> Result<String> {
+    check_chat_provider(model, guc_configs)?;
     let messages = vec![
         ChatMessageRequest {
             role: "system".to_owned(),
@@ -166,6 +206,7 @@ pub fn call_chat_completions(
                 let provider = OpenAIProvider::new(
                     guc_configs.service_url.clone(),
                     guc_configs.api_key.clone(),
+                    RetryConfig::interactive(),
                 );
                 provider
                     .generate_response(model.api_name(), &messages)
@@ -176,19 +217,39 @@ pub fn call_chat_completions(
                     guc_configs.service_url.clone(),
                     guc_configs.api_key.clone(),
                     guc_configs.virtual_key.clone(),
+                    RetryConfig::interactive(),
                 );
                 provider
                     .generate_response(model.api_name(), &messages)
                     .await
             }
             ModelSource::Ollama => {
-                let provider = OllamaProvider::new(guc_configs.service_url.clone());
+                let provider = OllamaProvider::new(
+                    guc_configs.service_url.clone(),
+                    RetryConfig::interactive(),
+                );
+                provider
+                    .generate_response(model.api_name(), &messages)
+                    .await
+            }
+            ModelSource::Azure => {
+                let provider = AzureOpenAIProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                    guc_configs.api_version.clone(),
+                    RetryConfig::interactive(),
+                );
                 provider
                     .generate_response(model.api_name(), &messages)
                     .await
             }
-            ModelSource::SentenceTransformers | ModelSource::Cohere | ModelSource::Voyage => {
-                error!("SentenceTransformers and Cohere not yet supported for chat completions")
+            ModelSource::SentenceTransformers
+            | ModelSource::Cohere
+            | ModelSource::Voyage
+            | ModelSource::Gemini
+            | ModelSource::Tei
+            | ModelSource::Local => {
+                error!("SentenceTransformers, Cohere, Voyage, Gemini, TEI and local models not yet supported for chat completions")
             }
         }
     })?;
@@ -87,6 +87,10 @@ fn job_execute(job_name: String) {
             .unwrap_or_else(|e| error!("failed to get job metadata: {}", e));
         let job_params = serde_json::from_value::<JobParams>(meta.params.clone())
             .unwrap_or_else(|e| error!("failed to deserialize job params: {}", e));
+        if job_params.paused {
+            info!("pg-vectorize: job {} is paused, skipping refresh", job_name);
+            return;
+        }
 
         let new_or_updated_rows = get_new_updates(&conn, &job_name, job_params)
             .await
@@ -106,6 +110,7 @@ fn job_execute(job_name: String) {
                     let msg = JobMessage {
                         job_name: job_name.clone(),
                         record_ids,
+                        shadow: false,
                     };
                     let msg_id = queue
                         .send(VECTORIZE_QUEUE, &msg)
@@ -121,6 +126,18 @@ fn job_execute(job_name: String) {
     })
 }
 
+// the embeddings table of a join job, with one row per source row
+// the chunks of a row of a chunked job are written together, so they share an update time
+pub fn embedded_rows_source(job_name: &str, job_params: &JobParams) -> String {
+    match job_params.chunking {
+        Some(_) => format!(
+            "(SELECT {join_key}, MIN(updated_at) AS updated_at FROM vectorize._embeddings_{job_name} GROUP BY {join_key})",
+            join_key = job_params.primary_key,
+        ),
+        None => format!("vectorize._embeddings_{job_name}"),
+    }
+}
+
 pub fn new_rows_query_join(job_name: &str, job_params: &JobParams) -> String {
     let cols = &job_params
         .columns
@@ -135,13 +152,13 @@ pub fn new_rows_query_join(job_name: &str, job_params: &JobParams) -> String {
         "
     SELECT t0.{join_key}::text as record_id, {cols} as input_text
     FROM {schema}.{table} t0
-    LEFT JOIN vectorize._embeddings_{job_name} t1 ON t0.{join_key} = t1.{join_key}
+    LEFT JOIN {embeddings} t1 ON t0.{join_key} = t1.{join_key}
     WHERE t1.{join_key} IS NULL",
         join_key = job_params.primary_key,
         cols = cols,
         schema = schema,
         table = table,
-        job_name = job_name
+        embeddings = embedded_rows_source(job_name, job_params),
     );
     if let Some(updated_at_col) = &job_params.update_time_col {
         // updated_at_column is not required when `schedule` is realtime
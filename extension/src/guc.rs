@@ -1,10 +1,10 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use core::ffi::CStr;
 use pgrx::*;
 
-use crate::transformers::generic::env_interpolate_string;
-use vectorize_core::guc::{ModelGucConfig, VectorizeGuc};
-use vectorize_core::types::ModelSource;
+use vectorize_core::guc::{custom_provider_configs, ModelGucConfig, VectorizeGuc};
+use vectorize_core::transformers::generic::env_interpolate_string;
+use vectorize_core::types::{Model, ModelSource};
 
 pub static VECTORIZE_HOST: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static VECTORIZE_DATABASE_NAME: GucSetting<Option<&CStr>> =
@@ -28,7 +28,22 @@ pub static PORTKEY_VIRTUAL_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<
 pub static PORTKEY_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static VOYAGE_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static VOYAGE_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static AZURE_OPENAI_SERVICE_URL: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static AZURE_OPENAI_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static AZURE_OPENAI_API_VERSION: GucSetting<Option<&'static CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(Some(c"2024-02-01"));
+pub static GEMINI_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static GEMINI_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static TEI_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static TEI_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static TEI_TRUNCATE: GucSetting<bool> = GucSetting::<bool>::new(true);
+pub static TEI_NORMALIZE: GucSetting<bool> = GucSetting::<bool>::new(true);
+pub static LOCAL_MODEL_DIR: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static SEMANTIC_WEIGHT: GucSetting<i32> = GucSetting::<i32>::new(50);
+pub static EMBEDDING_CACHE: GucSetting<bool> = GucSetting::<bool>::new(false);
+pub static EMBEDDING_CACHE_TTL_SEC: GucSetting<i32> = GucSetting::<i32>::new(604800);
+pub static EMBEDDING_CACHE_MAX_SIZE: GucSetting<i32> = GucSetting::<i32>::new(100000);
 // EXPERIMENTAL
 pub static FTS_INDEX_TYPE: GucSetting<Option<&'static CStr>> =
     GucSetting::<Option<&'static CStr>>::new(None);
@@ -198,6 +213,96 @@ pub fn init_guc() {
         GucFlags::default(),
     );
 
+    GucRegistry::define_string_guc(
+        "vectorize.azure_openai_service_url",
+        "Endpoint of the Azure OpenAI resource",
+        "Endpoint of the Azure OpenAI resource, e.g. https://my-resource.openai.azure.com",
+        &AZURE_OPENAI_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.azure_openai_key",
+        "API key for the Azure OpenAI resource",
+        "API key for the Azure OpenAI resource. Overridden by any values provided in function calls.",
+        &AZURE_OPENAI_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.azure_openai_api_version",
+        "API version of Azure OpenAI",
+        "Value of the api-version query parameter sent to Azure OpenAI. Default is 2024-02-01.",
+        &AZURE_OPENAI_API_VERSION,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.gemini_service_url",
+        "Base url for the Gemini API",
+        "Base url for the Gemini API. Default is https://generativelanguage.googleapis.com/v1beta",
+        &GEMINI_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.gemini_api_key",
+        "API key for the Gemini API",
+        "API key for the Gemini API. Overridden by any values provided in function calls.",
+        &GEMINI_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.tei_service_url",
+        "Url of a Text Embeddings Inference server",
+        "Url of a Hugging Face Text Embeddings Inference server. Default is http://localhost:8080",
+        &TEI_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.tei_api_key",
+        "API key for the Text Embeddings Inference server",
+        "API key of the Text Embeddings Inference server, sent as a bearer token. Only needed when the server was started with --api-key.",
+        &TEI_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_bool_guc(
+        "vectorize.tei_truncate",
+        "Truncate inputs sent to Text Embeddings Inference",
+        "Truncate inputs that exceed the max_input_length of the Text Embeddings Inference server instead of failing the request. Default is on.",
+        &TEI_TRUNCATE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "vectorize.tei_normalize",
+        "Normalize embeddings from Text Embeddings Inference",
+        "Request unit length embeddings from the Text Embeddings Inference server. Default is on.",
+        &TEI_NORMALIZE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.local_model_dir",
+        "Directory of local ONNX models",
+        "Directory that contains a directory with a model.onnx and a tokenizer.json for each local/ model. Only used when vectorize is built with the onnx feature. Default is /var/lib/vectorize/models",
+        &LOCAL_MODEL_DIR,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
     GucRegistry::define_int_guc(
         "vectorize.semantic_weight",
         "weight for semantic search",
@@ -217,6 +322,37 @@ pub fn init_guc() {
         GucContext::Suset,
         GucFlags::default(),
     );
+
+    GucRegistry::define_bool_guc(
+        "vectorize.embedding_cache",
+        "Enables the embedding cache",
+        "Reuse the embeddings of previously seen input text, per model, instead of calling the model provider again. Default is off.",
+        &EMBEDDING_CACHE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.embedding_cache_ttl_sec",
+        "Time to live, in seconds, of cached embeddings",
+        "Number of seconds a cached embedding is reused before it expires. Default is 7 days.",
+        &EMBEDDING_CACHE_TTL_SEC,
+        1,
+        i32::MAX,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.embedding_cache_max_size",
+        "Maximum number of cached embeddings",
+        "Number of embeddings kept in the cache, across all models. The least recently used embeddings are removed first. Default is 100000.",
+        &EMBEDDING_CACHE_MAX_SIZE,
+        1,
+        i32::MAX,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
 }
 
 /// a convenience function to get this project's GUCs
@@ -237,6 +373,14 @@ pub fn get_guc(guc: VectorizeGuc) -> Option<String> {
         VectorizeGuc::PortkeyServiceUrl => PORTKEY_SERVICE_URL.get(),
         VectorizeGuc::VoyageApiKey => VOYAGE_API_KEY.get(),
         VectorizeGuc::VoyageServiceUrl => VOYAGE_SERVICE_URL.get(),
+        VectorizeGuc::AzureOpenAIServiceUrl => AZURE_OPENAI_SERVICE_URL.get(),
+        VectorizeGuc::AzureOpenAIKey => AZURE_OPENAI_KEY.get(),
+        VectorizeGuc::AzureOpenAIApiVersion => AZURE_OPENAI_API_VERSION.get(),
+        VectorizeGuc::GeminiApiKey => GEMINI_API_KEY.get(),
+        VectorizeGuc::GeminiServiceUrl => GEMINI_SERVICE_URL.get(),
+        VectorizeGuc::TeiServiceUrl => TEI_SERVICE_URL.get(),
+        VectorizeGuc::TeiApiKey => TEI_API_KEY.get(),
+        VectorizeGuc::LocalModelDir => LOCAL_MODEL_DIR.get(),
         VectorizeGuc::TextIndexType => FTS_INDEX_TYPE.get(),
     };
     if let Some(cstr) = val {
@@ -261,42 +405,140 @@ fn handle_cstr(cstr: &CStr) -> Result<String> {
     }
 }
 
-pub fn get_guc_configs(model_source: &ModelSource) -> ModelGucConfig {
-    match model_source {
+pub fn get_guc_configs(model: &Model) -> ModelGucConfig {
+    if let Some(guc_configs) = get_custom_provider_configs(model) {
+        return guc_configs;
+    }
+    match model.source {
         ModelSource::OpenAI => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::OpenAIKey),
             service_url: get_guc(VectorizeGuc::OpenAIServiceUrl),
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Tembo => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::TemboAIKey),
             service_url: get_guc(VectorizeGuc::TemboServiceUrl),
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::SentenceTransformers => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::EmbeddingServiceApiKey),
             service_url: get_guc(VectorizeGuc::EmbeddingServiceUrl),
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Cohere => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::CohereApiKey),
             service_url: None,
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Ollama => ModelGucConfig {
             api_key: None,
             service_url: get_guc(VectorizeGuc::OllamaServiceUrl),
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Portkey => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::PortkeyApiKey),
             service_url: get_guc(VectorizeGuc::PortkeyServiceUrl),
             virtual_key: get_guc(VectorizeGuc::PortkeyVirtualKey),
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
         ModelSource::Voyage => ModelGucConfig {
             api_key: get_guc(VectorizeGuc::VoyageApiKey),
             service_url: get_guc(VectorizeGuc::VoyageServiceUrl),
             virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Azure => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::AzureOpenAIKey),
+            service_url: get_guc(VectorizeGuc::AzureOpenAIServiceUrl),
+            virtual_key: None,
+            api_version: get_guc(VectorizeGuc::AzureOpenAIApiVersion),
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Gemini => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::GeminiApiKey),
+            service_url: get_guc(VectorizeGuc::GeminiServiceUrl),
+            virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
+        },
+        ModelSource::Tei => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::TeiApiKey),
+            service_url: get_guc(VectorizeGuc::TeiServiceUrl),
+            virtual_key: None,
+            api_version: None,
+            truncate: Some(TEI_TRUNCATE.get()),
+            normalize: Some(TEI_NORMALIZE.get()),
+            custom_provider: None,
+        },
+        ModelSource::Local => ModelGucConfig {
+            api_key: None,
+            service_url: get_guc(VectorizeGuc::LocalModelDir),
+            virtual_key: None,
+            api_version: None,
+            truncate: None,
+            normalize: None,
+            custom_provider: None,
         },
     }
 }
+
+// the configuration of the provider registered in vectorize.model_providers for the model, if any
+// the api key is read from the GUC that the provider references
+fn get_custom_provider_configs(model: &Model) -> Option<ModelGucConfig> {
+    let name = model.custom_provider_name()?;
+    let configs: Result<Option<ModelGucConfig>> = Spi::connect(|c| {
+        let rows = c.select(
+            "SELECT base_url, api_format, auth_header, auth_scheme, current_setting(api_key_guc, true) AS api_key
+            FROM vectorize.model_providers
+            WHERE name = $1",
+            None,
+            &[name.as_str().into()],
+        )?;
+        let mut configs = None;
+        for row in rows {
+            let base_url: Option<String> = row["base_url"].value()?;
+            let api_format: Option<String> = row["api_format"].value()?;
+            configs = Some(custom_provider_configs(
+                name.clone(),
+                base_url.ok_or_else(|| anyhow!("base_url of model provider {name} is null"))?,
+                &api_format
+                    .ok_or_else(|| anyhow!("api_format of model provider {name} is null"))?,
+                row["auth_header"].value()?,
+                row["auth_scheme"].value()?,
+                row["api_key"].value()?,
+            )?);
+        }
+        Ok(configs)
+    });
+    configs.unwrap_or_else(|e| error!("failed to fetch model provider: {}", e))
+}
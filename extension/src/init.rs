@@ -4,7 +4,9 @@ use pgrx::prelude::*;
 use anyhow::{anyhow, Context, Result};
 use vectorize_core::guc::VectorizeGuc;
 use vectorize_core::types::IndexDist;
-use vectorize_core::types::{JobParams, TableMethod, VECTORIZE_SCHEMA};
+use vectorize_core::types::{
+    shadow_job_name, EmbeddingStorage, JobParams, TableMethod, VECTORIZE_SCHEMA,
+};
 
 pub static VECTORIZE_QUEUE: &str = "vectorize_jobs";
 
@@ -52,6 +54,14 @@ pub fn init_cron(cron: &str, job_name: &str) -> Result<Option<i64>, spi::Error>
     Spi::get_one(&cronjob)
 }
 
+pub fn unschedule_cron(job_name: &str) -> Result<(), spi::Error> {
+    // no-op when the job was never scheduled, e.g. realtime or manual jobs
+    Spi::run_with_args(
+        "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = $1;",
+        &[job_name.into()],
+    )
+}
+
 pub fn init_job_query() -> String {
     format!(
         "
@@ -73,10 +83,15 @@ fn drop_project_view(job_name: &str) -> String {
 }
 
 /// creates a project view over a source table and the embeddings table
+/// the view of a chunked job has a row for each chunk
 fn create_project_view(job_name: &str, job_params: &JobParams) -> String {
+    let chunk_cols = match job_params.chunking {
+        Some(_) => "t1.chunk_index, t1.chunk, ",
+        None => "",
+    };
     format!(
         "CREATE VIEW vectorize.{job_name}_view as 
-        SELECT t0.*, t1.embeddings, t1.updated_at as embeddings_updated_at
+        SELECT t0.*, {chunk_cols}t1.embeddings, t1.updated_at as embeddings_updated_at
         FROM {schema}.{table} t0
         INNER JOIN vectorize._embeddings_{job_name} t1
             ON t0.{primary_key} = t1.{primary_key};
@@ -118,7 +133,7 @@ pub fn init_embedding_table_query(
     let src_schema = job_params.schema.clone();
     let src_table = job_params.relation.clone();
 
-    let col_type = format!("vector({model_dim})");
+    let col_type = job_params.storage.column_type(model_dim);
 
     let (index_schema, table_name, embeddings_col) = match job_params.table_method {
         TableMethod::append => {
@@ -136,20 +151,15 @@ pub fn init_embedding_table_query(
         }
     };
 
-    let index_stmt = match index_type {
-        IndexDist::pgv_hnsw_cosine => {
-            create_hnsw_cosine_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::vsc_diskann_cosine => {
-            create_diskann_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::pgv_hnsw_ip => {
-            create_hnsw_ip_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::pgv_hnsw_l2 => {
-            create_hnsw_l2_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-    };
+    let index_stmt = create_embedding_index(
+        job_name,
+        index_type,
+        &job_params.storage,
+        model_dim,
+        &index_schema,
+        &table_name,
+        &embeddings_col,
+    );
 
     match job_params.table_method {
         TableMethod::append => {
@@ -160,15 +170,10 @@ pub fn init_embedding_table_query(
         }
         TableMethod::join => {
             let mut stmts = vec![
-                create_embedding_table(
-                    job_name,
-                    &job_params.primary_key,
-                    &job_params.pkey_type,
-                    &col_type,
-                    &src_schema,
-                    &src_table,
-                ),
+                create_embedding_table(job_name, job_params, &col_type),
                 index_stmt,
+                create_delete_trigger(job_name, job_params),
+                create_truncate_trigger(job_name, job_params),
                 // also create a view over the source table and the embedding table, for this project
                 drop_project_view(job_name),
                 create_project_view(job_name, job_params),
@@ -184,56 +189,243 @@ pub fn init_embedding_table_query(
     }
 }
 
-fn create_embedding_table(
+/// drops everything init_embedding_table_query created for a job
+/// indexes on the embeddings are dropped along with their table or column
+pub fn drop_embedding_table_query(job_name: &str, job_params: &JobParams) -> Vec<String> {
+    check_input(job_name).expect("invalid job name");
+    match job_params.table_method {
+        TableMethod::append => vec![format!(
+            "ALTER TABLE {schema}.{table}
+            DROP COLUMN IF EXISTS {job_name}_embeddings,
+            DROP COLUMN IF EXISTS {job_name}_content_hash,
+            DROP COLUMN IF EXISTS {job_name}_updated_at;",
+            schema = job_params.schema,
+            table = job_params.relation,
+        )],
+        TableMethod::join => vec![
+            format!(
+                "DROP TRIGGER IF EXISTS vectorize_delete_trigger_{job_name} ON {schema}.{table};",
+                schema = job_params.schema,
+                table = job_params.relation,
+            ),
+            format!(
+                "DROP TRIGGER IF EXISTS vectorize_truncate_trigger_{job_name} ON {schema}.{table};",
+                schema = job_params.schema,
+                table = job_params.relation,
+            ),
+            drop_project_view(job_name),
+            format!("DROP TABLE IF EXISTS {VECTORIZE_SCHEMA}._embeddings_{job_name};"),
+            // only exists while the job is changing transformers
+            format!(
+                "DROP TABLE IF EXISTS {VECTORIZE_SCHEMA}._embeddings_{shadow};",
+                shadow = shadow_job_name(job_name),
+            ),
+            // full text search index, only exists when experimental_fts_index_type is set
+            format!(
+                "DROP INDEX IF EXISTS {schema}.{job_name}_idx;",
+                schema = job_params.schema,
+            ),
+        ],
+    }
+}
+
+/// creates the shadow embeddings table that a join job is backfilled into while changing transformers
+pub fn init_shadow_table_query(
     job_name: &str,
-    join_key: &str,
-    join_key_type: &str,
-    col_type: &str,
-    src_schema: &str,
-    src_table: &str,
-) -> String {
+    job_params: &JobParams,
+    index_type: &IndexDist,
+    model_dim: u32,
+) -> Vec<String> {
+    check_input(job_name).expect("invalid job name");
+    let shadow = shadow_job_name(job_name);
+    vec![
+        // leftovers from an abandoned migration
+        format!("DROP TABLE IF EXISTS {VECTORIZE_SCHEMA}._embeddings_{shadow};"),
+        create_embedding_table(
+            &shadow,
+            job_params,
+            &job_params.storage.column_type(model_dim),
+        ),
+        create_embedding_index(
+            &shadow,
+            index_type,
+            &job_params.storage,
+            model_dim,
+            VECTORIZE_SCHEMA,
+            &format!("_embeddings_{shadow}"),
+            "embeddings",
+        ),
+    ]
+}
+
+/// replaces a join job's embeddings table with its shadow table
+/// the view is recreated since it references the dropped table
+pub fn swap_shadow_table_query(
+    job_name: &str,
+    job_params: &JobParams,
+    index_type: &IndexDist,
+) -> Vec<String> {
+    check_input(job_name).expect("invalid job name");
+    let shadow = shadow_job_name(job_name);
+    vec![
+        // waits for in-flight searches on the old embeddings
+        format!("LOCK TABLE {VECTORIZE_SCHEMA}._embeddings_{job_name} IN ACCESS EXCLUSIVE MODE;"),
+        drop_project_view(job_name),
+        format!("DROP TABLE {VECTORIZE_SCHEMA}._embeddings_{job_name};"),
+        format!(
+            "ALTER TABLE {VECTORIZE_SCHEMA}._embeddings_{shadow} RENAME TO _embeddings_{job_name};"
+        ),
+        // constraint names must be free for the next migration's shadow table
+        format!(
+            "ALTER TABLE {VECTORIZE_SCHEMA}._embeddings_{job_name}
+            RENAME CONSTRAINT _embeddings_{shadow}_key TO _embeddings_{job_name}_key;"
+        ),
+        format!(
+            "ALTER INDEX {VECTORIZE_SCHEMA}.{shadow_idx} RENAME TO {idx};",
+            shadow_idx = embedding_index_name(&shadow, index_type),
+            idx = embedding_index_name(job_name, index_type),
+        ),
+        create_project_view(job_name, job_params),
+    ]
+}
+
+// columns of the unique constraint of a join job's embeddings table
+// chunked jobs store a row for each chunk of a source row
+fn unique_key_columns(job_params: &JobParams) -> Vec<&str> {
+    match job_params.chunking {
+        Some(_) => vec![job_params.primary_key.as_str(), "chunk_index"],
+        None => vec![job_params.primary_key.as_str()],
+    }
+}
+
+// rows deleted from the source table are removed by the job's delete trigger
+// a foreign key to the source table would prevent truncating it
+// the unique constraint is named explicitly, so that a shadow table's constraint can be renamed
+// when it is swapped in, since generated names are truncated at 63 bytes
+fn create_embedding_table(job_name: &str, job_params: &JobParams, col_type: &str) -> String {
+    let join_key = &job_params.primary_key;
+    let join_key_type = &job_params.pkey_type;
+    let chunk_cols = match job_params.chunking {
+        Some(_) => {
+            "chunk_index INT NOT NULL,
+            chunk TEXT NOT NULL,
+            "
+        }
+        None => "",
+    };
     format!(
         "CREATE TABLE IF NOT EXISTS vectorize._embeddings_{job_name} (
-            {join_key} {join_key_type} UNIQUE NOT NULL,
-            embeddings {col_type} NOT NULL,
+            {join_key} {join_key_type} NOT NULL,
+            {chunk_cols}embeddings {col_type} NOT NULL,
+            content_hash TEXT,
             updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
-            FOREIGN KEY ({join_key}) REFERENCES {src_schema}.{src_table} ({join_key}) ON DELETE CASCADE
+            CONSTRAINT _embeddings_{job_name}_key UNIQUE ({unique_key})
         );
         ",
-        job_name = job_name,
-        join_key = join_key,
-        join_key_type = join_key_type,
-        col_type = col_type,
-        src_schema = src_schema,
-        src_table = src_table,
+        unique_key = unique_key_columns(job_params).join(", "),
     )
 }
 
-fn create_hnsw_l2_index(job_name: &str, schema: &str, table: &str, embedding_col: &str) -> String {
+// removes the embeddings of rows deleted from the source table
+fn create_delete_trigger(job_name: &str, job_params: &JobParams) -> String {
     format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_l2_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_l2_ops);
-        ",
+        "CREATE OR REPLACE TRIGGER vectorize_delete_trigger_{job_name}
+        AFTER DELETE ON {schema}.{table}
+        REFERENCING OLD TABLE AS old_table
+        FOR EACH STATEMENT
+        EXECUTE FUNCTION vectorize._handle_source_delete('{job_name}', '{pkey}');",
+        schema = job_params.schema,
+        table = job_params.relation,
+        pkey = job_params.primary_key,
     )
 }
 
-fn create_hnsw_ip_index(job_name: &str, schema: &str, table: &str, embedding_col: &str) -> String {
+// clears the embeddings when the source table is truncated
+// reloaded rows are then picked up by the job's schedule like any new row
+fn create_truncate_trigger(job_name: &str, job_params: &JobParams) -> String {
     format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_ip_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_ip_ops);
-        ",
+        "CREATE OR REPLACE TRIGGER vectorize_truncate_trigger_{job_name}
+        AFTER TRUNCATE ON {schema}.{table}
+        FOR EACH STATEMENT
+        EXECUTE FUNCTION vectorize._handle_source_truncate('{job_name}');",
+        schema = job_params.schema,
+        table = job_params.relation,
     )
 }
 
-fn create_hnsw_cosine_index(
+pub fn embedding_index_name(job_name: &str, index_type: &IndexDist) -> String {
+    match index_type {
+        IndexDist::pgv_hnsw_cosine => format!("{job_name}_hnsw_cos_idx"),
+        IndexDist::vsc_diskann_cosine => format!("{job_name}_diskann_idx"),
+        IndexDist::pgv_hnsw_ip => format!("{job_name}_hnsw_ip_idx"),
+        IndexDist::pgv_hnsw_l2 => format!("{job_name}_hnsw_l2_idx"),
+    }
+}
+
+fn create_embedding_index(
     job_name: &str,
+    index_type: &IndexDist,
+    storage: &EmbeddingStorage,
+    model_dim: u32,
     schema: &str,
     table: &str,
     embedding_col: &str,
 ) -> String {
+    let column = |distance: &str| hnsw_index_column(storage, distance, embedding_col, model_dim);
+    match index_type {
+        IndexDist::pgv_hnsw_cosine => {
+            create_hnsw_cosine_index(job_name, schema, table, &column("cosine"))
+        }
+        IndexDist::vsc_diskann_cosine => {
+            create_diskann_index(job_name, schema, table, embedding_col)
+        }
+        IndexDist::pgv_hnsw_ip => create_hnsw_ip_index(job_name, schema, table, &column("ip")),
+        IndexDist::pgv_hnsw_l2 => create_hnsw_l2_index(job_name, schema, table, &column("l2")),
+    }
+}
+
+/// the binary quantization of an embedding, which bit storage is indexed and searched by
+pub fn binary_quantize(embedding: &str, model_dim: u32) -> String {
+    format!("binary_quantize({embedding})::bit({model_dim})")
+}
+
+// the indexed expression of the embeddings column, and its operator class
+// the index of bit storage finds candidates by hamming distance, whatever the distance of the job
+fn hnsw_index_column(
+    storage: &EmbeddingStorage,
+    distance: &str,
+    embedding_col: &str,
+    model_dim: u32,
+) -> String {
+    match storage {
+        EmbeddingStorage::bit => format!(
+            "({}) bit_hamming_ops",
+            binary_quantize(embedding_col, model_dim)
+        ),
+        _ => format!("{embedding_col} {}_{distance}_ops", storage.pg_type()),
+    }
+}
+
+fn create_hnsw_l2_index(job_name: &str, schema: &str, table: &str, column: &str) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_l2_idx ON {schema}.{table}
+        USING hnsw ({column});
+        ",
+    )
+}
+
+fn create_hnsw_ip_index(job_name: &str, schema: &str, table: &str, column: &str) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_ip_idx ON {schema}.{table}
+        USING hnsw ({column});
+        ",
+    )
+}
+
+fn create_hnsw_cosine_index(job_name: &str, schema: &str, table: &str, column: &str) -> String {
     format!(
         "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_cos_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_cosine_ops);
+        USING hnsw ({column});
         ",
     )
 }
@@ -261,6 +453,7 @@ fn append_embedding_column(job_name: &str, schema: &str, table: &str, col_type:
             )
             THEN ALTER TABLE {schema}.{table}
             ADD COLUMN {job_name}_embeddings {col_type},
+            ADD COLUMN {job_name}_content_hash TEXT,
             ADD COLUMN {job_name}_updated_at TIMESTAMP WITH TIME ZONE;
            END IF;
         END
@@ -269,6 +462,23 @@ fn append_embedding_column(job_name: &str, schema: &str, table: &str, col_type:
     )
 }
 
+// the access method of an index, e.g. gin, or None when the index does not exist
+pub fn get_index_type(schema: &str, index: &str) -> Result<Option<String>> {
+    Ok(Spi::get_one_with_args(
+        "
+        SELECT am.amname::text
+        FROM pg_class c
+        INNER JOIN pg_namespace n ON n.oid = c.relnamespace
+        INNER JOIN pg_am am ON am.oid = c.relam
+        WHERE
+            n.nspname = $1
+            AND c.relname = $2
+            AND c.relkind = 'i'
+        ",
+        &[schema.into(), index.into()],
+    )?)
+}
+
 pub fn get_column_datatype(schema: &str, table: &str, column: &str) -> Result<String> {
     Spi::get_one_with_args(
         "
@@ -1,12 +1,21 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
-use crate::executor::{create_batches, new_rows_query, new_rows_query_join};
-use crate::guc::BATCH_SIZE;
-use crate::init::VECTORIZE_QUEUE;
+use crate::executor::{create_batches, embedded_rows_source, new_rows_query, new_rows_query_join};
+use crate::guc::{get_guc_configs, BATCH_SIZE};
+use crate::init::{
+    drop_embedding_table_query, get_column_datatype, get_index_type, init_cron, init_index_query,
+    init_shadow_table_query, swap_shadow_table_query, unschedule_cron, VECTORIZE_QUEUE,
+};
+use crate::query::check_input;
+use crate::search::{embedding_dim, init_transformer, transformer_dim};
+use crate::util::get_vectorize_meta_spi;
 use pgrx::prelude::*;
 use tiktoken_rs::cl100k_base;
 use vectorize_core::transformers::types::Inputs;
-use vectorize_core::types::{JobMessage, JobParams, TableMethod};
+use vectorize_core::types::{
+    shadow_job_name, DiskAnnSearchParams, IndexDist, InputPrefixes, JobMessage, JobParams, Model,
+    ModelMigration, TableMethod, VectorizeMeta,
+};
 
 static TRIGGER_FN_PREFIX: &str = "vectorize.handle_update_";
 
@@ -45,6 +54,34 @@ EXECUTE FUNCTION vectorize.handle_update_{job_name}();",
     )
 }
 
+// drops the triggers and trigger handler of a realtime job
+pub fn drop_event_triggers(job_name: &str, schema: &str, table_name: &str) -> Vec<String> {
+    vec![
+        format!(
+            "DROP TRIGGER IF EXISTS vectorize_insert_trigger_{job_name} ON {schema}.{table_name};"
+        ),
+        format!(
+            "DROP TRIGGER IF EXISTS vectorize_update_trigger_{job_name} ON {schema}.{table_name};"
+        ),
+        format!("DROP FUNCTION IF EXISTS {TRIGGER_FN_PREFIX}{job_name}();"),
+    ]
+}
+
+// enables or disables the triggers of a realtime job, without dropping them
+pub fn alter_event_triggers(
+    job_name: &str,
+    schema: &str,
+    table_name: &str,
+    enable: bool,
+) -> String {
+    let action = if enable { "ENABLE" } else { "DISABLE" };
+    format!(
+        "ALTER TABLE {schema}.{table_name}
+        {action} TRIGGER vectorize_insert_trigger_{job_name},
+        {action} TRIGGER vectorize_update_trigger_{job_name};"
+    )
+}
+
 // creates batches of embedding jobs
 // typically used on table init
 pub fn initalize_table_job(job_name: &str, job_params: &JobParams) -> Result<()> {
@@ -53,10 +90,29 @@ pub fn initalize_table_job(job_name: &str, job_params: &JobParams) -> Result<()>
         TableMethod::append => new_rows_query(job_name, job_params),
         TableMethod::join => new_rows_query_join(job_name, job_params),
     };
+    enqueue_rows(job_name, &rows_need_update_query, false)?;
+    Ok(())
+}
+
+// creates batches of embedding jobs for every row in the source table
+pub fn enqueue_all_rows(job_name: &str, job_params: &JobParams) -> Result<()> {
+    // without an update column, new_rows_query selects the entire source table
+    let all_rows = JobParams {
+        update_time_col: None,
+        ..job_params.clone()
+    };
+    enqueue_rows(job_name, &new_rows_query(job_name, &all_rows), false)?;
+    Ok(())
+}
+
+// sends the rows returned by a query to the queue, in batches
+// the query must return `record_id` and `input_text` columns
+// returns the number of rows sent
+fn enqueue_rows(job_name: &str, rows_need_update_query: &str, shadow: bool) -> Result<usize> {
     let mut inputs: Vec<Inputs> = Vec::new();
     let bpe = cl100k_base().unwrap();
     let _: Result<_, spi::Error> = Spi::connect(|c| {
-        let rows = c.select(&rows_need_update_query, None, &[])?;
+        let rows = c.select(rows_need_update_query, None, &[])?;
         for row in rows {
             let ipt = row["input_text"]
                 .value::<String>()?
@@ -73,6 +129,7 @@ pub fn initalize_table_job(job_name: &str, job_params: &JobParams) -> Result<()>
         Ok(())
     });
 
+    let num_rows = inputs.len();
     let max_batch_size = BATCH_SIZE.get();
     let batches = create_batches(inputs, max_batch_size);
 
@@ -80,6 +137,7 @@ pub fn initalize_table_job(job_name: &str, job_params: &JobParams) -> Result<()>
         let job_message = JobMessage {
             job_name: job_name.to_string(),
             record_ids: b.iter().map(|i| i.record_id.clone()).collect(),
+            shadow,
         };
         let query = "select pgmq.send($1, $2::jsonb);";
         let _ran: Result<_, spi::Error> = Spi::connect_mut(|c| {
@@ -97,12 +155,562 @@ pub fn initalize_table_job(job_name: &str, job_params: &JobParams) -> Result<()>
             Ok(())
         });
     }
+    Ok(num_rows)
+}
+
+fn get_job_params(job_name: &str) -> Result<(VectorizeMeta, JobParams)> {
+    let meta = get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params.clone())?;
+    Ok((meta, job_params))
+}
+
+fn update_job_params(job_name: &str, job_params: &JobParams) -> Result<()> {
+    let params = pgrx::JsonB(serde_json::to_value(job_params)?);
+    Spi::run_with_args(
+        "UPDATE vectorize.job SET params = $2 WHERE name = $1;",
+        &[job_name.into(), params.into()],
+    )?;
+    Ok(())
+}
+
+// starts enqueueing embedding work according to the job's schedule
+fn activate_schedule(job_name: &str, job_params: &JobParams) -> Result<()> {
+    match job_params.schedule.as_str() {
+        "realtime" => {
+            let stmts = vec![
+                create_trigger_handler(job_name, &job_params.primary_key),
+                create_event_trigger(job_name, &job_params.schema, &job_params.relation, "INSERT"),
+                create_event_trigger(job_name, &job_params.schema, &job_params.relation, "UPDATE"),
+            ];
+            for q in stmts {
+                Spi::run(&q)?;
+            }
+        }
+        "manual" => (),
+        schedule => {
+            init_cron(schedule, job_name)?;
+        }
+    }
+    Ok(())
+}
+
+// stops enqueueing embedding work for the job's schedule
+fn deactivate_schedule(job_name: &str, job_params: &JobParams) -> Result<()> {
+    match job_params.schedule.as_str() {
+        "realtime" => {
+            for q in drop_event_triggers(job_name, &job_params.schema, &job_params.relation) {
+                Spi::run(&q)?;
+            }
+        }
+        "manual" => (),
+        _ => unschedule_cron(job_name)?,
+    }
+    Ok(())
+}
+
+/// removes a job, its triggers or cron schedule, its embeddings, indexes and any queued messages
+pub fn drop_job(job_name: &str) -> Result<()> {
+    let (_meta, job_params) = get_job_params(job_name)?;
+
+    deactivate_schedule(job_name, &job_params)?;
+    for q in drop_embedding_table_query(job_name, &job_params) {
+        Spi::run(&q)?;
+    }
+    Spi::run_with_args(
+        &format!("DELETE FROM pgmq.q_{VECTORIZE_QUEUE} WHERE message->>'job_name' = $1;"),
+        &[job_name.into()],
+    )?;
+    Spi::run_with_args(
+        "DELETE FROM vectorize.job WHERE name = $1;",
+        &[job_name.into()],
+    )?;
+    Ok(())
+}
+
+/// stops a job from enqueueing new embedding work
+/// messages already in the queue are still processed
+pub fn pause_job(job_name: &str) -> Result<()> {
+    let (_meta, mut job_params) = get_job_params(job_name)?;
+    if job_params.paused {
+        return Ok(());
+    }
+    match job_params.schedule.as_str() {
+        "realtime" => Spi::run(&alter_event_triggers(
+            job_name,
+            &job_params.schema,
+            &job_params.relation,
+            false,
+        ))?,
+        "manual" => (),
+        _ => unschedule_cron(job_name)?,
+    }
+    job_params.paused = true;
+    job_params.paused_at = Some(chrono::Utc::now());
+    update_job_params(job_name, &job_params)
+}
+
+/// restarts a paused job, and enqueues rows inserted or updated while it was paused
+pub fn resume_job(job_name: &str) -> Result<()> {
+    let (_meta, mut job_params) = get_job_params(job_name)?;
+    if !job_params.paused {
+        return Ok(());
+    }
+    match job_params.schedule.as_str() {
+        "realtime" => Spi::run(&alter_event_triggers(
+            job_name,
+            &job_params.schema,
+            &job_params.relation,
+            true,
+        ))?,
+        "manual" => (),
+        schedule => {
+            init_cron(schedule, job_name)?;
+        }
+    }
+    let paused_at = job_params.paused_at.take();
+    job_params.paused = false;
+    update_job_params(job_name, &job_params)?;
+    // catch up on rows inserted or updated while the job was paused
+    match (&job_params.update_time_col, paused_at) {
+        (Some(update_col), Some(paused_at)) => {
+            let rows_need_update_query =
+                updated_rows_query(job_name, &job_params, update_col, &paused_at.to_rfc3339());
+            enqueue_rows(job_name, &rows_need_update_query, false)?;
+            Ok(())
+        }
+        // every row is enqueued, and the worker skips rows whose content hash is unchanged
+        _ => enqueue_all_rows(job_name, &job_params),
+    }
+}
+
+// rows updated since a point in time, and for the join method rows missing from the embeddings table
+fn updated_rows_query(
+    job_name: &str,
+    job_params: &JobParams,
+    update_col: &str,
+    since: &str,
+) -> String {
+    // without an update column, the queries select every row, or every missing row
+    let base_params = JobParams {
+        update_time_col: None,
+        ..job_params.clone()
+    };
+    match job_params.table_method {
+        TableMethod::append => format!(
+            "{base_query} WHERE {update_col} > '{since}'::timestamptz",
+            base_query = new_rows_query(job_name, &base_params),
+        ),
+        TableMethod::join => format!(
+            "{base_query} OR t0.{update_col} > '{since}'::timestamptz",
+            base_query = new_rows_query_join(job_name, &base_params),
+        ),
+    }
+}
+
+/// updates the schedule, columns, search parameters, or input prefixes of an existing job
+/// arguments that are None are left unchanged
+pub fn alter_job(
+    job_name: &str,
+    schedule: Option<String>,
+    columns: Option<Vec<String>>,
+    update_col: Option<String>,
+    diskann_search: DiskAnnSearchParams,
+    input_prefixes: InputPrefixes,
+) -> Result<()> {
+    let (meta, mut job_params) = get_job_params(job_name)?;
+
+    if let Some(col) = update_col {
+        let update_time_dtype =
+            get_column_datatype(&job_params.schema, &job_params.relation, &col)?;
+        if update_time_dtype != "timestamp with time zone" {
+            return Err(anyhow!(
+                "`update_col` must be of type `timestamp with time zone`. column: '{col}' is of type `{update_time_dtype}`"
+            ));
+        }
+        job_params.update_time_col = Some(col);
+    }
+
+    let columns_changed = match columns {
+        Some(cols) if cols != job_params.columns => {
+            for col in &cols {
+                check_input(col)?;
+                get_column_datatype(&job_params.schema, &job_params.relation, col)?;
+            }
+            job_params.columns = cols;
+            true
+        }
+        _ => false,
+    };
+
+    if !diskann_search.is_empty() {
+        if !matches!(meta.index_dist_type, IndexDist::vsc_diskann_cosine) {
+            return Err(anyhow!(
+                "diskann search parameters require index_dist_type `vsc_diskann_cosine`, got: `{}`",
+                meta.index_dist_type
+            ));
+        }
+        let current = job_params.diskann_search.clone().unwrap_or_default();
+        job_params.diskann_search = Some(diskann_search.or(&current));
+    }
+
+    let document_prefix_changed = if !input_prefixes.is_empty() {
+        let current = job_params.input_prefixes.clone().unwrap_or_default();
+        let mut prefixes = input_prefixes.or(&current);
+        // an empty prefix removes the job's prefix
+        prefixes.query = prefixes.query.filter(|p| !p.is_empty());
+        prefixes.document = prefixes.document.filter(|p| !p.is_empty());
+        let changed = prefixes.document != current.document;
+        job_params.input_prefixes = (!prefixes.is_empty()).then_some(prefixes);
+        changed
+    } else {
+        false
+    };
+
+    if let Some(new_schedule) = schedule.filter(|s| *s != job_params.schedule) {
+        if new_schedule == "realtime" && job_params.table_method != TableMethod::join {
+            return Err(anyhow!(
+                "realtime schedule is only compatible with the join table method"
+            ));
+        }
+        if new_schedule != "realtime"
+            && new_schedule != "manual"
+            && job_params.update_time_col.is_none()
+        {
+            return Err(anyhow!(
+                "`update_col` is required to move job `{job_name}` to a cron schedule"
+            ));
+        }
+        // a paused job picks up its new schedule when resumed
+        if !job_params.paused {
+            deactivate_schedule(job_name, &job_params)?;
+        }
+        job_params.schedule = new_schedule;
+        if job_params.schedule == "realtime" {
+            // updates are based on triggers in the realtime configuration
+            job_params.update_time_col = None;
+        }
+        if !job_params.paused {
+            activate_schedule(job_name, &job_params)?;
+        }
+    }
+
+    // existing embeddings were generated from the old columns or document prefix
+    // a paused job re-embeds every row when it is resumed
+    let reembed = columns_changed || document_prefix_changed;
+    if reembed && job_params.paused {
+        job_params.paused_at = None;
+    }
+    update_job_params(job_name, &job_params)?;
+
+    if columns_changed {
+        rebuild_fts_index(job_name, &job_params)?;
+    }
+    if reembed && !job_params.paused {
+        enqueue_all_rows(job_name, &job_params)?;
+    }
+    Ok(())
+}
+
+// recreates the full text search index of a job on its current columns, when it has one
+// the index is created with vectorize.experimental_fts_index_type, and keeps its index type
+fn rebuild_fts_index(job_name: &str, job_params: &JobParams) -> Result<()> {
+    let index_name = format!("{job_name}_idx");
+    let Some(index_type) = get_index_type(&job_params.schema, &index_name)? else {
+        return Ok(());
+    };
+    Spi::run(&format!(
+        "DROP INDEX {schema}.{index_name};",
+        schema = job_params.schema
+    ))?;
+    Spi::run(&init_index_query(job_name, &index_type, job_params))?;
     Ok(())
 }
 
+/// starts moving a join job to a new transformer
+/// search keeps using the current embeddings until the shadow table is fully backfilled
+pub fn change_model(job_name: &str, transformer: &Model) -> Result<()> {
+    let (meta, mut job_params) = get_job_params(job_name)?;
+    if job_params.table_method != TableMethod::join {
+        return Err(anyhow!(
+            "changing the transformer is only supported for the join table method"
+        ));
+    }
+    // the vocabulary, and so the dimensions, of sparse jobs are specific to their transformer
+    if job_params.sparse {
+        return Err(anyhow!(
+            "changing the transformer is not supported for sparse jobs"
+        ));
+    }
+    if meta.transformer.fullname == transformer.fullname && job_params.model_migration.is_none() {
+        return Err(anyhow!(
+            "job `{job_name}` already uses transformer `{transformer}`"
+        ));
+    }
+
+    let guc_configs = get_guc_configs(transformer);
+    init_transformer(transformer, &guc_configs)?;
+    let model_dim = transformer_dim(transformer, &guc_configs, false)?;
+    // the new transformer's embeddings are shortened to the job's dimensions as well
+    let model_dim = embedding_dim(model_dim, job_params.dimensions)?;
+    for q in init_shadow_table_query(job_name, &job_params, &meta.index_dist_type, model_dim) {
+        Spi::run(&q)?;
+    }
+
+    job_params.model_migration = Some(ModelMigration {
+        transformer: transformer.clone(),
+        api_key: guc_configs.api_key,
+    });
+    update_job_params(job_name, &job_params)?;
+
+    // nothing to backfill, e.g. an empty source table
+    if backfill_shadow_table(job_name, &job_params)? == 0 {
+        finalize_model_change(job_name, true)?;
+    }
+    Ok(())
+}
+
+// enqueues the rows that are missing from the shadow table, or stale in it
+fn backfill_shadow_table(job_name: &str, job_params: &JobParams) -> Result<usize> {
+    let rows_need_update_query = new_rows_query_join(&shadow_job_name(job_name), job_params);
+    enqueue_rows(job_name, &rows_need_update_query, true)
+}
+
+// whether rows are missing from the shadow table, or stale in it
+fn shadow_rows_missing(job_name: &str, job_params: &JobParams) -> Result<bool> {
+    let rows_need_update_query = new_rows_query_join(&shadow_job_name(job_name), job_params);
+    let missing = Spi::get_one(&format!("SELECT EXISTS ({rows_need_update_query})"))?;
+    Ok(missing.unwrap_or(false))
+}
+
+/// swaps in the shadow table once it holds embeddings for every row
+/// when enqueue_missing is set, rows that are still missing are enqueued again, and the cutover is
+/// retried after they are processed
+/// returns whether the cutover happened
+pub fn finalize_model_change(job_name: &str, enqueue_missing: bool) -> Result<bool> {
+    // serializes concurrent calls on the job's row
+    Spi::run_with_args(
+        "SELECT 1 FROM vectorize.job WHERE name = $1 FOR UPDATE;",
+        &[job_name.into()],
+    )?;
+    let (meta, mut job_params) = get_job_params(job_name)?;
+    let Some(migration) = job_params.model_migration.take() else {
+        return Ok(false);
+    };
+    let backfilled = if enqueue_missing {
+        backfill_shadow_table(job_name, &job_params)? == 0
+    } else {
+        !shadow_rows_missing(job_name, &job_params)?
+    };
+    if !backfilled {
+        return Ok(false);
+    }
+
+    for q in swap_shadow_table_query(job_name, &job_params, &meta.index_dist_type) {
+        Spi::run(&q)?;
+    }
+    if let Some(api_key) = migration.api_key {
+        job_params.api_key = Some(api_key);
+    }
+    let params = pgrx::JsonB(serde_json::to_value(&job_params)?);
+    Spi::run_with_args(
+        "UPDATE vectorize.job SET transformer = $2, params = $3 WHERE name = $1;",
+        &[
+            job_name.into(),
+            migration.transformer.to_string().into(),
+            params.into(),
+        ],
+    )?;
+    Ok(true)
+}
+
+/// abandons a job's change of transformer, e.g. when rows of the backfill were dead-lettered
+/// drops the shadow table, so the job keeps its current transformer and embeddings
+/// backfill messages still in the queue are discarded by the worker
+/// returns whether a change was in progress
+pub fn abort_model_change(job_name: &str) -> Result<bool> {
+    check_input(job_name)?;
+    // serializes with finalize_model_change on the job's row
+    Spi::run_with_args(
+        "SELECT 1 FROM vectorize.job WHERE name = $1 FOR UPDATE;",
+        &[job_name.into()],
+    )?;
+    let (_meta, mut job_params) = get_job_params(job_name)?;
+    if job_params.model_migration.take().is_none() {
+        return Ok(false);
+    }
+    Spi::run(&format!(
+        "DROP TABLE IF EXISTS vectorize._embeddings_{};",
+        shadow_job_name(job_name)
+    ))?;
+    update_job_params(job_name, &job_params)?;
+    Ok(true)
+}
+
+/// reconciles a job's embeddings with its source table
+/// removes embeddings of rows that no longer exist, then enqueues rows with missing or stale embeddings
+/// returns the number of embeddings removed and the number of rows enqueued
+pub fn resync(job_name: &str) -> Result<(i64, usize)> {
+    let (_meta, job_params) = get_job_params(job_name)?;
+    if job_params.paused {
+        return Err(anyhow!(
+            "job `{job_name}` is paused, resuming it enqueues any missing rows"
+        ));
+    }
+    match job_params.table_method {
+        TableMethod::join => {
+            let removed: i64 = Spi::get_one(&format!(
+                "WITH removed AS (
+                    DELETE FROM vectorize._embeddings_{job_name} e
+                    WHERE NOT EXISTS (
+                        SELECT 1 FROM {schema}.{table} s WHERE s.{pkey} = e.{pkey}
+                    )
+                    RETURNING 1
+                )
+                SELECT COUNT(*) FROM removed;",
+                schema = job_params.schema,
+                table = job_params.relation,
+                pkey = job_params.primary_key,
+            ))?
+            .unwrap_or(0);
+            let enqueued =
+                enqueue_rows(job_name, &new_rows_query_join(job_name, &job_params), false)?;
+            Ok((removed, enqueued))
+        }
+        TableMethod::append => {
+            // embeddings live on the source rows, so there is nothing to remove
+            let rows_need_update_query = match job_params.update_time_col {
+                // also covers rows that were never embedded
+                Some(_) => new_rows_query(job_name, &job_params),
+                None => format!(
+                    "{} WHERE {job_name}_embeddings IS NULL",
+                    new_rows_query(job_name, &job_params)
+                ),
+            };
+            let enqueued = enqueue_rows(job_name, &rows_need_update_query, false)?;
+            Ok((0, enqueued))
+        }
+    }
+}
+
+// progress of a job's embeddings
+pub struct JobStatus {
+    pub job_name: String,
+    pub total_rows: i64,
+    pub embedded_rows: i64,
+    // None when the job has no update column to compare against
+    pub stale_rows: Option<i64>,
+    // rows that were not re-embedded because their input text did not change
+    pub skipped_rows: i64,
+    pub queued_messages: i64,
+    pub failed_messages: i64,
+    pub last_success_at: Option<TimestampWithTimeZone>,
+    pub last_error: Option<String>,
+    pub paused: bool,
+}
+
+// counts the rows of a job's source table and its embeddings
+fn embeddings_status_query(job_name: &str, job_params: &JobParams) -> String {
+    let schema = &job_params.schema;
+    let table = &job_params.relation;
+    match job_params.table_method {
+        TableMethod::join => {
+            let embeddings = embedded_rows_source(job_name, job_params);
+            let stale = match &job_params.update_time_col {
+                Some(updated_at_col) => format!(
+                    "(SELECT COUNT(*) FROM {schema}.{table} t0
+            INNER JOIN {embeddings} t1 ON t0.{pkey} = t1.{pkey}
+            WHERE t0.{updated_at_col} > t1.updated_at)",
+                    pkey = job_params.primary_key,
+                ),
+                None => "NULL::bigint".to_string(),
+            };
+            format!(
+                "SELECT
+            (SELECT COUNT(*) FROM {schema}.{table}) AS total_rows,
+            (SELECT COUNT(*) FROM {embeddings} t1) AS embedded_rows,
+            {stale} AS stale_rows,
+            (SELECT MAX(updated_at) FROM vectorize._embeddings_{job_name}) AS last_success_at;"
+            )
+        }
+        TableMethod::append => {
+            let stale = match &job_params.update_time_col {
+                Some(updated_at_col) => format!(
+                    "COUNT(*) FILTER (
+                WHERE {job_name}_embeddings IS NOT NULL
+                AND {updated_at_col} > {job_name}_updated_at
+            )"
+                ),
+                None => "NULL::bigint".to_string(),
+            };
+            format!(
+                "SELECT
+            COUNT(*) AS total_rows,
+            COUNT({job_name}_embeddings) AS embedded_rows,
+            {stale} AS stale_rows,
+            MAX({job_name}_updated_at) AS last_success_at
+            FROM {schema}.{table};"
+            )
+        }
+    }
+}
+
+/// reports the progress of every job, or of a single job
+pub fn job_status(job_name: Option<&str>) -> Result<Vec<JobStatus>> {
+    let mut job_names: Vec<String> = Vec::new();
+    Spi::connect(|c| {
+        let rows = c.select(
+            "SELECT name FROM vectorize.job WHERE $1::text IS NULL OR name = $1 ORDER BY name;",
+            None,
+            &[job_name.into()],
+        )?;
+        for row in rows {
+            job_names.push(row["name"].value::<String>()?.expect("job name is null"));
+        }
+        Ok::<_, spi::Error>(())
+    })?;
+
+    let mut statuses = Vec::new();
+    for name in job_names {
+        let (_meta, job_params) = get_job_params(&name)?;
+        let status = Spi::connect(|c| {
+            let embeddings = c
+                .select(&embeddings_status_query(&name, &job_params), Some(1), &[])?
+                .first();
+            let messages = c
+                .select(
+                    &format!(
+                        "SELECT
+                    (SELECT COUNT(*) FROM pgmq.q_{VECTORIZE_QUEUE} WHERE message->>'job_name' = $1) AS queued_messages,
+                    (SELECT COUNT(*) FROM vectorize.dead_letter WHERE job_name = $1) AS failed_messages,
+                    (SELECT error FROM vectorize.dead_letter WHERE job_name = $1 ORDER BY failed_at DESC LIMIT 1) AS last_error,
+                    (SELECT skipped_rows FROM vectorize.job_stats WHERE job_name = $1) AS skipped_rows;"
+                    ),
+                    Some(1),
+                    &[name.as_str().into()],
+                )?
+                .first();
+            Ok::<_, spi::Error>(JobStatus {
+                job_name: name.clone(),
+                total_rows: embeddings.get_by_name("total_rows")?.unwrap_or(0),
+                embedded_rows: embeddings.get_by_name("embedded_rows")?.unwrap_or(0),
+                stale_rows: embeddings.get_by_name("stale_rows")?,
+                skipped_rows: messages.get_by_name("skipped_rows")?.unwrap_or(0),
+                queued_messages: messages.get_by_name("queued_messages")?.unwrap_or(0),
+                failed_messages: messages.get_by_name("failed_messages")?.unwrap_or(0),
+                last_success_at: embeddings.get_by_name("last_success_at")?,
+                last_error: messages.get_by_name("last_error")?,
+                paused: job_params.paused,
+            })
+        })?;
+        statuses.push(status);
+    }
+    Ok(statuses)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vectorize_core::chunking::ChunkConfig;
 
     #[test]
     fn test_create_update_trigger_single() {
@@ -137,4 +745,55 @@ EXECUTE FUNCTION vectorize.handle_update_another_job();"
         let result = create_event_trigger(job_name, "myschema", table_name, "INSERT");
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn test_alter_event_triggers() {
+        let expected = "ALTER TABLE myschema.another_table
+        DISABLE TRIGGER vectorize_insert_trigger_another_job,
+        DISABLE TRIGGER vectorize_update_trigger_another_job;";
+        let result = alter_event_triggers("another_job", "myschema", "another_table", false);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_embeddings_status_query() {
+        let job_params = JobParams {
+            schema: "public".to_string(),
+            relation: "products".to_string(),
+            columns: vec!["description".to_string()],
+            update_time_col: Some("last_updated_at".to_string()),
+            table_method: TableMethod::append,
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let append = embeddings_status_query("my_job", &job_params);
+        assert!(append.contains("COUNT(my_job_embeddings) AS embedded_rows"));
+        assert!(append.contains("AND last_updated_at > my_job_updated_at"));
+        assert!(append.contains("FROM public.products;"));
+
+        let realtime_join = JobParams {
+            table_method: TableMethod::join,
+            update_time_col: None,
+            ..job_params
+        };
+        let join = embeddings_status_query("my_job", &realtime_join);
+        assert!(join
+            .contains("(SELECT COUNT(*) FROM vectorize._embeddings_my_job t1) AS embedded_rows"));
+        assert!(join.contains("NULL::bigint AS stale_rows"));
+
+        // rows of chunked jobs are counted once, however many chunks they have
+        let chunked_join = JobParams {
+            update_time_col: Some("last_updated_at".to_string()),
+            chunking: Some(
+                ChunkConfig::new(vectorize_core::types::ChunkStrategy::tokens, 256, 0).unwrap(),
+            ),
+            ..realtime_join
+        };
+        let chunked = embeddings_status_query("my_job", &chunked_join);
+        assert!(chunked.contains(
+            "(SELECT COUNT(*) FROM (SELECT product_id, MIN(updated_at) AS updated_at FROM vectorize._embeddings_my_job GROUP BY product_id) t1) AS embedded_rows"
+        ));
+        assert!(chunked.contains("WHERE t0.last_updated_at > t1.updated_at"));
+    }
 }
@@ -3,7 +3,7 @@ use crate::guc::get_guc_configs;
 use crate::init;
 use crate::job::{create_event_trigger, create_trigger_handler, initalize_table_job};
 use crate::transformers::openai;
-use crate::transformers::transform;
+use crate::transformers::{rerank, transform, transform_sparse};
 use crate::util;
 
 use anyhow::{Context, Result};
@@ -11,10 +11,15 @@ use pgrx::prelude::*;
 use pgrx::JsonB;
 use serde_json::Value;
 use std::collections::HashMap;
-use vectorize_core::guc::VectorizeGuc;
-use vectorize_core::transformers::providers::get_provider;
+use vectorize_core::chunking::{cosine_similarity, ChunkConfig};
+use vectorize_core::guc::{ModelGucConfig, VectorizeGuc};
+use vectorize_core::transformers::http_handler::RetryConfig;
 use vectorize_core::transformers::providers::ollama::check_model_host;
-use vectorize_core::types::{self, Model, ModelSource, TableMethod, VectorizeMeta};
+use vectorize_core::transformers::providers::{get_model_provider, RerankResult};
+use vectorize_core::types::{
+    self, ChunkAggregation, DiskAnnSearchParams, EmbeddingStorage, InputPrefixes, InputType, Model,
+    ModelSource, TableMethod, VectorizeMeta,
+};
 
 #[allow(clippy::too_many_arguments)]
 pub fn init_table(
@@ -29,6 +34,13 @@ pub fn init_table(
     table_method: TableMethod,
     // cron-like for a cron based update model, or 'realtime' for a trigger-based
     schedule: &str,
+    diskann_search: DiskAnnSearchParams,
+    input_prefixes: InputPrefixes,
+    dimensions: Option<i32>,
+    storage: EmbeddingStorage,
+    sparse: bool,
+    chunking: Option<ChunkConfig>,
+    chunk_aggregation: ChunkAggregation,
 ) -> Result<String> {
     // validate table method
     // realtime is only compatible with the join method
@@ -36,6 +48,63 @@ pub fn init_table(
         error!("realtime schedule is only compatible with the join table method");
     }
 
+    // the term weights of learned sparse models are stored as sparsevec and searched by inner product
+    let (storage, index_dist_type) = if sparse {
+        if table_method != TableMethod::join {
+            return Err(anyhow::anyhow!(
+                "sparse jobs are only compatible with the join table method"
+            ));
+        }
+        if !matches!(
+            storage,
+            EmbeddingStorage::vector | EmbeddingStorage::sparsevec
+        ) {
+            return Err(anyhow::anyhow!(
+                "sparse jobs are stored as `sparsevec`, got: `{}`",
+                storage
+            ));
+        }
+        (EmbeddingStorage::sparsevec, types::IndexDist::pgv_hnsw_ip)
+    } else if storage == EmbeddingStorage::sparsevec {
+        // dense embeddings can not be stored as sparsevec
+        return Err(anyhow::anyhow!(
+            "`sparsevec` storage is only compatible with sparse jobs, set `sparse => true`"
+        ));
+    } else {
+        (storage, index_dist_type)
+    };
+
+    // the chunks of a row are stored as separate rows of the embeddings table
+    if chunking.is_some() {
+        if table_method != TableMethod::join {
+            return Err(anyhow::anyhow!(
+                "chunked jobs are only compatible with the join table method"
+            ));
+        }
+        if sparse {
+            return Err(anyhow::anyhow!("sparse jobs can not be chunked"));
+        }
+    }
+
+    if !diskann_search.is_empty()
+        && !matches!(index_dist_type, types::IndexDist::vsc_diskann_cosine)
+    {
+        return Err(anyhow::anyhow!(
+            "diskann search parameters require index_dist_type `vsc_diskann_cosine`, got: `{}`",
+            index_dist_type
+        ));
+    }
+
+    // pgvectorscale only indexes vector columns
+    if storage != EmbeddingStorage::vector
+        && matches!(index_dist_type, types::IndexDist::vsc_diskann_cosine)
+    {
+        return Err(anyhow::anyhow!(
+            "index_dist_type `vsc_diskann_cosine` requires `vector` storage, got: `{}`",
+            storage
+        ));
+    }
+
     if let Some(col) = &update_col {
         // validate update_col
         let update_time_dtype = init::get_column_datatype(schema, table, col)?;
@@ -50,67 +119,24 @@ pub fn init_table(
     let pkey_type = init::get_column_datatype(schema, table, primary_key)?;
     init::init_pgmq()?;
 
-    let guc_configs = get_guc_configs(&transformer.source);
-    // validate API key where necessary and collect any optional arguments
-    // certain embedding services require an API key, e.g. openAI
-    // key can be set in a GUC, so if its required but not provided in args, and not in GUC, error
-    let optional_args = match transformer.source {
-        ModelSource::OpenAI => {
-            openai::validate_api_key(
-                &guc_configs
-                    .api_key
-                    .clone()
-                    .context("OpenAI key is required")?,
-            )?;
-            None
-        }
-        ModelSource::Tembo => {
-            error!("Tembo not implemented for search yet");
-        }
-        ModelSource::Ollama => {
-            let url = match guc::get_guc(VectorizeGuc::OllamaServiceUrl) {
-                Some(k) => k,
-                None => {
-                    error!("failed to get Ollama url from GUC");
-                }
-            };
-            let res = check_model_host(&url);
-            match res {
-                Ok(_) => {
-                    info!("Model host active!");
-                    None
-                }
-                Err(e) => {
-                    error!("Error with model host: {:?}", e)
-                }
-            }
-        }
-        ModelSource::Portkey => Some(serde_json::json!({
-            "virtual_key": guc_configs.virtual_key.clone().expect("Portkey virtual key is required")
-        })),
-        _ => None,
+    let guc_configs = get_guc_configs(transformer);
+    let optional_args = init_transformer(transformer, &guc_configs)?;
+    let dimensions = dimensions
+        .map(|dim| {
+            u32::try_from(dim).map_err(|_| anyhow::anyhow!("dimensions must be greater than 0"))
+        })
+        .transpose()?;
+    let model_dim = match dimensions {
+        // not every provider knows the vocabulary of its sparse model, so it can be set to any size
+        Some(dim) if sparse => dim,
+        _ => transformer_dim(transformer, &guc_configs, sparse)?,
+    };
+    let dimensions = match dimensions {
+        Some(dim) => Some(embedding_dim(model_dim, Some(dim))?),
+        // sparse embeddings are written as sparsevecs of the size of the vocabulary
+        None if sparse => Some(model_dim),
+        None => None,
     };
-
-    let provider = get_provider(
-        &transformer.source,
-        guc_configs.api_key.clone(),
-        guc_configs.service_url.clone(),
-        guc_configs.virtual_key.clone(),
-    )?;
-
-    // synchronous
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
-        .enable_time()
-        .build()
-        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
-    let model_dim =
-        match runtime.block_on(async { provider.model_dim(&transformer.api_name()).await }) {
-            Ok(e) => e,
-            Err(e) => {
-                error!("error getting model dim: {}", e);
-            }
-        };
 
     let valid_params = types::JobParams {
         schema: schema.to_string(),
@@ -123,6 +149,24 @@ pub fn init_table(
         api_key: guc_configs.api_key.clone(),
         schedule: schedule.to_string(),
         args: optional_args,
+        diskann_search: if diskann_search.is_empty() {
+            None
+        } else {
+            Some(diskann_search)
+        },
+        paused: false,
+        paused_at: None,
+        model_migration: None,
+        input_prefixes: if input_prefixes.is_empty() {
+            None
+        } else {
+            Some(input_prefixes)
+        },
+        dimensions,
+        storage,
+        sparse,
+        chunking,
+        chunk_aggregation,
     };
     let params =
         JsonB(serde_json::to_value(valid_params.clone()).expect("error serializing params"));
@@ -151,8 +195,12 @@ pub fn init_table(
     });
     ran?;
 
-    let init_embed_q =
-        init::init_embedding_table_query(job_name, &valid_params, &index_dist_type, model_dim);
+    let init_embed_q = init::init_embedding_table_query(
+        job_name,
+        &valid_params,
+        &index_dist_type,
+        dimensions.unwrap_or(model_dim),
+    );
     let ran_semantic: Result<_, spi::Error> = Spi::connect_mut(|c| {
         for q in init_embed_q {
             let _r = c.update(&q, None, &[])?;
@@ -188,6 +236,116 @@ pub fn init_table(
     Ok(format!("Successfully created job: {job_name}"))
 }
 
+// validates the transformer's configuration and returns the job's optional arguments
+pub fn init_transformer(
+    transformer: &Model,
+    guc_configs: &ModelGucConfig,
+) -> Result<Option<Value>> {
+    // validate API key where necessary and collect any optional arguments
+    // certain embedding services require an API key, e.g. openAI
+    // key can be set in a GUC, so if its required but not provided in args, and not in GUC, error
+    let optional_args = match transformer.source {
+        ModelSource::OpenAI => {
+            openai::validate_api_key(
+                &guc_configs
+                    .api_key
+                    .clone()
+                    .context("OpenAI key is required")?,
+            )?;
+            None
+        }
+        ModelSource::Azure => {
+            guc_configs
+                .service_url
+                .as_ref()
+                .context("Azure OpenAI endpoint is required")?;
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("Azure OpenAI key is required")?;
+            None
+        }
+        ModelSource::Gemini => {
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("Gemini API key is required")?;
+            None
+        }
+        ModelSource::Tembo => {
+            error!("Tembo not implemented for search yet");
+        }
+        ModelSource::Ollama => {
+            let url = match guc::get_guc(VectorizeGuc::OllamaServiceUrl) {
+                Some(k) => k,
+                None => {
+                    error!("failed to get Ollama url from GUC");
+                }
+            };
+            let res = check_model_host(&url);
+            match res {
+                Ok(_) => {
+                    info!("Model host active!");
+                    None
+                }
+                Err(e) => {
+                    error!("Error with model host: {:?}", e)
+                }
+            }
+        }
+        ModelSource::Portkey => Some(serde_json::json!({
+            "virtual_key": guc_configs.virtual_key.clone().expect("Portkey virtual key is required")
+        })),
+        _ => None,
+    };
+    Ok(optional_args)
+}
+
+// the dimension of the transformer's embeddings, or of its vocabulary for sparse models
+pub fn transformer_dim(
+    transformer: &Model,
+    guc_configs: &ModelGucConfig,
+    sparse: bool,
+) -> Result<u32> {
+    let provider = get_model_provider(
+        &transformer.source,
+        guc_configs.api_key.clone(),
+        guc_configs,
+        RetryConfig::interactive(),
+    )?;
+
+    // synchronous
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+    match runtime.block_on(async {
+        if sparse {
+            provider.sparse_dim(&transformer.api_name()).await
+        } else {
+            provider.model_dim(&transformer.api_name()).await
+        }
+    }) {
+        Ok(dim) => Ok(dim),
+        Err(e) => {
+            error!("error getting model dim: {}", e);
+        }
+    }
+}
+
+// the size of a job's embeddings, which can be smaller than the size of the transformer's embeddings
+pub fn embedding_dim(model_dim: u32, dimensions: Option<u32>) -> Result<u32> {
+    match dimensions {
+        Some(0) => Err(anyhow::anyhow!("dimensions must be greater than 0")),
+        Some(dim) if dim > model_dim => Err(anyhow::anyhow!(
+            "dimensions must not exceed the {model_dim} dimensions of the transformer, got: {dim}"
+        )),
+        Some(dim) => Ok(dim),
+        None => Ok(model_dim),
+    }
+}
+
 pub fn full_text_search(
     job_name: &str,
     query: &str,
@@ -272,11 +430,22 @@ pub fn rrf_score(rank: Option<i32>) -> f32 {
 #[derive(Debug)]
 pub struct AllResults {
     data: JsonB,
-    full_text_rank: Option<i32>,
+    lexical_rank: Option<i32>,
     semantic_rank: Option<i32>,
     rrf_score: f32,
 }
 
+// results of both searches are matched on their columns, without the similarity score of either search
+// or the matching chunks of a chunked job
+fn without_similarity_score(result: &Value) -> Value {
+    let mut key = result.clone();
+    if let Some(obj) = key.as_object_mut() {
+        obj.remove("similarity_score");
+        obj.remove("chunks");
+    }
+    key
+}
+
 pub fn hybrid_search(
     job_name: &str,
     query: &str,
@@ -284,50 +453,91 @@ pub fn hybrid_search(
     return_columns: Vec<String>,
     num_results: i32,
     where_clause: Option<String>,
+    sparse_job_name: Option<String>,
+    mmr_lambda: Option<f64>,
 ) -> Result<Vec<JsonB>> {
+    validate_mmr_lambda(mmr_lambda)?;
     let semantic_weight: i32 = guc::SEMANTIC_WEIGHT.get();
 
-    // Getting the results from both full-text and semantic search
+    // mmr selects from the fused results by the embeddings of the dense job, looked up by primary key
+    let (job_params, hidden_columns, num_candidates) = match mmr_lambda {
+        Some(_) => {
+            let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+            let job_params: types::JobParams =
+                serde_json::from_value(serde_json::to_value(project_meta.params)?)?;
+            let hidden = hidden_columns(&return_columns, vec![job_params.primary_key.clone()]);
+            (Some(job_params), hidden, num_results * SELECTION_CANDIDATES)
+        }
+        None => (None, vec![], num_results * 2),
+    };
+    let candidate_columns: Vec<String> = return_columns
+        .iter()
+        .chain(&hidden_columns)
+        .cloned()
+        .collect();
+
+    // Getting the results from both lexical and semantic search
+    // lexical results come from the learned sparse embeddings of the sparse job, when given,
+    // and otherwise from full-text search
     // num_results * 2 to get a larger pool of results to rank
-    let full_text_results =
-        full_text_search(job_name, query, return_columns.clone(), num_results * 2)?;
+    let lexical_results = match &sparse_job_name {
+        Some(sparse_job_name) => search(
+            sparse_job_name,
+            query,
+            api_key.clone(),
+            candidate_columns.clone(),
+            num_candidates,
+            where_clause.clone(),
+            &DiskAnnSearchParams::default(),
+            None,
+            None,
+        )?,
+        None => full_text_search(job_name, query, candidate_columns.clone(), num_candidates)?,
+    };
     let semantic_results = search(
         job_name,
         query,
         api_key,
-        return_columns,
-        num_results * 2,
+        candidate_columns,
+        num_candidates,
         where_clause,
+        &DiskAnnSearchParams::default(),
+        None,
+        None,
     )?;
 
     // Use a HashMap with serde_json::Value as the key
     let mut combined_results_map: HashMap<Value, AllResults> = HashMap::new();
 
-    // Process full-text search results to combine with semantic search results
-    for (i, result) in full_text_results.iter().enumerate() {
-        let json_value = result.0.clone(); // Extract serde_json::Value from JsonB
+    // Process lexical search results to combine with semantic search results
+    for (i, result) in lexical_results.iter().enumerate() {
+        let json_value = without_similarity_score(&result.0);
 
         combined_results_map
             .entry(json_value.clone())
-            .and_modify(|entry| entry.full_text_rank = Some(i as i32))
+            .and_modify(|entry| entry.lexical_rank = Some(i as i32))
             .or_insert(AllResults {
                 data: JsonB(json_value),
-                full_text_rank: Some(i as i32),
+                lexical_rank: Some(i as i32),
                 semantic_rank: None,
                 rrf_score: 0.0,
             });
     }
 
     // Process semantic search results
+    // matched results keep the similarity score of the semantic search
     for (i, result) in semantic_results.iter().enumerate() {
         let json_value = result.0.clone(); // Extract serde_json::Value from JsonB
 
         combined_results_map
-            .entry(json_value.clone())
-            .and_modify(|entry| entry.semantic_rank = Some(i as i32))
+            .entry(without_similarity_score(&json_value))
+            .and_modify(|entry| {
+                entry.semantic_rank = Some(i as i32);
+                entry.data = JsonB(json_value.clone());
+            })
             .or_insert(AllResults {
                 data: JsonB(json_value),
-                full_text_rank: None,
+                lexical_rank: None,
                 semantic_rank: Some(i as i32),
                 rrf_score: 0.0,
             });
@@ -339,22 +549,26 @@ pub fn hybrid_search(
     // Calculate RRF score for each result, sum it and store in AllResults
     for result in all_results.iter_mut() {
         // Iterate mutably to update results
-        let ft_score = (1.0 - semantic_weight as f32 / 100.0) * rrf_score(result.full_text_rank);
+        let lexical_score = (1.0 - semantic_weight as f32 / 100.0) * rrf_score(result.lexical_rank);
         let s_score = (semantic_weight as f32 / 100.0) * rrf_score(result.semantic_rank);
-        let final_rrf_score = ft_score + s_score;
+        let final_rrf_score = lexical_score + s_score;
         result.rrf_score = final_rrf_score; // Store RRF score in AllResults
     }
 
     // Sort by RRF score
     all_results.sort_by(|a, b| b.rrf_score.partial_cmp(&a.rrf_score).unwrap());
 
-    let final_results: Vec<JsonB> = all_results
+    let lexical_rank_key = match sparse_job_name {
+        Some(_) => "sparse_rank",
+        None => "full_text_rank",
+    };
+    let mut final_results: Vec<JsonB> = all_results
         .into_iter()
         .map(|res| {
             let mut result_json = res.data.0.clone(); // Extract original JSON structure
 
             // Inject score values at the top level
-            result_json["full_text_rank"] = serde_json::Value::from(res.full_text_rank);
+            result_json[lexical_rank_key] = serde_json::Value::from(res.lexical_rank);
             result_json["semantic_rank"] = serde_json::Value::from(res.semantic_rank);
             result_json["rrf_score"] = serde_json::Value::from(res.rrf_score);
 
@@ -362,13 +576,28 @@ pub fn hybrid_search(
         })
         .collect();
 
+    if let (Some(mmr_lambda), Some(job_params)) = (mmr_lambda, job_params) {
+        let embeddings = candidate_embeddings(job_name, &job_params, &final_results)?;
+        final_results = mmr_select(
+            final_results,
+            &embeddings,
+            "rrf_score",
+            mmr_lambda,
+            num_results,
+        );
+    }
+
     // Return only the top num_results
-    Ok(final_results
-        .into_iter()
-        .take(num_results as usize)
-        .collect())
+    Ok(returned_results(
+        final_results,
+        &hidden_columns,
+        num_results,
+    ))
 }
 
+// with a rerank model, more candidates are searched and then reordered by their relevance to the query
+// with an mmr lambda, more candidates are searched and then selected for diversity, see mmr_select
+#[allow(clippy::too_many_arguments)]
 pub fn search(
     job_name: &str,
     query: &str,
@@ -376,8 +605,20 @@ pub fn search(
     return_columns: Vec<String>,
     num_results: i32,
     where_clause: Option<String>,
+    diskann_search: &DiskAnnSearchParams,
+    rerank_model: Option<&Model>,
+    mmr_lambda: Option<f64>,
 ) -> Result<Vec<JsonB>> {
-    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    validate_mmr_lambda(mmr_lambda)?;
+    let mut project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    if project_meta.params.get("model_migration").is_some() {
+        // the embeddings table can be swapped during a model migration
+        // holding a lock on it keeps the transformer and the embeddings consistent for this query
+        Spi::run(&format!(
+            "LOCK TABLE vectorize._embeddings_{job_name} IN ACCESS SHARE MODE;"
+        ))?;
+        project_meta = util::get_vectorize_meta_spi(job_name)?;
+    }
     let proj_params: types::JobParams = serde_json::from_value(
         serde_json::to_value(project_meta.params).unwrap_or_else(|e| {
             error!("failed to serialize metadata: {}", e);
@@ -391,56 +632,450 @@ pub fn search(
         // if not, use the one from the project metadata
         None => proj_params.api_key.clone(),
     };
-    let embeddings = transform(query, &project_meta.transformer, proj_api_key);
+    // per-call diskann params take precedence over the job's params
+    let job_diskann_search = proj_params.diskann_search.clone().unwrap_or_default();
+    let diskann_search = diskann_search.or(&job_diskann_search);
+    if !diskann_search.is_empty()
+        && !matches!(
+            project_meta.index_dist_type,
+            types::IndexDist::vsc_diskann_cosine
+        )
+    {
+        return Err(anyhow::anyhow!(
+            "diskann search parameters require index_dist_type `vsc_diskann_cosine`, got: `{}`",
+            project_meta.index_dist_type
+        ));
+    }
+
+    let raw_query = query;
+    let query = proj_params
+        .input_prefixes
+        .clone()
+        .unwrap_or_default()
+        .apply(InputType::Query, query);
+    let embedding = if proj_params.sparse {
+        let dim = proj_params
+            .dimensions
+            .context("sparse job has no dimensions")?;
+        let embedding = transform_sparse(
+            &query,
+            &project_meta.transformer,
+            proj_api_key,
+            InputType::Query,
+        );
+        QueryEmbedding::Sparse(embedding.to_sparsevec(dim))
+    } else {
+        let mut embeddings = transform(
+            &query,
+            &project_meta.transformer,
+            proj_api_key,
+            InputType::Query,
+            proj_params.dimensions,
+        );
+        QueryEmbedding::Dense(embeddings.remove(0))
+    };
+
+    if rerank_model.is_none() && mmr_lambda.is_none() {
+        return similarity_search(
+            job_name,
+            &proj_params,
+            &project_meta.index_dist_type,
+            &return_columns,
+            num_results,
+            &embedding,
+            where_clause,
+            &diskann_search,
+        );
+    }
+
+    // reranking needs the job's columns as documents, and mmr needs the primary key to look up embeddings
+    let mut needed_columns = vec![];
+    if rerank_model.is_some() {
+        needed_columns.extend(proj_params.columns.iter().cloned());
+    }
+    if mmr_lambda.is_some() {
+        needed_columns.push(proj_params.primary_key.clone());
+    }
+    let hidden_columns = hidden_columns(&return_columns, needed_columns);
+    let candidate_columns: Vec<String> = return_columns
+        .iter()
+        .chain(&hidden_columns)
+        .cloned()
+        .collect();
+    let mut candidates = similarity_search(
+        job_name,
+        &proj_params,
+        &project_meta.index_dist_type,
+        &candidate_columns,
+        num_results * SELECTION_CANDIDATES,
+        &embedding,
+        where_clause,
+        &diskann_search,
+    )?;
+    let mut relevance_key = "similarity_score";
+    if let Some(rerank_model) = rerank_model {
+        let documents: Vec<String> = candidates
+            .iter()
+            .map(|candidate| rerank_document(&candidate.0, &proj_params))
+            .collect();
+        let ranking = rerank(raw_query, &documents, rerank_model);
+        candidates = rerank_results(candidates, ranking);
+        relevance_key = "rerank_score";
+    }
+    if let Some(mmr_lambda) = mmr_lambda {
+        let embeddings = candidate_embeddings(job_name, &proj_params, &candidates)?;
+        candidates = mmr_select(
+            candidates,
+            &embeddings,
+            relevance_key,
+            mmr_lambda,
+            num_results,
+        );
+    }
+    Ok(returned_results(candidates, &hidden_columns, num_results))
+}
+
+// the needed columns that are not returned, which are selected and then removed from the results
+fn hidden_columns(return_columns: &[String], needed_columns: Vec<String>) -> Vec<String> {
+    if return_columns.iter().any(|c| c == "*") {
+        return vec![];
+    }
+    let mut hidden: Vec<String> = vec![];
+    for col in needed_columns {
+        if !return_columns.contains(&col) && !hidden.contains(&col) {
+            hidden.push(col);
+        }
+    }
+    hidden
+}
+
+// the first num_results results, without the hidden columns
+fn returned_results(
+    results: Vec<JsonB>,
+    hidden_columns: &[String],
+    num_results: i32,
+) -> Vec<JsonB> {
+    results
+        .into_iter()
+        .take(num_results as usize)
+        .map(|mut result| {
+            if let Some(obj) = result.0.as_object_mut() {
+                for col in hidden_columns {
+                    obj.remove(col);
+                }
+            }
+            result
+        })
+        .collect()
+}
+
+pub fn validate_mmr_lambda(mmr_lambda: Option<f64>) -> Result<()> {
+    match mmr_lambda {
+        Some(lambda) if !(0.0..=1.0).contains(&lambda) => Err(anyhow::anyhow!(
+            "mmr_lambda must be between 0 and 1, got: {lambda}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+// the text of a search result that is reranked, which is the best matching chunks of a chunked job,
+// and otherwise the job's columns
+fn rerank_document(result: &Value, job_params: &types::JobParams) -> String {
+    let as_text = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    if job_params.chunking.is_some() {
+        if let Some(chunks) = result.get("chunks").and_then(Value::as_array) {
+            return chunks
+                .iter()
+                .filter_map(|chunk| chunk.get("chunk"))
+                .map(as_text)
+                .collect::<Vec<String>>()
+                .join("\n");
+        }
+    }
+    job_params
+        .columns
+        .iter()
+        .filter_map(|col| result.get(col))
+        .map(as_text)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// orders the candidates by their relevance scores, with the scores injected as rerank_score
+// candidates that are not ranked by the provider are dropped
+fn rerank_results(candidates: Vec<JsonB>, mut ranking: Vec<RerankResult>) -> Vec<JsonB> {
+    ranking.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    let mut candidates: Vec<Option<Value>> = candidates.into_iter().map(|c| Some(c.0)).collect();
+    ranking
+        .into_iter()
+        .filter_map(|ranked| {
+            let mut result = candidates.get_mut(ranked.index)?.take()?;
+            result["rerank_score"] = Value::from(ranked.relevance_score);
+            Some(JsonB(result))
+        })
+        .collect()
+}
+
+// the stored embeddings of the candidates, looked up by their primary key, in the order of the candidates
+// a row of a chunked job is represented by the embedding of its best matching chunk
+fn candidate_embeddings(
+    project: &str,
+    job_params: &types::JobParams,
+    candidates: &[JsonB],
+) -> Result<Vec<Option<Vec<f64>>>> {
+    let keys: Vec<Option<(String, i32)>> = candidates
+        .iter()
+        .map(|candidate| {
+            let record_id = match candidate.0.get(&job_params.primary_key)? {
+                Value::String(s) => s.clone(),
+                Value::Null => return None,
+                other => other.to_string(),
+            };
+            let chunk_index = candidate
+                .0
+                .get("chunks")
+                .and_then(|chunks| chunks.get(0))
+                .and_then(|chunk| chunk.get("chunk_index"))
+                .and_then(Value::as_i64)
+                .unwrap_or(0);
+            Some((record_id, chunk_index as i32))
+        })
+        .collect();
+    let record_ids: Vec<String> = keys.iter().flatten().map(|(id, _)| id.clone()).collect();
+    let query = candidate_embeddings_query(project, job_params);
+    let mut embeddings: HashMap<(String, i32), Vec<f64>> = Spi::connect(|client| {
+        let mut embeddings = HashMap::new();
+        let tup_table = client.select(&query, None, &[record_ids.into()])?;
+        for row in tup_table {
+            let record_id: Option<String> = row["record_id"].value()?;
+            let chunk_index: Option<i32> = row["chunk_index"].value()?;
+            let embedding: Option<Vec<f64>> = row["embedding"].value()?;
+            if let (Some(record_id), Some(chunk_index), Some(embedding)) =
+                (record_id, chunk_index, embedding)
+            {
+                embeddings.insert((record_id, chunk_index), embedding);
+            }
+        }
+        Ok::<_, spi::Error>(embeddings)
+    })?;
+    Ok(keys
+        .into_iter()
+        .map(|key| key.and_then(|key| embeddings.remove(&key)))
+        .collect())
+}
+
+fn candidate_embeddings_query(project: &str, job_params: &types::JobParams) -> String {
+    let primary_key = &job_params.primary_key;
+    let (chunk_index, embeddings_col, source) = match job_params.table_method {
+        TableMethod::append => (
+            "0",
+            format!("{project}_embeddings"),
+            format!("{}.{}", job_params.schema, job_params.relation),
+        ),
+        TableMethod::join => (
+            if job_params.chunking.is_some() {
+                "chunk_index"
+            } else {
+                "0"
+            },
+            "embeddings".to_string(),
+            format!("vectorize._embeddings_{project}"),
+        ),
+    };
+    format!(
+        "
+    SELECT
+        {primary_key}::text AS record_id,
+        {chunk_index} AS chunk_index,
+        {embeddings_col}::vector::real[]::float8[] AS embedding
+    FROM {source}
+    WHERE {primary_key} = ANY ($1::text[]::{pkey_type}[])
+    ",
+        pkey_type = job_params.pkey_type,
+    )
+}
+
+// maximal marginal relevance: greedily selects the candidate with the highest
+// lambda * relevance - (1 - lambda) * (highest similarity of its embedding to an already selected candidate)
+// so that near-duplicates of selected results are passed over, and a lambda of 1 keeps the order of relevance
+// relevance is min-max normalized, so that it is on the scale of the cosine similarity of the embeddings
+fn mmr_select(
+    candidates: Vec<JsonB>,
+    embeddings: &[Option<Vec<f64>>],
+    relevance_key: &str,
+    lambda: f64,
+    num_results: i32,
+) -> Vec<JsonB> {
+    let scores: Vec<f64> = candidates
+        .iter()
+        .map(|c| {
+            c.0.get(relevance_key)
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0)
+        })
+        .collect();
+    let min = scores.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = scores.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let relevance: Vec<f64> = scores
+        .iter()
+        .map(|s| {
+            if max > min {
+                (s - min) / (max - min)
+            } else {
+                1.0
+            }
+        })
+        .collect();
+
+    let mut remaining: Vec<usize> = (0..candidates.len()).collect();
+    let mut selected: Vec<usize> = vec![];
+    while selected.len() < num_results as usize && !remaining.is_empty() {
+        let mut best: Option<(usize, f64)> = None;
+        for (position, &i) in remaining.iter().enumerate() {
+            let redundancy = selected
+                .iter()
+                .filter_map(|&j| {
+                    Some(cosine_similarity(
+                        embeddings.get(i)?.as_ref()?,
+                        embeddings.get(j)?.as_ref()?,
+                    ))
+                })
+                .reduce(f64::max)
+                .unwrap_or(0.0);
+            let score = lambda * relevance[i] - (1.0 - lambda) * redundancy;
+            // ties keep the order of the candidates
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((position, score));
+            }
+        }
+        let (position, _) = best.expect("remaining candidates");
+        selected.push(remaining.remove(position));
+    }
+
+    let mut candidates: Vec<Option<JsonB>> = candidates.into_iter().map(Some).collect();
+    selected
+        .into_iter()
+        .filter_map(|i| candidates[i].take())
+        .collect()
+}
+
+// the embedding of a search query, bound as a float array or as the text of a sparsevec
+pub enum QueryEmbedding {
+    Dense(Vec<f64>),
+    Sparse(String),
+}
+
+// bit storage re-ranks this many candidates per result on the full vector
+const BINARY_QUANTIZED_CANDIDATES: i32 = 4;
+
+// reranking and mmr choose the results from this many candidates per result
+const SELECTION_CANDIDATES: i32 = 4;
+
+// chunked jobs search this many chunks per result, since a row can have several matching chunks
+const CHUNK_CANDIDATES: i32 = 4;
 
-    match project_meta.index_dist_type {
-        types::IndexDist::pgv_hnsw_l2 => error!("Not implemented."),
-        types::IndexDist::pgv_hnsw_ip => error!("Not implemented."),
+// the best matching chunks of a row that its score is aggregated from, and that are returned with it
+const MATCHING_CHUNKS_PER_ROW: i32 = 3;
+
+// pgvector operator matching the distance of the index built for the job
+fn distance_operator(index_dist_type: &types::IndexDist) -> &'static str {
+    match index_dist_type {
+        types::IndexDist::pgv_hnsw_l2 => "<->",
+        types::IndexDist::pgv_hnsw_ip => "<#>",
+        types::IndexDist::pgv_hnsw_cosine | types::IndexDist::vsc_diskann_cosine => "<=>",
+    }
+}
+
+// expression for similarity_score, where a higher score is always a closer match
+// cosine: 1 - cosine distance
+// inner product: pgvector's <#> returns the negative inner product, so flip the sign
+// l2: 1 / (1 + euclidean distance), bounded to (0, 1]
+fn similarity_score_expr(
+    index_dist_type: &types::IndexDist,
+    embeddings_col: &str,
+    query_embedding: &str,
+) -> String {
+    let op = distance_operator(index_dist_type);
+    match index_dist_type {
+        types::IndexDist::pgv_hnsw_l2 => {
+            format!("1 / (1 + ({embeddings_col} {op} {query_embedding}))")
+        }
+        types::IndexDist::pgv_hnsw_ip => {
+            format!("({embeddings_col} {op} {query_embedding}) * -1")
+        }
         types::IndexDist::pgv_hnsw_cosine | types::IndexDist::vsc_diskann_cosine => {
-            cosine_similarity_search(
-                job_name,
-                &proj_params,
-                &return_columns,
-                num_results,
-                &embeddings[0],
-                where_clause,
-            )
+            format!("1 - ({embeddings_col} {op} {query_embedding})")
         }
     }
 }
 
-pub fn cosine_similarity_search(
+// orders by the hamming distance of binary quantized embeddings, which the index of bit storage is built on
+fn binary_quantized_order(embeddings_col: &str, query_embedding: &str, model_dim: u32) -> String {
+    format!(
+        "ORDER BY {} <~> {}",
+        init::binary_quantize(embeddings_col, model_dim),
+        init::binary_quantize(query_embedding, model_dim)
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn similarity_search(
     project: &str,
     job_params: &types::JobParams,
+    index_dist_type: &types::IndexDist,
     return_columns: &[String],
     num_results: i32,
-    embeddings: &[f64],
+    embedding: &QueryEmbedding,
     where_clause: Option<String>,
+    diskann_search: &DiskAnnSearchParams,
 ) -> Result<Vec<JsonB>> {
-    let schema = job_params.schema.clone();
-    let table = job_params.relation.clone();
-
+    let model_dim = match embedding {
+        QueryEmbedding::Dense(embedding) => embedding.len() as u32,
+        QueryEmbedding::Sparse(_) => job_params.dimensions.unwrap_or_default(),
+    };
     // switch on table method
     let query = match job_params.table_method {
-        TableMethod::append => single_table_cosine_similarity(
+        TableMethod::append => single_table_similarity(
             project,
-            &schema,
-            &table,
+            job_params,
+            index_dist_type,
             return_columns,
             num_results,
             where_clause,
+            model_dim,
         ),
-        TableMethod::join => join_table_cosine_similarity(
+        TableMethod::join => join_table_similarity(
             project,
             job_params,
+            index_dist_type,
             return_columns,
             num_results,
             where_clause,
+            model_dim,
         ),
     };
     Spi::connect(|client| {
+        // is_local = true, so the settings only last for the current transaction
+        for (guc, value) in diskann_search.gucs() {
+            let _ = client.select(
+                "SELECT set_config($1, $2, true)",
+                None,
+                &[guc.into(), value.to_string().into()],
+            )?;
+        }
         let mut results: Vec<JsonB> = Vec::new();
-        let tup_table = client.select(&query, None, &[embeddings.into()])?;
+        let tup_table = match embedding {
+            QueryEmbedding::Dense(embedding) => {
+                client.select(&query, None, &[embedding.as_slice().into()])?
+            }
+            QueryEmbedding::Sparse(embedding) => {
+                client.select(&query, None, &[embedding.as_str().into()])?
+            }
+        };
         for row in tup_table {
             match row["results"].value()? {
                 Some(r) => results.push(r),
@@ -451,12 +1086,14 @@ pub fn cosine_similarity_search(
     })
 }
 
-fn join_table_cosine_similarity(
+fn join_table_similarity(
     project: &str,
     job_params: &types::JobParams,
+    index_dist_type: &types::IndexDist,
     return_columns: &[String],
     num_results: i32,
     where_clause: Option<String>,
+    model_dim: u32,
 ) -> String {
     let schema = job_params.schema.clone();
     let table = job_params.relation.clone();
@@ -467,25 +1104,58 @@ fn join_table_cosine_similarity(
         .collect::<Vec<_>>()
         .join(",");
 
-    let where_str = if let Some(w) = where_clause {
-        prepare_filter(&w, join_key)
+    let (key_cols, num_embeddings, chunks_col) = match job_params.chunking {
+        Some(_) => (
+            format!("{join_key}, chunk_index, chunk"),
+            num_results * CHUNK_CANDIDATES,
+            ", t1.chunks",
+        ),
+        None => (join_key.to_string(), num_results, ""),
+    };
+
+    // without a filter, the limit can be pushed down to the embeddings table
+    // so that the nearest neighbors are found with the vector index
+    let (where_str, inner_limit) = if let Some(w) = where_clause {
+        (prepare_filter(&w, join_key), "".to_string())
     } else {
-        "".to_string()
+        ("".to_string(), format!("LIMIT {num_embeddings}"))
     };
+    let query_embedding = job_params.embedding_cast("$1");
+    let source = match job_params.storage {
+        // candidates from the binary quantized index are re-ranked on the full vector
+        EmbeddingStorage::bit if !inner_limit.is_empty() => format!(
+            "(
+        SELECT {key_cols}, embeddings
+        FROM vectorize._embeddings_{project}
+        {order}
+        LIMIT {candidates}
+    ) candidates",
+            order = binary_quantized_order("embeddings", &query_embedding, model_dim),
+            candidates = num_embeddings * BINARY_QUANTIZED_CANDIDATES,
+        ),
+        _ => format!("vectorize._embeddings_{project}"),
+    };
+    let op = distance_operator(index_dist_type);
+    let score = similarity_score_expr(index_dist_type, "embeddings", &query_embedding);
     let inner_query = format!(
         "
     SELECT
-        {join_key},
-        1 - (embeddings <=> $1::vector) AS similarity_score
-    FROM vectorize._embeddings_{project}
-    ORDER BY similarity_score DESC
+        {key_cols},
+        {score} AS similarity_score
+    FROM {source}
+    ORDER BY embeddings {op} {query_embedding}
+    {inner_limit}
     "
     );
+    let inner_query = match job_params.chunking {
+        Some(_) => aggregate_chunks(&inner_query, join_key, &job_params.chunk_aggregation),
+        None => inner_query,
+    };
     format!(
         "
     SELECT to_jsonb(t) as results
     FROM (
-        SELECT {cols}, t1.similarity_score
+        SELECT {cols}, t1.similarity_score{chunks_col}
         FROM
             (
                 {inner_query}
@@ -499,30 +1169,82 @@ fn join_table_cosine_similarity(
     )
 }
 
-fn single_table_cosine_similarity(
+// scores each row by its best matching chunks, which are returned with it as `chunks`
+fn aggregate_chunks(
+    chunks_query: &str,
+    join_key: &str,
+    aggregation: &types::ChunkAggregation,
+) -> String {
+    format!(
+        "
+    SELECT
+        {join_key},
+        {agg}(similarity_score) AS similarity_score,
+        jsonb_agg(
+            jsonb_build_object('chunk_index', chunk_index, 'chunk', chunk, 'similarity_score', similarity_score)
+            ORDER BY similarity_score DESC
+        ) AS chunks
+    FROM (
+        SELECT
+            *,
+            row_number() OVER (PARTITION BY {join_key} ORDER BY similarity_score DESC) AS chunk_rank
+        FROM ({chunks_query}) chunk_candidates
+    ) ranked_chunks
+    WHERE chunk_rank <= {MATCHING_CHUNKS_PER_ROW}
+    GROUP BY {join_key}
+    ",
+        agg = aggregation.sql_function(),
+    )
+}
+
+fn single_table_similarity(
     project: &str,
-    schema: &str,
-    table: &str,
+    job_params: &types::JobParams,
+    index_dist_type: &types::IndexDist,
     return_columns: &[String],
     num_results: i32,
     where_clause: Option<String>,
+    model_dim: u32,
 ) -> String {
+    let schema = &job_params.schema;
+    let table = &job_params.relation;
     let where_str = if let Some(w) = where_clause {
         format!("AND {}", w)
     } else {
         "".to_string()
     };
+    let filter = format!("WHERE {project}_updated_at is NOT NULL\n    {where_str}");
+    let embeddings_col = format!("{project}_embeddings");
+    let query_embedding = job_params.embedding_cast("$1");
+    let (source, filter) = match job_params.storage {
+        // candidates from the binary quantized index are re-ranked on the full vector
+        EmbeddingStorage::bit => (
+            format!(
+                "(
+        SELECT * FROM {schema}.{table}
+        {filter}
+        {order}
+        LIMIT {candidates}
+    ) candidates",
+                order = binary_quantized_order(&embeddings_col, &query_embedding, model_dim),
+                candidates = num_results * BINARY_QUANTIZED_CANDIDATES,
+            ),
+            "".to_string(),
+        ),
+        _ => (format!("{schema}.{table}"), filter),
+    };
+    let op = distance_operator(index_dist_type);
+    let score = similarity_score_expr(index_dist_type, &embeddings_col, &query_embedding);
     format!(
         "
     SELECT to_jsonb(t) as results
     FROM (
         SELECT 
-        1 - ({project}_embeddings <=> $1::vector) AS similarity_score,
+        {score} AS similarity_score,
         {cols}
-    FROM {schema}.{table}
-    WHERE {project}_updated_at is NOT NULL
-    {where_str}
-    ORDER BY similarity_score DESC
+    FROM {source}
+    {filter}
+    ORDER BY {embeddings_col} {op} {query_embedding}
     LIMIT {num_results}
     ) t
     ",
@@ -535,3 +1257,231 @@ fn prepare_filter(filter: &str, pkey: &str) -> String {
     let wc = filter.replace(pkey, &format!("t0.{}", pkey));
     format!("AND {wc}")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity_score_expr() {
+        let q = "$1::vector";
+        let l2 = similarity_score_expr(&types::IndexDist::pgv_hnsw_l2, "embeddings", q);
+        assert_eq!(l2, "1 / (1 + (embeddings <-> $1::vector))");
+        let ip = similarity_score_expr(&types::IndexDist::pgv_hnsw_ip, "embeddings", q);
+        assert_eq!(ip, "(embeddings <#> $1::vector) * -1");
+        let cos = similarity_score_expr(&types::IndexDist::pgv_hnsw_cosine, "embeddings", q);
+        assert_eq!(cos, "1 - (embeddings <=> $1::vector)");
+        let diskann = similarity_score_expr(&types::IndexDist::vsc_diskann_cosine, "embeddings", q);
+        assert_eq!(diskann, "1 - (embeddings <=> $1::vector)");
+    }
+
+    #[test]
+    fn test_append_query_orders_by_distance_operator() {
+        let q = single_table_similarity(
+            "myjob",
+            &products_job(EmbeddingStorage::vector),
+            &types::IndexDist::pgv_hnsw_ip,
+            &["product_name".to_string()],
+            3,
+            None,
+            384,
+        );
+        assert!(q.contains("ORDER BY myjob_embeddings <#> $1::vector"));
+        assert!(q.contains("(myjob_embeddings <#> $1::vector) * -1 AS similarity_score"));
+    }
+
+    fn products_job(storage: EmbeddingStorage) -> types::JobParams {
+        types::JobParams {
+            schema: "public".to_string(),
+            relation: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            storage,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_halfvec_query_casts_embedding() {
+        let q = join_table_similarity(
+            "myjob",
+            &products_job(EmbeddingStorage::halfvec),
+            &types::IndexDist::pgv_hnsw_cosine,
+            &["product_name".to_string()],
+            3,
+            None,
+            384,
+        );
+        assert!(q.contains("ORDER BY embeddings <=> $1::vector::halfvec"));
+    }
+
+    #[test]
+    fn test_sparse_query_casts_embedding() {
+        let job_params = types::JobParams {
+            sparse: true,
+            ..products_job(EmbeddingStorage::sparsevec)
+        };
+        let q = join_table_similarity(
+            "myjob",
+            &job_params,
+            &types::IndexDist::pgv_hnsw_ip,
+            &["product_name".to_string()],
+            3,
+            None,
+            30522,
+        );
+        assert!(q.contains("ORDER BY embeddings <#> $1::sparsevec"));
+        assert!(q.contains("(embeddings <#> $1::sparsevec) * -1 AS similarity_score"));
+    }
+
+    #[test]
+    fn test_chunked_query_aggregates_chunks() {
+        let job_params = types::JobParams {
+            chunking: Some(ChunkConfig::new(types::ChunkStrategy::characters, 1000, 0).unwrap()),
+            chunk_aggregation: ChunkAggregation::sum,
+            ..products_job(EmbeddingStorage::vector)
+        };
+        let q = join_table_similarity(
+            "myjob",
+            &job_params,
+            &types::IndexDist::pgv_hnsw_cosine,
+            &["product_name".to_string()],
+            3,
+            None,
+            384,
+        );
+        assert!(q.contains("product_id, chunk_index, chunk,"));
+        assert!(q.contains(&format!("LIMIT {}", 3 * CHUNK_CANDIDATES)));
+        assert!(q.contains("SUM(similarity_score) AS similarity_score"));
+        assert!(q.contains(&format!("WHERE chunk_rank <= {MATCHING_CHUNKS_PER_ROW}")));
+        assert!(q.contains("GROUP BY product_id"));
+        assert!(q.contains("SELECT t0.product_name, t1.similarity_score, t1.chunks"));
+    }
+
+    #[test]
+    fn test_without_similarity_score() {
+        let result = serde_json::json!({"product_id": 1, "similarity_score": 0.5, "chunks": []});
+        assert_eq!(
+            without_similarity_score(&result),
+            serde_json::json!({"product_id": 1})
+        );
+    }
+
+    #[test]
+    fn test_rerank_results() {
+        let job_params = types::JobParams {
+            columns: vec!["product_name".to_string(), "description".to_string()],
+            ..products_job(EmbeddingStorage::vector)
+        };
+        let candidates: Vec<JsonB> = (0..3)
+            .map(|i| {
+                JsonB(serde_json::json!({
+                    "product_id": i,
+                    "product_name": format!("product {i}"),
+                    "description": "a product",
+                    "similarity_score": 0.5,
+                }))
+            })
+            .collect();
+        assert_eq!(
+            rerank_document(&candidates[1].0, &job_params),
+            "product 1 a product"
+        );
+
+        let ranking = vec![
+            RerankResult {
+                index: 0,
+                relevance_score: 0.1,
+            },
+            RerankResult {
+                index: 2,
+                relevance_score: 0.9,
+            },
+            RerankResult {
+                index: 1,
+                relevance_score: 0.5,
+            },
+        ];
+        let results = rerank_results(candidates, ranking);
+        let results = returned_results(results, &["description".to_string()], 2);
+        let results: Vec<Value> = results.into_iter().map(|r| r.0).collect();
+        assert_eq!(
+            results,
+            vec![
+                serde_json::json!({"product_id": 2, "product_name": "product 2", "similarity_score": 0.5, "rerank_score": 0.9}),
+                serde_json::json!({"product_id": 1, "product_name": "product 1", "similarity_score": 0.5, "rerank_score": 0.5}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mmr_select() {
+        // the second candidate is a near-duplicate of the first
+        let candidates = || -> Vec<JsonB> {
+            [0.9, 0.85, 0.5]
+                .iter()
+                .enumerate()
+                .map(|(i, score)| {
+                    JsonB(serde_json::json!({"product_id": i, "similarity_score": score}))
+                })
+                .collect()
+        };
+        let embeddings = vec![
+            Some(vec![1.0, 0.0]),
+            Some(vec![1.0, 0.01]),
+            Some(vec![0.0, 1.0]),
+        ];
+        let selected_ids = |lambda: f64| -> Vec<Value> {
+            mmr_select(candidates(), &embeddings, "similarity_score", lambda, 2)
+                .into_iter()
+                .map(|r| r.0["product_id"].clone())
+                .collect()
+        };
+        assert_eq!(selected_ids(1.0), vec![Value::from(0), Value::from(1)]);
+        assert_eq!(selected_ids(0.5), vec![Value::from(0), Value::from(2)]);
+
+        assert!(validate_mmr_lambda(Some(1.5)).is_err());
+        assert!(validate_mmr_lambda(None).is_ok());
+    }
+
+    #[test]
+    fn test_candidate_embeddings_query() {
+        let job_params = types::JobParams {
+            pkey_type: "integer".to_string(),
+            chunking: Some(ChunkConfig::new(types::ChunkStrategy::characters, 1000, 0).unwrap()),
+            ..products_job(EmbeddingStorage::halfvec)
+        };
+        let q = candidate_embeddings_query("myjob", &job_params);
+        assert!(q.contains("chunk_index AS chunk_index"));
+        assert!(q.contains("embeddings::vector::real[]::float8[] AS embedding"));
+        assert!(q.contains("FROM vectorize._embeddings_myjob"));
+        assert!(q.contains("WHERE product_id = ANY ($1::text[]::integer[])"));
+
+        let job_params = types::JobParams {
+            pkey_type: "integer".to_string(),
+            table_method: TableMethod::append,
+            ..products_job(EmbeddingStorage::vector)
+        };
+        let q = candidate_embeddings_query("myjob", &job_params);
+        assert!(q.contains("0 AS chunk_index"));
+        assert!(q.contains("myjob_embeddings::vector::real[]::float8[] AS embedding"));
+        assert!(q.contains("FROM public.products"));
+    }
+
+    #[test]
+    fn test_bit_query_reranks_candidates() {
+        let q = join_table_similarity(
+            "myjob",
+            &products_job(EmbeddingStorage::bit),
+            &types::IndexDist::pgv_hnsw_cosine,
+            &["product_name".to_string()],
+            3,
+            None,
+            384,
+        );
+        assert!(q.contains(
+            "ORDER BY binary_quantize(embeddings)::bit(384) <~> binary_quantize($1::vector)::bit(384)"
+        ));
+        assert!(q.contains(&format!("LIMIT {}", 3 * BINARY_QUANTIZED_CANDIDATES)));
+        assert!(q.contains("ORDER BY embeddings <=> $1::vector"));
+    }
+}
@@ -4,7 +4,8 @@ use anyhow::{Context, Result};
 use pgrx::prelude::*;
 
 use vectorize_core::guc::ModelGucConfig;
-use vectorize_core::transformers::providers::get_provider;
+use vectorize_core::transformers::http_handler::RetryConfig;
+use vectorize_core::transformers::providers::get_model_provider;
 use vectorize_core::transformers::types::TransformerMetadata;
 use vectorize_core::types::Model;
 
@@ -13,7 +14,7 @@ pub fn mod_info(model_name: &str, api_key: default!(Option<String>, "NULL")) ->
     let transformer_model = Model::new(model_name)
         .context("Invalid model name")
         .unwrap();
-    let mut guc_configs = guc::get_guc_configs(&transformer_model.source);
+    let mut guc_configs = guc::get_guc_configs(&transformer_model);
     if let Some(key) = api_key {
         guc_configs.api_key = Some(key);
     }
@@ -43,11 +44,11 @@ pub async fn get_model_info(
     model: &Model,
     guc_configs: &ModelGucConfig,
 ) -> Result<TransformerMetadata> {
-    let provider = get_provider(
+    let provider = get_model_provider(
         &model.source,
         guc_configs.api_key.clone(),
-        guc_configs.service_url.clone(),
-        guc_configs.virtual_key.clone(),
+        guc_configs,
+        RetryConfig::interactive(),
     )?;
     let dim = provider.model_dim(&model.api_name()).await?;
     Ok(TransformerMetadata {
@@ -1,42 +1,107 @@
-pub mod generic;
 pub mod http_handler;
 pub mod openai;
 
 use crate::guc;
 use pgrx::prelude::*;
+use pgrx::{spi, JsonB};
 
 use vectorize_core::guc::ModelGucConfig;
-use vectorize_core::transformers::providers::{self, prepare_generic_embedding_request};
-use vectorize_core::transformers::types::Inputs;
-use vectorize_core::types::Model;
+use vectorize_core::transformers::http_handler::RetryConfig;
+use vectorize_core::transformers::providers::{
+    self, prepare_generic_embedding_request, GenericRerankRequest, RerankResult,
+};
+use vectorize_core::transformers::types::{Inputs, SparseEmbedding};
+use vectorize_core::types::{InputType, Model};
+
+pub fn transform(
+    input: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    input_type: InputType,
+    dimensions: Option<u32>,
+) -> Vec<Vec<f64>> {
+    let cache_key = input_type.cache_key(transformer, dimensions);
+    if let Some(embedding) = cached_embedding(input, &cache_key) {
+        return vec![embedding];
+    }
 
-pub fn transform(input: &str, transformer: &Model, api_key: Option<String>) -> Vec<Vec<f64>> {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
 
-    let guc_configs: ModelGucConfig = guc::get_guc_configs(&transformer.source);
+    let guc_configs: ModelGucConfig = guc::get_guc_configs(transformer);
     let api_key = if let Some(k) = api_key {
         Some(k)
     } else {
-        guc_configs.api_key
+        guc_configs.api_key.clone()
     };
 
-    let provider = providers::get_provider(
+    let provider = providers::get_model_provider(
         &transformer.source,
         api_key,
-        guc_configs.service_url,
-        guc_configs.virtual_key,
+        &guc_configs,
+        RetryConfig::interactive(),
     )
     .expect("failed to get provider");
-    let input = Inputs {
+    let inputs = Inputs {
         record_id: "".to_string(),
         inputs: input.to_string(),
         token_estimate: 0,
     };
-    let embedding_request = prepare_generic_embedding_request(transformer, &[input]);
+    let embedding_request =
+        prepare_generic_embedding_request(transformer, &[inputs], input_type, dimensions);
+    match runtime.block_on(async { provider.generate_embedding(&embedding_request).await }) {
+        Ok(e) => {
+            let embeddings = providers::reduce_dimensions(e.embeddings, dimensions);
+            cache_embeddings(input, &cache_key, &embeddings);
+            embeddings
+        }
+        Err(e) => {
+            error!("error getting embeddings: {}", e);
+        }
+    }
+}
+
+// the embeddings of the inputs, in a single request to the provider
+// used for many short inputs, e.g. the sentences of a document, which are not cached
+pub fn transform_batch(
+    inputs: &[String],
+    transformer: &Model,
+    api_key: Option<String>,
+    input_type: InputType,
+) -> Vec<Vec<f64>> {
+    if inputs.is_empty() {
+        return vec![];
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+
+    let guc_configs: ModelGucConfig = guc::get_guc_configs(transformer);
+    let api_key = api_key.or(guc_configs.api_key.clone());
+
+    let provider = providers::get_model_provider(
+        &transformer.source,
+        api_key,
+        &guc_configs,
+        RetryConfig::interactive(),
+    )
+    .expect("failed to get provider");
+    let inputs: Vec<Inputs> = inputs
+        .iter()
+        .enumerate()
+        .map(|(position, input)| Inputs {
+            record_id: position.to_string(),
+            inputs: input.clone(),
+            token_estimate: 0,
+        })
+        .collect();
+    let embedding_request =
+        prepare_generic_embedding_request(transformer, &inputs, input_type, None);
     match runtime.block_on(async { provider.generate_embedding(&embedding_request).await }) {
         Ok(e) => e.embeddings,
         Err(e) => {
@@ -44,3 +109,102 @@ pub fn transform(input: &str, transformer: &Model, api_key: Option<String>) -> V
         }
     }
 }
+
+// the term weights of the input from a learned sparse model
+// sparse embeddings are not cached
+pub fn transform_sparse(
+    input: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    input_type: InputType,
+) -> SparseEmbedding {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+
+    let guc_configs: ModelGucConfig = guc::get_guc_configs(transformer);
+    let api_key = api_key.or(guc_configs.api_key.clone());
+
+    let provider = providers::get_model_provider(
+        &transformer.source,
+        api_key,
+        &guc_configs,
+        RetryConfig::interactive(),
+    )
+    .expect("failed to get provider");
+    let inputs = Inputs {
+        record_id: "".to_string(),
+        inputs: input.to_string(),
+        token_estimate: 0,
+    };
+    let embedding_request =
+        prepare_generic_embedding_request(transformer, &[inputs], input_type, None);
+    match runtime.block_on(async { provider.generate_sparse_embedding(&embedding_request).await }) {
+        Ok(mut e) => e.embeddings.remove(0),
+        Err(e) => {
+            error!("error getting sparse embeddings: {}", e);
+        }
+    }
+}
+
+// the relevance of each of the documents to the query, from the rerank model's provider
+pub fn rerank(query: &str, documents: &[String], rerank_model: &Model) -> Vec<RerankResult> {
+    if documents.is_empty() {
+        return vec![];
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+
+    let guc_configs: ModelGucConfig = guc::get_guc_configs(rerank_model);
+    let provider = providers::get_rerank_provider(
+        &rerank_model.source,
+        guc_configs.api_key.clone(),
+        &guc_configs,
+        RetryConfig::interactive(),
+    )
+    .unwrap_or_else(|e| error!("failed to get rerank provider: {}", e));
+    let request = GenericRerankRequest {
+        model: rerank_model.api_name(),
+        query: query.to_string(),
+        documents: documents.to_vec(),
+    };
+    match runtime.block_on(async { provider.rerank(&request).await }) {
+        Ok(response) => response.results,
+        Err(e) => {
+            error!("error reranking search results: {}", e);
+        }
+    }
+}
+
+// the embedding of the input from vectorize.embedding_cache, when the cache is enabled
+fn cached_embedding(input: &str, cache_key: &str) -> Option<Vec<f64>> {
+    let cached: Result<Option<Vec<f64>>, spi::Error> = Spi::connect_mut(|c| {
+        let rows = c.update(
+            "SELECT embedding FROM vectorize._embedding_cache_get($1, ARRAY[$2]);",
+            None,
+            &[cache_key.into(), input.into()],
+        )?;
+        let mut embedding = None;
+        for row in rows {
+            embedding = row["embedding"].value::<Vec<f64>>()?;
+        }
+        Ok(embedding)
+    });
+    cached.unwrap_or_else(|e| error!("error reading the embedding cache: {}", e))
+}
+
+// stores the embeddings of the input in vectorize.embedding_cache, when the cache is enabled
+fn cache_embeddings(input: &str, cache_key: &str, embeddings: &[Vec<f64>]) {
+    let embeddings = JsonB(serde_json::to_value(embeddings).expect("error serializing embeddings"));
+    if let Err(e) = Spi::run_with_args(
+        "SELECT vectorize._embedding_cache_put($1, ARRAY[$2], $3);",
+        &[cache_key.into(), input.into(), embeddings.into()],
+    ) {
+        error!("error writing the embedding cache: {}", e);
+    }
+}
@@ -1,6 +1,8 @@
 use pgrx::*;
 use vectorize_core::types::{
-    IndexDist as CoreIndexDist, SimilarityAlg as CoreSimilarityAlg, TableMethod as CoreTableMethod,
+    ChunkAggregation as CoreChunkAggregation, ChunkStrategy as CoreChunkStrategy,
+    EmbeddingStorage as CoreEmbeddingStorage, IndexDist as CoreIndexDist,
+    SimilarityAlg as CoreSimilarityAlg, TableMethod as CoreTableMethod,
 };
 
 use serde::{Deserialize, Serialize};
@@ -61,3 +63,63 @@ impl From<IndexDist> for CoreIndexDist {
         }
     }
 }
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Serialize, Deserialize, PostgresEnum)]
+pub enum EmbeddingStorage {
+    vector,
+    halfvec,
+    bit,
+    sparsevec,
+}
+
+impl From<EmbeddingStorage> for CoreEmbeddingStorage {
+    fn from(storage: EmbeddingStorage) -> Self {
+        match storage {
+            EmbeddingStorage::vector => CoreEmbeddingStorage::vector,
+            EmbeddingStorage::halfvec => CoreEmbeddingStorage::halfvec,
+            EmbeddingStorage::bit => CoreEmbeddingStorage::bit,
+            EmbeddingStorage::sparsevec => CoreEmbeddingStorage::sparsevec,
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PostgresEnum)]
+pub enum ChunkStrategy {
+    #[default]
+    characters,
+    tokens,
+    markdown,
+    code,
+    semantic,
+}
+
+impl From<ChunkStrategy> for CoreChunkStrategy {
+    fn from(strategy: ChunkStrategy) -> Self {
+        match strategy {
+            ChunkStrategy::characters => CoreChunkStrategy::characters,
+            ChunkStrategy::tokens => CoreChunkStrategy::tokens,
+            ChunkStrategy::markdown => CoreChunkStrategy::markdown,
+            ChunkStrategy::code => CoreChunkStrategy::code,
+            ChunkStrategy::semantic => CoreChunkStrategy::semantic,
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PostgresEnum)]
+pub enum ChunkAggregation {
+    #[default]
+    max,
+    sum,
+}
+
+impl From<ChunkAggregation> for CoreChunkAggregation {
+    fn from(aggregation: ChunkAggregation) -> Self {
+        match aggregation {
+            ChunkAggregation::max => CoreChunkAggregation::max,
+            ChunkAggregation::sum => CoreChunkAggregation::sum,
+        }
+    }
+}
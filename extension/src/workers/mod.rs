@@ -5,7 +5,9 @@ use pgmq::{Message, PGMQueueExt};
 use pgrx::*;
 use sqlx::{Pool, Postgres};
 use vectorize_core::types;
-use vectorize_core::worker::base::execute_job;
+use vectorize_core::worker::base::{
+    dead_letter, execute_job, finalize_if_backfilled, is_retryable,
+};
 
 pub async fn run_worker(
     queue: PGMQueueExt,
@@ -31,7 +33,7 @@ pub async fn run_worker(
         "pg-vectorize: received message for job: {:?}",
         msg.message.job_name
     );
-    let job_success = execute_job(&conn.clone(), msg).await;
+    let job_success = execute_job(&conn.clone(), msg.clone()).await;
     let delete_it = match job_success {
         Ok(_) => {
             info!("pg-vectorize: job success");
@@ -39,7 +41,26 @@ pub async fn run_worker(
         }
         Err(e) => {
             warning!("pg-vectorize: job failed: {:?}", e);
-            read_ct > 2
+            if read_ct > 2 || !is_retryable(&e) {
+                // retries exhausted, dead_letter also removes the message from the queue
+                match dead_letter(conn, queue_name, &msg, Some(&e)).await {
+                    Ok(_) => {
+                        warning!("pg-vectorize: moved message to dead letter: {}", msg_id);
+                        if let Err(e) = finalize_if_backfilled(conn, queue_name, &msg, false).await
+                        {
+                            warning!("pg-vectorize: Error finalizing model change: {}", e);
+                        }
+                        false
+                    }
+                    Err(e) => {
+                        // the message stays on the queue, and is dead lettered again after its vt
+                        warning!("pg-vectorize: Error moving message to dead letter: {}", e);
+                        false
+                    }
+                }
+            } else {
+                false
+            }
         }
     };
 
@@ -48,6 +69,9 @@ pub async fn run_worker(
         match queue.delete(queue_name, msg_id).await {
             Ok(_) => {
                 info!("pg-vectorize: deleted message: {}", msg_id);
+                if let Err(e) = finalize_if_backfilled(conn, queue_name, &msg, true).await {
+                    warning!("pg-vectorize: Error finalizing model change: {}", e);
+                }
             }
             Err(e) => {
                 warning!("pg-vectorize: Error deleting message: {}", e);
@@ -1,5 +1,6 @@
 mod util;
 use rand::Rng;
+use sqlx::Row;
 use util::common;
 
 #[tokio::test]
@@ -705,6 +706,7 @@ async fn test_index_dist_type_hnsw_cosine() {
     );
 }
 
+#[tokio::test]
 async fn test_index_dist_type_hnsw_l2() {
     let conn = common::init_database().await;
     common::init_embedding_svc_url(&conn).await;
@@ -734,24 +736,22 @@ async fn test_index_dist_type_hnsw_l2() {
     .await
     .expect("failed to initialize job");
 
-    // Directly call vectorize.search to perform a search operation
-    let search_query = format!(
-        "SELECT * FROM vectorize.search(
-            job_name => '{job_name}',
-            query => 'search query',
-            return_columns => ARRAY['product_name'],
-            num_results => 10
-        );",
-        job_name = job_name
-    );
-
-    let search_result = sqlx::query(&search_query).fetch_all(&conn).await;
+    let search_results: Vec<common::SearchJSON> =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search with pgv_hnsw_l2");
+    assert_eq!(search_results.len(), 3);
 
-    // Assert that the search operation fails as expected with pgv_hnsw_l2
-    assert!(
-        search_result.is_err(),
-        "Expected search with pgv_hnsw_l2 to fail, but it succeeded."
-    );
+    // similarity_score is descending regardless of the distance metric
+    let scores: Vec<f64> = search_results
+        .iter()
+        .map(|r| {
+            r.search_results["similarity_score"]
+                .as_f64()
+                .expect("missing similarity_score")
+        })
+        .collect();
+    assert!(scores.windows(2).all(|w| w[0] >= w[1]));
 }
 
 #[tokio::test]
@@ -784,24 +784,22 @@ async fn test_index_dist_type_hnsw_ip() {
     .await
     .expect("failed to initialize job");
 
-    // Directly call vectorize.search to perform a search operation
-    let search_query = format!(
-        "SELECT * FROM vectorize.search(
-            job_name => '{job_name}',
-            query => 'search query',
-            return_columns => ARRAY['product_name'],
-            num_results => 10
-        );",
-        job_name = job_name
-    );
-
-    let search_result = sqlx::query(&search_query).fetch_all(&conn).await;
+    let search_results: Vec<common::SearchJSON> =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search with pgv_hnsw_ip");
+    assert_eq!(search_results.len(), 3);
 
-    // Assert that the search operation fails as expected with pgv_hnsw_l2
-    assert!(
-        search_result.is_err(),
-        "Expected search with pgv_hnsw_ip to fail, but it succeeded."
-    );
+    // similarity_score is descending regardless of the distance metric
+    let scores: Vec<f64> = search_results
+        .iter()
+        .map(|r| {
+            r.search_results["similarity_score"]
+                .as_f64()
+                .expect("missing similarity_score")
+        })
+        .collect();
+    assert!(scores.windows(2).all(|w| w[0] >= w[1]));
 }
 
 #[ignore]
@@ -902,6 +900,117 @@ async fn test_diskann_cosine() {
     assert_eq!(search_results.len(), 3);
 }
 
+#[tokio::test]
+async fn test_diskann_search_params() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_diskann_params_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    // diskann params are rejected for non-diskann indexes
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => 'hnsw_{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        index_dist_type => 'pgv_hnsw_cosine',
+        schedule => 'realtime',
+        diskann_query_rescore => 25
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    // per-job diskann params
+    sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        index_dist_type => 'vsc_diskann_cosine',
+        schedule => 'realtime',
+        diskann_search_list_size => 75,
+        diskann_query_rescore => 25
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search on diskann");
+    assert_eq!(search_results.len(), 3);
+
+    let idx_scans = |conn: sqlx::Pool<sqlx::Postgres>, index_name: String| async move {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(SUM(idx_scan), 0)::bigint FROM pg_stat_user_indexes WHERE indexrelname = $1",
+        )
+        .bind(index_name)
+        .fetch_one(&conn)
+        .await
+        .expect("failed to read index stats")
+    };
+    let index_name = format!("{job_name}_diskann_idx");
+    let scans_before = idx_scans(conn.clone(), index_name.clone()).await;
+
+    // per-call params override the job's params, and only last for the transaction
+    let mut tx = conn.begin().await.expect("failed to begin transaction");
+    sqlx::query("SET LOCAL enable_seqscan = off")
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    let rows = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search(
+        job_name => '{job_name}',
+        query => 'mobile devices',
+        return_columns => ARRAY['product_id', 'product_name'],
+        num_results => 3,
+        diskann_query_rescore => 10
+    );"
+    ))
+    .fetch_all(&mut *tx)
+    .await
+    .expect("failed to search with diskann params");
+    assert_eq!(rows.len(), 3);
+    let rescore: String = sqlx::query_scalar("SELECT current_setting('diskann.query_rescore')")
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+    assert_eq!(rescore, "10");
+    let list_size: String =
+        sqlx::query_scalar("SELECT current_setting('diskann.query_search_list_size')")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+    assert_eq!(list_size, "75");
+    tx.commit().await.expect("failed to commit");
+
+    // the search must have been served by the diskann index
+    // index stats are flushed asynchronously, so allow for some delay
+    let mut scans_after = scans_before;
+    for _ in 0..10 {
+        scans_after = idx_scans(conn.clone(), index_name.clone()).await;
+        if scans_after > scans_before {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+    assert!(
+        scans_after > scans_before,
+        "diskann index was not used by search"
+    );
+}
+
 #[ignore]
 #[tokio::test]
 async fn test_cohere() {
@@ -1092,6 +1201,200 @@ async fn test_chunk_table() {
     assert_eq!(rows[7].2, "pieces.");
 }
 
+#[tokio::test]
+async fn test_chunk_table_strategies() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("chunk_docs_{}", test_num);
+    let output_table_name = format!("chunked_docs_{}", test_num);
+
+    // text primary key, and a row without text
+    sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (slug TEXT PRIMARY KEY, body TEXT)"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create test table");
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (slug, body) VALUES
+        ('intro', E'# Intro\\nWelcome to the docs.\\n## Install\\nRun the installer.'),
+        ('usage', E'# Usage\\nCall the API.'),
+        ('empty', NULL)"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert data into test table");
+
+    sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+            input_table => '{test_table_name}',
+            column_name => 'body',
+            primary_key => 'slug',
+            max_chunk_size => 30,
+            output_table => '{output_table_name}',
+            strategy => 'markdown'
+        )"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // every row is chunked, and the offsets locate each chunk in its source text
+    let rows: Vec<(String, i32, String, bool)> = sqlx::query_as(&format!(
+        "SELECT o.original_id, o.chunk_index, o.chunk,
+            substr(t.body, o.start_offset + 1, o.end_offset - o.start_offset) = o.chunk
+        FROM {output_table_name} o
+        INNER JOIN {test_table_name} t ON t.slug = o.original_id
+        ORDER BY o.original_id, o.chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to select chunked data");
+    let chunks: Vec<(&str, i32, &str)> = rows
+        .iter()
+        .map(|(id, index, chunk, _)| (id.as_str(), *index, chunk.as_str()))
+        .collect();
+    assert_eq!(
+        chunks,
+        vec![
+            ("intro", 0, "# Intro\nWelcome to the docs."),
+            ("intro", 1, "## Install\nRun the installer."),
+            ("usage", 0, "# Usage\nCall the API."),
+        ]
+    );
+    assert!(rows.iter().all(|(_, _, _, located)| *located));
+
+    // token chunks with overlap repeat the end of the previous chunk
+    let chunks: Vec<(String, i32, i32)> = sqlx::query_as(
+        "SELECT chunk, start_offset, end_offset
+        FROM vectorize.chunk_document(repeat('alpha beta gamma delta ', 4), 'tokens', 6, 2)",
+    )
+    .fetch_all(&conn)
+    .await
+    .expect("failed to chunk document");
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[0].0, "alpha beta gamma delta alpha beta");
+    for pair in chunks.windows(2) {
+        assert!(pair[1].1 < pair[0].2);
+        assert!(pair[0].0.ends_with("alpha beta") && pair[1].0.starts_with("alpha beta"));
+    }
+
+    // code is split at the functions of its syntax tree
+    let chunks: Vec<String> = sqlx::query_scalar(
+        "SELECT chunk
+        FROM vectorize.chunk_document(E'def a():\\n    return 1\\n\\n\\ndef b():\\n    return 2\\n', 'code', 30, language => 'python')
+        ORDER BY chunk_index",
+    )
+    .fetch_all(&conn)
+    .await
+    .expect("failed to chunk code");
+    assert_eq!(
+        chunks,
+        vec!["def a():\n    return 1", "def b():\n    return 2"]
+    );
+    let result = sqlx::query("SELECT * FROM vectorize.chunk_document('fn a() {}', 'code', 30)")
+        .execute(&conn)
+        .await;
+    assert!(result.is_err());
+
+    // invalid chunk configs are rejected
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'body', 'slug', 10, '{output_table_name}', 'characters', 10)"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    // output tables of earlier versions, without offsets, gain the offset columns
+    let old_output_table = format!("{output_table_name}_old");
+    sqlx::query(&format!(
+        "CREATE TABLE {old_output_table} (
+            id SERIAL PRIMARY KEY,
+            original_id TEXT,
+            chunk_index INT,
+            chunk TEXT
+        )"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create old output table");
+    sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'body', 'slug', 30, '{old_output_table}', 'markdown')"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk into old output table");
+    let located: Vec<bool> = sqlx::query_scalar(&format!(
+        "SELECT substr(t.body, o.start_offset + 1, o.end_offset - o.start_offset) = o.chunk
+        FROM {old_output_table} o
+        INNER JOIN {test_table_name} t ON t.slug = o.original_id"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to select old output table");
+    assert_eq!(located, vec![true; 3]);
+}
+
+#[tokio::test]
+async fn test_chunk_semantic() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let document = "Cats purr when they are content. Most cats sleep all day. \
+        Rust compiles to native code. It has no garbage collector.";
+
+    let chunk_semantic = |threshold: f64, max_chunk_size: &str| {
+        format!(
+            "SELECT chunk, substr('{document}', start_offset + 1, end_offset - start_offset) = chunk
+            FROM vectorize.chunk_semantic(
+                '{document}',
+                threshold => {threshold},
+                max_chunk_size => {max_chunk_size}
+            ) ORDER BY chunk_index"
+        )
+    };
+
+    // no similarity of adjacent sentences is below the lowest one
+    let chunks: Vec<(String, bool)> = sqlx::query_as(&chunk_semantic(0.0, "NULL"))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to chunk document");
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].0, document);
+
+    // only the most similar pair of the four sentences stays together
+    let chunks: Vec<(String, bool)> = sqlx::query_as(&chunk_semantic(100.0, "NULL"))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to chunk document");
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks.iter().all(|(_, located)| *located));
+
+    // chunks end before they grow larger than the max size
+    let chunks: Vec<(String, bool)> = sqlx::query_as(&chunk_semantic(0.0, "40"))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to chunk document");
+    assert_eq!(chunks.len(), 4);
+    assert!(chunks
+        .iter()
+        .all(|(chunk, located)| *located && chunk.chars().count() <= 40));
+
+    // the semantic strategy needs a model
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.chunk_document('{document}', 'semantic', 100)"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.chunk_semantic('{document}', threshold => 101)"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
 #[tokio::test]
 async fn test_import_embeddings() {
     let conn = common::init_database().await;
@@ -1460,3 +1763,1381 @@ async fn test_table_from() {
         "Expected cron job to still have 2 embeddings"
     );
 }
+
+#[tokio::test]
+async fn test_job_lifecycle() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+    let embeddings_table = format!("vectorize._embeddings_{job_name}");
+    let initial_rows = common::row_count(&embeddings_table, &conn).await;
+
+    // rows inserted while paused are not embedded
+    let _ = sqlx::query(&format!("SELECT vectorize.pause_job('{job_name}');"))
+        .execute(&conn)
+        .await
+        .expect("failed to pause job");
+    let paused: bool = sqlx::query_scalar(&format!(
+        "SELECT (params->>'paused')::bool FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(paused);
+    let paused_at: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT params->>'paused_at' FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(paused_at.is_some());
+    let random_product_id = rng.gen_range(100000..200000);
+    sqlx::query(&format!(
+        "INSERT INTO \"{test_table_name}\"(product_id, product_name, description, product_category, price)
+        VALUES ({random_product_id}, 'car tester', $$a product for testing car's components$$, 'electronics', 10.99);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert into test_table");
+    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    assert_eq!(
+        common::row_count(&embeddings_table, &conn).await,
+        initial_rows
+    );
+
+    // resuming catches up on rows inserted while paused
+    let _ = sqlx::query(&format!("SELECT vectorize.resume_job('{job_name}');"))
+        .execute(&conn)
+        .await
+        .expect("failed to resume job");
+    let mut caught_up = false;
+    for _ in 0..10 {
+        if common::row_count(&embeddings_table, &conn).await == initial_rows + 1 {
+            caught_up = true;
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert!(caught_up);
+
+    // alter the embedded columns
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.alter_job(
+            job_name => '{job_name}',
+            columns => ARRAY['product_name', 'description']
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to alter job");
+    let columns: serde_json::Value = sqlx::query_scalar(&format!(
+        "SELECT params->'columns' FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(columns, serde_json::json!(["product_name", "description"]));
+
+    // a cron schedule requires an update column
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.alter_job(job_name => '{job_name}', schedule => '* * * * *');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    // dropping removes the job, its triggers and embeddings
+    let _ = sqlx::query(&format!("SELECT vectorize.drop_job('{job_name}');"))
+        .execute(&conn)
+        .await
+        .expect("failed to drop job");
+    let job_count =
+        common::row_count(&format!("vectorize.job WHERE name = '{job_name}'"), &conn).await;
+    assert_eq!(job_count, 0);
+    let trigger_count = common::row_count(
+        &format!("pg_trigger WHERE tgname = 'vectorize_insert_trigger_{job_name}'"),
+        &conn,
+    )
+    .await;
+    assert_eq!(trigger_count, 0);
+    let embeddings_exists: bool = sqlx::query_scalar(&format!(
+        "SELECT to_regclass('{embeddings_table}') IS NOT NULL;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(!embeddings_exists);
+    let search = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search(job_name => '{job_name}', query => 'mobile devices');"
+    ))
+    .fetch_all(&conn)
+    .await;
+    assert!(search.is_err());
+}
+
+#[tokio::test]
+async fn test_alter_job_rebuilds_fts_index() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    // the index type is set for this transaction only, so that concurrent tests are unaffected
+    let mut tx = conn.begin().await.expect("failed to begin transaction");
+    sqlx::query("SET LOCAL vectorize.experimental_fts_index_type = 'GIN';")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to set fts index type");
+    sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&mut *tx)
+    .await
+    .expect("failed to init job");
+    sqlx::query(&format!(
+        "SELECT vectorize.alter_job(
+            job_name => '{job_name}',
+            columns => ARRAY['product_name', 'description']
+        );"
+    ))
+    .execute(&mut *tx)
+    .await
+    .expect("failed to alter job");
+
+    let indexdef: String = sqlx::query_scalar(&format!(
+        "SELECT indexdef FROM pg_indexes WHERE indexname = '{job_name}_idx';"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .expect("failed to get fts index");
+    assert!(indexdef.contains("USING gin"));
+    assert!(indexdef.contains("description"));
+    tx.rollback().await.expect("failed to rollback");
+}
+
+#[tokio::test]
+async fn test_change_model() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.change_model('{job_name}', 'sentence-transformers/all-MiniLM-L12-v2');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to change model");
+
+    // search keeps working on the old embeddings during the migration
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // the worker swaps in the new embeddings once the backfill completes
+    let mut transformer = String::new();
+    for _ in 0..30 {
+        transformer = sqlx::query_scalar(&format!(
+            "SELECT transformer FROM vectorize.job WHERE name = '{job_name}';"
+        ))
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        if transformer == "sentence-transformers/all-MiniLM-L12-v2" {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(transformer, "sentence-transformers/all-MiniLM-L12-v2");
+
+    let migrating: bool = sqlx::query_scalar(&format!(
+        "SELECT params ? 'model_migration' FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(!migrating);
+    let shadow_exists: bool = sqlx::query_scalar(&format!(
+        "SELECT to_regclass('vectorize._embeddings_{job_name}_shadow') IS NOT NULL;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(!shadow_exists);
+    let rows = common::row_count(&format!("vectorize._embeddings_{job_name}"), &conn).await;
+    let src_rows = common::row_count(&format!("public.{test_table_name}"), &conn).await;
+    assert_eq!(rows, src_rows);
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // the project view is recreated over the new embeddings
+    let view_rows = common::row_count(&format!("vectorize.{job_name}_view"), &conn).await;
+    assert_eq!(view_rows, src_rows);
+
+    // there is no model change left to abort
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.abort_model_change('{job_name}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_truncate_reload_and_resync() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let embeddings_table = format!("vectorize._embeddings_{job_name}");
+    let src_rows = common::row_count(&test_table_name, &conn).await;
+    let wait_for_rows = |expected: i64| {
+        let conn = conn.clone();
+        let embeddings_table = embeddings_table.clone();
+        async move {
+            for _ in 0..15 {
+                if common::row_count(&embeddings_table, &conn).await == expected {
+                    return true;
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            }
+            false
+        }
+    };
+    assert!(wait_for_rows(src_rows).await);
+
+    // deletes are removed from the embeddings
+    sqlx::query(&format!(
+        "DELETE FROM {test_table_name} WHERE product_id = (SELECT MIN(product_id) FROM {test_table_name});"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete from source");
+    assert_eq!(
+        common::row_count(&embeddings_table, &conn).await,
+        src_rows - 1
+    );
+
+    // truncate clears the embeddings, and reloaded rows are embedded again
+    sqlx::query(&format!("TRUNCATE {test_table_name};"))
+        .execute(&conn)
+        .await
+        .expect("failed to truncate source");
+    assert_eq!(common::row_count(&embeddings_table, &conn).await, 0);
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} SELECT * FROM vectorize.example_products;"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to reload source");
+    assert!(wait_for_rows(src_rows).await);
+
+    // resync removes orphaned embeddings and enqueues missing rows
+    sqlx::query(&format!(
+        "INSERT INTO {embeddings_table} (product_id, embeddings)
+        SELECT -1, embeddings FROM {embeddings_table} LIMIT 1;"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert orphaned embedding");
+    sqlx::query(&format!(
+        "DELETE FROM {embeddings_table} WHERE product_id IN (
+            SELECT product_id FROM {embeddings_table} WHERE product_id > 0 LIMIT 3
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete embeddings");
+    let result: String = sqlx::query_scalar(&format!("SELECT vectorize.resync('{job_name}');"))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to resync");
+    assert!(result.contains("removed 1 orphaned"));
+    assert!(result.contains("enqueued 3 row"));
+    assert!(wait_for_rows(src_rows).await);
+}
+
+#[tokio::test]
+async fn test_replay_dead_letters() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // simulate a message that exhausted its retries
+    let embeddings_table = format!("vectorize._embeddings_{job_name}");
+    let product_id: i32 = sqlx::query_scalar(&format!(
+        "DELETE FROM {embeddings_table}
+        WHERE product_id = (SELECT MIN(product_id) FROM {embeddings_table})
+        RETURNING product_id;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to delete embedding");
+    sqlx::query(&format!(
+        "INSERT INTO vectorize.dead_letter
+            (msg_id, job_name, message, read_ct, enqueued_at, error, provider, http_status)
+        VALUES (
+            -1,
+            '{job_name}',
+            jsonb_build_object('job_name', '{job_name}', 'record_ids', ARRAY['{product_id}']),
+            3,
+            NOW(),
+            'Failed to call method ''embeddings''',
+            'sentence-transformers',
+            503
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert dead letter");
+
+    let dead_letters =
+        common::row_count(&format!("vectorize.dead_letters('{job_name}')"), &conn).await;
+    assert_eq!(dead_letters, 1);
+
+    let replayed: i64 = sqlx::query_scalar(&format!(
+        "SELECT vectorize.replay_dead_letters(job_name => '{job_name}');"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to replay dead letters");
+    assert_eq!(replayed, 1);
+    let dead_letters =
+        common::row_count(&format!("vectorize.dead_letters('{job_name}')"), &conn).await;
+    assert_eq!(dead_letters, 0);
+
+    // the replayed message is processed by the worker
+    let mut restored = false;
+    for _ in 0..15 {
+        let count = common::row_count(
+            &format!("{embeddings_table} WHERE product_id = {product_id}"),
+            &conn,
+        )
+        .await;
+        if count == 1 {
+            restored = true;
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert!(restored);
+}
+
+#[tokio::test]
+async fn test_job_status() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let src_rows = common::row_count(&test_table_name, &conn).await;
+    let mut embedded_rows = 0;
+    for _ in 0..15 {
+        let row = sqlx::query(&format!(
+            "SELECT * FROM vectorize.job_status('{job_name}');"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get job status");
+        assert_eq!(row.get::<String, _>("name"), job_name);
+        assert_eq!(row.get::<i64, _>("total_rows"), src_rows);
+        // realtime jobs have no update column to compare against
+        assert!(row.get::<Option<i64>, _>("stale_rows").is_none());
+        assert!(!row.get::<bool, _>("paused"));
+        embedded_rows = row.get::<i64, _>("embedded_rows");
+        if embedded_rows == src_rows {
+            assert_eq!(row.get::<i64, _>("queued_messages"), 0);
+            assert_eq!(row.get::<i64, _>("failed_messages"), 0);
+            assert!(row.get::<Option<String>, _>("last_error").is_none());
+            assert!(row
+                .get::<Option<chrono::DateTime<chrono::Utc>>, _>("last_success_at")
+                .is_some());
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(embedded_rows, src_rows);
+}
+
+#[tokio::test]
+async fn test_skip_unchanged_rows() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let src_rows = common::row_count(&test_table_name, &conn).await;
+    let hashed_rows_query = format!(
+        "(SELECT * FROM vectorize._embeddings_{job_name} WHERE content_hash IS NOT NULL) t"
+    );
+    let mut hashed_rows = 0;
+    for _ in 0..15 {
+        hashed_rows = common::row_count(&hashed_rows_query, &conn).await;
+        if hashed_rows == src_rows {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(hashed_rows, src_rows);
+
+    // the description is not embedded, so no row needs new embeddings
+    sqlx::query(&format!(
+        "UPDATE {test_table_name} SET description = description || ' (updated)';"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update description");
+
+    let mut skipped_rows = 0;
+    for _ in 0..15 {
+        skipped_rows = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT skipped_rows FROM vectorize.job_status('{job_name}');"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get job status");
+        if skipped_rows == src_rows {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(skipped_rows, src_rows);
+
+    // a changed input is embedded again, with a new hash
+    let old_hash: String = sqlx::query_scalar(&format!(
+        "SELECT content_hash FROM vectorize._embeddings_{job_name} WHERE product_id = 1;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get content hash");
+    sqlx::query(&format!(
+        "UPDATE {test_table_name} SET product_name = 'Pencil Sharpener' WHERE product_id = 1;"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update product name");
+
+    let mut new_hash = old_hash.clone();
+    for _ in 0..15 {
+        new_hash = sqlx::query_scalar(&format!(
+            "SELECT content_hash FROM vectorize._embeddings_{job_name} WHERE product_id = 1;"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get content hash");
+        if new_hash != old_hash {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_ne!(new_hash, old_hash);
+    let skipped_rows: i64 = sqlx::query_scalar(&format!(
+        "SELECT skipped_rows FROM vectorize.job_status('{job_name}');"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get job status");
+    assert_eq!(skipped_rows, src_rows);
+}
+
+#[tokio::test]
+async fn test_embedding_cache() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let model = "sentence-transformers/all-MiniLM-L6-v2";
+    let input = format!("cached query {test_num}");
+
+    // enabled for this transaction only, so that concurrent tests are unaffected
+    let mut tx = conn.begin().await.expect("failed to begin transaction");
+    sqlx::query("SET LOCAL vectorize.embedding_cache = on;")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to enable cache");
+    let stats_query = format!(
+        "SELECT hits, misses FROM vectorize.embedding_cache_stats WHERE model = '{model}';"
+    );
+    let before: (i64, i64) = sqlx::query_as(&stats_query)
+        .fetch_optional(&mut *tx)
+        .await
+        .expect("failed to get cache stats")
+        .unwrap_or((0, 0));
+
+    let encode_query = format!("SELECT vectorize.encode('{input}', '{model}');");
+    let first: Vec<f64> = sqlx::query_scalar(&encode_query)
+        .fetch_one(&mut *tx)
+        .await
+        .expect("failed to encode");
+    let second: Vec<f64> = sqlx::query_scalar(&encode_query)
+        .fetch_one(&mut *tx)
+        .await
+        .expect("failed to encode");
+    assert_eq!(first, second);
+
+    let after: (i64, i64) = sqlx::query_as(&stats_query)
+        .fetch_one(&mut *tx)
+        .await
+        .expect("failed to get cache stats");
+    assert_eq!(after.0 - before.0, 1);
+    assert_eq!(after.1 - before.1, 1);
+
+    let purged: i64 = sqlx::query_scalar(&format!(
+        "SELECT vectorize.purge_embedding_cache('{model}');"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .expect("failed to purge cache");
+    assert!(purged >= 1);
+    let cached: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM vectorize.embedding_cache WHERE model = '{model}';"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .expect("failed to count cache");
+    assert_eq!(cached, 0);
+    tx.rollback().await.expect("failed to rollback");
+}
+
+#[tokio::test]
+async fn test_embedding_cache_read_only() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let model = "sentence-transformers/all-MiniLM-L6-v2";
+    let input = format!("read only cached query {test_num}");
+    let encode_query = format!("SELECT vectorize.encode('{input}', '{model}');");
+
+    let mut tx = conn.begin().await.expect("failed to begin transaction");
+    sqlx::query("SET LOCAL vectorize.embedding_cache = on;")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to enable cache");
+    let cached: Vec<f64> = sqlx::query_scalar(&encode_query)
+        .fetch_one(&mut *tx)
+        .await
+        .expect("failed to encode");
+    tx.commit().await.expect("failed to commit");
+
+    // a read-only transaction reads the cache without updating it
+    let mut tx = conn.begin().await.expect("failed to begin transaction");
+    sqlx::query("SET TRANSACTION READ ONLY;")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to set transaction read only");
+    sqlx::query("SET LOCAL vectorize.embedding_cache = on;")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to enable cache");
+    let hits: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM vectorize._embedding_cache_get('{model}', ARRAY['{input}']);"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .expect("failed to get cached embeddings");
+    assert_eq!(hits, 1);
+    let read_only: Vec<f64> = sqlx::query_scalar(&encode_query)
+        .fetch_one(&mut *tx)
+        .await
+        .expect("failed to encode in a read-only transaction");
+    assert_eq!(cached, read_only);
+    tx.rollback().await.expect("failed to rollback");
+
+    // only this test's entry, so that concurrent cache tests are unaffected
+    sqlx::query(&format!(
+        "DELETE FROM vectorize.embedding_cache WHERE model = '{model}' AND input_hash = md5('{input}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete cached embedding");
+}
+
+#[tokio::test]
+async fn test_model_providers() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let provider = format!("localserve{test_num}");
+
+    // the embedding service is OpenAI compatible, and needs no api key
+    sqlx::query(&format!(
+        "INSERT INTO vectorize.model_providers (name, base_url, api_format, auth_header)
+        VALUES ('{provider}', 'http://0.0.0.0:3000/v1', 'openai', NULL);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to register provider");
+
+    let registered: Vec<f64> = sqlx::query_scalar(&format!(
+        "SELECT vectorize.encode('hello world', '{provider}/sentence-transformers/all-MiniLM-L6-v2');"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to encode with registered provider");
+    assert_eq!(registered.len(), 384);
+
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+    sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => '{provider}/sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results: Vec<common::SearchJSON> =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // built-in sources cannot be registered
+    let builtin = sqlx::query(
+        "INSERT INTO vectorize.model_providers (name, base_url) VALUES ('openai', 'http://0.0.0.0:3000/v1');",
+    )
+    .execute(&conn)
+    .await;
+    assert!(builtin.is_err());
+}
+
+#[tokio::test]
+async fn test_input_prefixes() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        query_prefix => 'query: ',
+        document_prefix => 'passage: '
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let prefixes = sqlx::query_scalar::<_, serde_json::Value>(&format!(
+        "SELECT params->'input_prefixes' FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get job params");
+    assert_eq!(
+        prefixes,
+        serde_json::json!({"query": "query: ", "document": "passage: "})
+    );
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // an empty string removes the prefix
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.alter_job(job_name => '{job_name}', query_prefix => '');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to alter job");
+
+    let prefixes = sqlx::query_scalar::<_, serde_json::Value>(&format!(
+        "SELECT params->'input_prefixes' FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get job params");
+    assert_eq!(prefixes, serde_json::json!({"document": "passage: "}));
+}
+
+#[tokio::test]
+async fn test_reduced_dimensions() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    // all-MiniLM-L6-v2 returns 384 dimensional embeddings
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        dimensions => 1024
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        dimensions => 128
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let dims = sqlx::query_scalar::<_, i32>(&format!(
+        "SELECT vector_dims(embeddings) FROM vectorize._embeddings_{job_name} LIMIT 1;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get embedding dims");
+    assert_eq!(dims, 128);
+}
+
+#[tokio::test]
+async fn test_embedding_storage() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+
+    for (storage, col_type) in [("halfvec", "halfvec(384)"), ("bit", "vector(384)")] {
+        let job_name = format!("job_{storage}_{test_num}");
+        let _ = sqlx::query(&format!(
+            "SELECT vectorize.table(
+            job_name => '{job_name}',
+            relation => '{test_table_name}',
+            primary_key => 'product_id',
+            columns => ARRAY['product_name'],
+            transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+            schedule => 'realtime',
+            storage => '{storage}'
+        );"
+        ))
+        .execute(&conn)
+        .await
+        .expect("failed to init job");
+
+        let column_type = sqlx::query_scalar::<_, String>(&format!(
+            "SELECT format_type(atttypid, atttypmod) FROM pg_attribute
+            WHERE attrelid = 'vectorize._embeddings_{job_name}'::regclass AND attname = 'embeddings';"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get column type");
+        assert_eq!(column_type, col_type);
+
+        let search_results =
+            common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+                .await
+                .expect("failed to exec search");
+        assert_eq!(search_results.len(), 3);
+    }
+
+    // pgvectorscale only indexes vector columns
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => 'job_diskann_{test_num}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        index_dist_type => 'vsc_diskann_cosine',
+        storage => 'halfvec'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    // sparsevec storage is only for sparse jobs
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => 'job_sparsevec_{test_num}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        storage => 'sparsevec'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_sparse_hybrid_search() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+    let sparse_job_name = format!("job_sparse_{}", test_num);
+
+    for (job, transformer, sparse) in [
+        (&job_name, "sentence-transformers/all-MiniLM-L6-v2", false),
+        (
+            &sparse_job_name,
+            "naver/splade-cocondenser-ensembledistil",
+            true,
+        ),
+    ] {
+        let _ = sqlx::query(&format!(
+            "SELECT vectorize.table(
+            job_name => '{job}',
+            relation => '{test_table_name}',
+            primary_key => 'product_id',
+            columns => ARRAY['product_name'],
+            transformer => '{transformer}',
+            schedule => 'realtime',
+            sparse => {sparse}
+        );"
+        ))
+        .execute(&conn)
+        .await
+        .expect("failed to init job");
+        let search_results =
+            common::search_with_retry(&conn, "mobile devices", job, 10, 2, 3, None)
+                .await
+                .expect("failed to exec search");
+        assert_eq!(search_results.len(), 3);
+    }
+
+    // term weights are stored as a sparsevec of the size of the vocabulary
+    let column_type = sqlx::query_scalar::<_, String>(&format!(
+        "SELECT format_type(atttypid, atttypmod) FROM pg_attribute
+        WHERE attrelid = 'vectorize._embeddings_{sparse_job_name}'::regclass AND attname = 'embeddings';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get column type");
+    assert_eq!(column_type, "sparsevec(30522)");
+
+    let results = sqlx::query_as::<_, common::SearchJSON>(&format!(
+        "SELECT * FROM vectorize.hybrid_search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            return_columns => ARRAY['product_id', 'product_name'],
+            num_results => 3,
+            sparse_job_name => '{sparse_job_name}'
+        );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to exec hybrid search");
+    assert_eq!(results.len(), 3);
+    for result in &results {
+        assert!(result.search_results.get("sparse_rank").is_some());
+        assert!(result.search_results.get("full_text_rank").is_none());
+    }
+    // results found by both searches are fused into one
+    assert!(results.iter().any(|r| {
+        r.search_results["sparse_rank"].is_number() && r.search_results["semantic_rank"].is_number()
+    }));
+
+    // the transformer of a sparse job can not be changed
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.change_model(
+            job_name => '{sparse_job_name}',
+            new_transformer => 'sentence-transformers/all-MiniLM-L6-v2'
+        );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_search_rerank() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let results = sqlx::query_as::<_, common::SearchJSON>(&format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            return_columns => ARRAY['product_id', 'product_name'],
+            num_results => 3,
+            rerank_model => 'cross-encoder/ms-marco-MiniLM-L-6-v2'
+        );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to exec reranked search");
+    assert_eq!(results.len(), 3);
+    let scores: Vec<f64> = results
+        .iter()
+        .map(|r| r.search_results["rerank_score"].as_f64().unwrap())
+        .collect();
+    assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+    for result in &results {
+        assert!(result.search_results["similarity_score"].is_number());
+        // columns that are only selected to be reranked are not returned
+        assert!(result.search_results.get("description").is_none());
+    }
+
+    // models without a rerank provider are rejected
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            rerank_model => 'openai/text-embedding-3-small'
+        );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_search_mmr() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let mmr_query = |mmr_lambda: f64| {
+        format!(
+            "SELECT * FROM vectorize.search(
+                job_name => '{job_name}',
+                query => 'mobile devices',
+                return_columns => ARRAY['product_name'],
+                num_results => 3,
+                mmr_lambda => {mmr_lambda}
+            );"
+        )
+    };
+    let names = |results: &[common::SearchJSON]| -> Vec<serde_json::Value> {
+        results
+            .iter()
+            .map(|r| r.search_results["product_name"].clone())
+            .collect()
+    };
+
+    // a lambda of 1 keeps the order of the similarity search
+    let relevant = sqlx::query_as::<_, common::SearchJSON>(&mmr_query(1.0))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to exec mmr search");
+    let similar = sqlx::query_as::<_, common::SearchJSON>(&format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            return_columns => ARRAY['product_name'],
+            num_results => 3
+        );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to exec search");
+    assert_eq!(names(&relevant), names(&similar));
+
+    let diverse = sqlx::query_as::<_, common::SearchJSON>(&mmr_query(0.5))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to exec mmr search");
+    assert_eq!(diverse.len(), 3);
+    for result in &diverse {
+        assert!(result.search_results["similarity_score"].is_number());
+        // the primary key is only selected to look up embeddings
+        assert!(result.search_results.get("product_id").is_none());
+    }
+
+    let hybrid = sqlx::query_as::<_, common::SearchJSON>(&format!(
+        "SELECT * FROM vectorize.hybrid_search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            return_columns => ARRAY['product_name'],
+            num_results => 3,
+            mmr_lambda => 0.5
+        );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to exec hybrid search");
+    assert_eq!(hybrid.len(), 3);
+
+    // lambda must be between 0 and 1
+    let result = sqlx::query(&mmr_query(1.5)).execute(&conn).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_chunked_job_search() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_chunked_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        max_chunk_size => 40,
+        chunk_aggregation => 'sum'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    // one result per source row, with its best matching chunks
+    assert_eq!(search_results.len(), 3);
+    for result in &search_results {
+        let chunks = result.search_results["chunks"]
+            .as_array()
+            .expect("missing chunks");
+        assert!(!chunks.is_empty() && chunks.len() <= 3);
+        assert!(chunks[0].get("chunk_index").is_some());
+        assert!(chunks[0]["chunk"].as_str().unwrap().chars().count() <= 40);
+    }
+
+    // every chunk of a row is embedded
+    let source_rows = common::row_count(&test_table_name, &conn).await;
+    let embedded_chunks =
+        common::row_count(&format!("vectorize._embeddings_{job_name}"), &conn).await;
+    assert!(embedded_chunks > source_rows);
+
+    // chunks of an updated row are replaced
+    let _ = sqlx::query(&format!(
+        "UPDATE {test_table_name} SET description = 'short' WHERE product_id = 1;"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update row");
+    let mut chunks = 0;
+    for _ in 0..10 {
+        chunks = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM vectorize._embeddings_{job_name} WHERE product_id = 1 AND chunk = 'short';"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to count chunks");
+        if chunks == 1 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(chunks, 1);
+    let row_chunks = sqlx::query_scalar::<_, i64>(&format!(
+        "SELECT COUNT(*) FROM vectorize._embeddings_{job_name} WHERE product_id = 1;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(row_chunks, 1);
+
+    // chunked jobs can not be sparse
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => 'job_chunked_sparse_{test_num}',
+        relation => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'naver/splade-cocondenser-ensembledistil',
+        sparse => true,
+        max_chunk_size => 40
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let job_name = format!("job_{}", test_num);
+
+    // job-scoped buckets, so that concurrent tests are unaffected
+    sqlx::query(&format!(
+        "SELECT vectorize.set_rate_limit(
+            job_name => '{job_name}',
+            requests_per_minute => 2,
+            tokens_per_minute => 1000
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to set rate limit");
+
+    let acquire = |tokens: i32| {
+        let conn = conn.clone();
+        let job_name = job_name.clone();
+        async move {
+            sqlx::query_scalar::<_, f64>(
+                "SELECT vectorize._acquire_rate_limit('no-such-provider', $1, 1, $2);",
+            )
+            .bind(job_name)
+            .bind(tokens)
+            .fetch_one(&conn)
+            .await
+            .expect("failed to acquire rate limit")
+        }
+    };
+
+    assert_eq!(acquire(100).await, 0.0);
+    assert_eq!(acquire(100).await, 0.0);
+    // the request budget is spent, one request refills every 30 seconds
+    let wait = acquire(100).await;
+    assert!(wait > 0.0 && wait <= 30.0);
+
+    // the token budget is enforced on its own
+    sqlx::query(&format!(
+        "SELECT vectorize.set_rate_limit(job_name => '{job_name}', tokens_per_minute => 1000);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to set rate limit");
+    let wait = acquire(5000).await;
+    assert!(wait > 0.0 && wait <= 60.0);
+
+    // removing the limit
+    sqlx::query(&format!(
+        "SELECT vectorize.set_rate_limit(job_name => '{job_name}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to remove rate limit");
+    assert_eq!(acquire(100_000).await, 0.0);
+
+    // a limit applies to either a provider or a job
+    let result = sqlx::query(
+        "SELECT vectorize.set_rate_limit(provider => 'openai', job_name => 'x', requests_per_minute => 1);",
+    )
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
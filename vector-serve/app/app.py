@@ -7,6 +7,7 @@ from fastapi.middleware.cors import CORSMiddleware
 from app.routes.transform import router as transform_router
 from app.routes.info import router as info_router
 from app.routes.health import router as health_router
+from app.routes.rerank import router as rerank_router
 
 from app.models import load_model_cache
 
@@ -27,6 +28,7 @@ app.add_middleware(
 app.include_router(transform_router)
 app.include_router(info_router)
 app.include_router(health_router)
+app.include_router(rerank_router)
 
 
 def start_app_handler(app: FastAPI) -> Callable:
@@ -1,8 +1,10 @@
 import os
 import logging
 
+import torch
 from fastapi import FastAPI, HTTPException
-from sentence_transformers import SentenceTransformer
+from sentence_transformers import CrossEncoder, SentenceTransformer
+from transformers import AutoModelForMaskedLM, AutoTokenizer
 
 from app.metrics import ML_MODEL_COUNT
 
@@ -44,6 +46,9 @@ def load_model_cache(app: FastAPI) -> dict[str, SentenceTransformer]:
             m, cache_folder=cache_dir, local_files_only=LOCAL_FILES_ONLY
         )
     app.state.model_cache = model_cache
+    # sparse models and cross-encoders are only loaded on first use
+    app.state.sparse_model_cache = {}
+    app.state.rerank_model_cache = {}
 
 
 def save_model_cache() -> None:
@@ -90,5 +95,87 @@ def get_model(
     return model
 
 
+def splade_pool(logits: torch.Tensor, attention_mask: torch.Tensor) -> torch.Tensor:
+    """max pools log(1 + relu(logits)) over the tokens of each input,
+    giving one weight per term of the vocabulary"""
+    weights = torch.log1p(torch.relu(logits)) * attention_mask.unsqueeze(-1)
+    return torch.max(weights, dim=1).values
+
+
+class SparseModel:
+    """a masked language model that encodes text into SPLADE term weights"""
+
+    def __init__(self, model_name: str, api_key: str | None = None):
+        self.tokenizer = AutoTokenizer.from_pretrained(model_name, token=api_key)
+        self.model = AutoModelForMaskedLM.from_pretrained(model_name, token=api_key)
+        self.model.eval()
+
+    @property
+    def vocab_size(self) -> int:
+        return self.model.config.vocab_size
+
+    def encode(self, sentences: list[str]) -> list[dict[int, float]]:
+        tokens = self.tokenizer(
+            sentences, padding=True, truncation=True, return_tensors="pt"
+        )
+        with torch.no_grad():
+            logits = self.model(**tokens).logits
+        weights = splade_pool(logits, tokens["attention_mask"])
+        embeddings = []
+        for row in weights:
+            indices = torch.nonzero(row).squeeze(-1)
+            embeddings.append(
+                dict(zip(indices.tolist(), row[indices].tolist(), strict=True))
+            )
+        return embeddings
+
+
+def get_sparse_model(
+    model_name: str, model_cache: dict[str, SparseModel], api_key: str = None
+) -> SparseModel:
+    model = model_cache.get(model_name)
+    if model is None:
+        if not MULTI_MODEL:
+            raise HTTPException(
+                status_code=400,
+                detail="Must enable multi-model via MULTI_MODEL env var",
+            )
+        logging.debug(f"Sparse model: {model_name} not in cache.")
+        try:
+            model = SparseModel(model_name, api_key=api_key)
+            model_cache[model_name] = model
+        except Exception:
+            logging.exception("Failed to load sparse model %s", model_name)
+            raise
+    ML_MODEL_COUNT.labels(model_name=model_name).inc()
+    return model
+
+
+def get_rerank_model(
+    model_name: str, model_cache: dict[str, CrossEncoder], api_key: str = None
+) -> CrossEncoder:
+    model = model_cache.get(model_name)
+    if model is None:
+        if not MULTI_MODEL:
+            raise HTTPException(
+                status_code=400,
+                detail="Must enable multi-model via MULTI_MODEL env var",
+            )
+        logging.debug(f"Rerank model: {model_name} not in cache.")
+        try:
+            model = CrossEncoder(model_name, token=api_key)
+            model_cache[model_name] = model
+        except Exception:
+            logging.exception("Failed to load rerank model %s", model_name)
+            raise
+    ML_MODEL_COUNT.labels(model_name=model_name).inc()
+    return model
+
+
+def rank_by_score(scores: list[float]) -> list[tuple[int, float]]:
+    """the indices of the scored documents, from the most to the least relevant"""
+    return sorted(enumerate(scores), key=lambda pair: pair[1], reverse=True)
+
+
 if __name__ == "__main__":
     save_model_cache()
@@ -0,0 +1,52 @@
+import logging
+
+from app.models import get_rerank_model, parse_header, rank_by_score
+from fastapi import APIRouter, Header, HTTPException, Request
+from pydantic import BaseModel
+
+router = APIRouter(tags=["rerank"])
+
+
+class RerankRequest(BaseModel):
+    query: str
+    documents: list[str]
+    model: str = "cross-encoder/ms-marco-MiniLM-L-6-v2"
+
+
+class RerankResult(BaseModel):
+    index: int
+    relevance_score: float
+
+
+class RerankResponse(BaseModel):
+    results: list[RerankResult]
+    model: str
+
+
+@router.post("/v1/rerank", response_model=RerankResponse)
+def rerank(
+    request: Request, payload: RerankRequest, authorization: str = Header(None)
+) -> RerankResponse:
+    logging.info({"rerank-len": len(payload.documents)})
+    api_key = parse_header(authorization)
+    try:
+        model = get_rerank_model(
+            model_name=payload.model,
+            model_cache=request.app.state.rerank_model_cache,
+            api_key=api_key,
+        )
+    except Exception as e:
+        raise HTTPException(
+            status_code=400,
+            detail=f"Unable to load {payload.model} -- {e}",
+        )
+
+    scores: list[float] = []
+    if payload.documents:
+        pairs = [(payload.query, document) for document in payload.documents]
+        scores = model.predict(pairs).tolist()
+    results = [
+        RerankResult(index=index, relevance_score=score)
+        for index, score in rank_by_score(scores)
+    ]
+    return RerankResponse(results=results, model=payload.model)
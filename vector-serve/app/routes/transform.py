@@ -2,7 +2,7 @@ import logging
 import os
 from typing import TYPE_CHECKING, Any, List
 
-from app.models import model_org_name, get_model, parse_header
+from app.models import model_org_name, get_model, get_sparse_model, parse_header
 from fastapi import APIRouter, Header, HTTPException, Request
 from pydantic import BaseModel, conlist
 
@@ -77,6 +77,57 @@ def batch_transform(
     )
 
 
+class SparseBatch(BaseModel):
+    input: Vector
+    model: str = "naver/splade-cocondenser-ensembledistil"
+
+
+class SparseEmbedding(BaseModel):
+    indices: list[int]
+    values: list[float]
+    index: int
+
+
+class SparseResponseModel(BaseModel):
+    data: list[SparseEmbedding]
+    model: str
+    vocab_size: int
+
+
+@router.post("/v1/sparse_embeddings", response_model=SparseResponseModel)
+def batch_sparse_transform(
+    request: Request, payload: SparseBatch, authorization: str = Header(None)
+) -> SparseResponseModel:
+    logging.info({"sparse-batch-predict-len": len(payload.input)})
+    api_key = parse_header(authorization)
+    try:
+        model = get_sparse_model(
+            model_name=payload.model,
+            model_cache=request.app.state.sparse_model_cache,
+            api_key=api_key,
+        )
+    except Exception as e:
+        raise HTTPException(
+            status_code=400,
+            detail=f"Unable to load {payload.model} -- {e}",
+        )
+
+    responses: list[dict[int, float]] = []
+    for batch in chunk_list(payload.input, BATCH_SIZE):
+        responses.extend(model.encode(batch))
+    embeds = [
+        SparseEmbedding(
+            indices=list(weights.keys()), values=list(weights.values()), index=i
+        )
+        for i, weights in enumerate(responses)
+    ]
+    return SparseResponseModel(
+        data=embeds,
+        model=payload.model,
+        vocab_size=model.vocab_size,
+    )
+
+
 def chunk_list(lst: List[Any], chunk_size: int) -> List[List[Any]]:
     """Split a list into smaller lists of equal length, except the last one."""
     chunks = []
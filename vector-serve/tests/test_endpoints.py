@@ -1,3 +1,8 @@
+import torch
+
+from app.models import rank_by_score, splade_pool
+
+
 def test_ready_endpoint(test_client):
     response = test_client.get("/ready")
     assert response.status_code == 200
@@ -21,3 +26,19 @@ def test_metrics_endpoint(test_client):
     response = test_client.get("/metrics")
     assert response.status_code == 200
     assert "all-MiniLM-L6-v2" in response.text
+
+
+def test_splade_pool():
+    logits = torch.tensor(
+        [[[1.0, -1.0, 0.0], [3.0, 2.0, -2.0], [9.0, 9.0, 9.0]]],
+    )
+    # the last token is padding and must not contribute
+    attention_mask = torch.tensor([[1, 1, 0]])
+    weights = splade_pool(logits, attention_mask)
+    expected = torch.log1p(torch.tensor([[3.0, 2.0, 0.0]]))
+    assert torch.allclose(weights, expected)
+
+
+def test_rank_by_score():
+    assert rank_by_score([-3.2, 8.5, 0.1]) == [(1, 8.5), (2, 0.1), (0, -3.2)]
+    assert rank_by_score([]) == []